@@ -0,0 +1,203 @@
+use super::elf::{R_X86_64_32, R_X86_64_64};
+
+// https://dwarfstd.org/doc/DWARF4.pdf
+const VERSION: u16 = 4;
+
+// the line program only uses standard opcodes, the special opcode parameters are the ones gnu as uses
+const LINE_BASE: i8 = -5;
+const LINE_RANGE: u8 = 14;
+const OPCODE_BASE: u8 = 13;
+
+const DW_LNS_COPY: u8 = 1;
+const DW_LNS_ADVANCE_PC: u8 = 2;
+const DW_LNS_ADVANCE_LINE: u8 = 3;
+const DW_LNE_END_SEQUENCE: u8 = 1;
+const DW_LNE_SET_ADDRESS: u8 = 2;
+
+const DW_LANG_MIPS_ASSEMBLER: u16 = 0x8001;
+
+// the name and contents of every debug section
+pub type Sections = Vec<(String, Vec<u8>)>;
+
+// a source line and the address of the first instruction on it
+pub struct Row {
+    pub address: u64,
+    pub line: usize,
+}
+
+// a contiguous run of code, every section of code is a sequence of its own
+pub struct Sequence {
+    pub section: usize,
+    pub start: u64,
+    pub end: u64,
+    pub rows: Vec<Row>,
+}
+
+// the code of a sequence or the start of another debug section
+pub enum Target {
+    Code(usize),
+    Debug(&'static str),
+}
+
+// addresses and offsets into other sections, objects relocate them and the field holds the addend
+pub struct Relocation {
+    pub section: &'static str,
+    pub at: u64,
+    pub target: Target,
+    pub reloc: u32,
+}
+
+fn uleb128(bytes: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+
+        value >>= 7;
+
+        if value == 0 {
+            bytes.push(byte);
+            break;
+        }
+
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn sleb128(bytes: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+
+        value >>= 7;
+
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            bytes.push(byte);
+            break;
+        }
+
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn string(bytes: &mut Vec<u8>, string: &str) {
+    bytes.extend(string.as_bytes());
+    bytes.push(0);
+}
+
+// [unit_length version header_length minimum_instruction_length maximum_operations_per_instruction default_is_stmt line_base line_range opcode_base ..]
+fn debug_line(file: &str, sequences: &[Sequence], relocations: &mut Vec<Relocation>) -> Vec<u8> {
+    let mut header: Vec<u8> = vec![1, 1, 1, LINE_BASE as u8, LINE_RANGE, OPCODE_BASE];
+
+    // operand counts of the standard opcodes, no include directories and the source file in the compilation directory
+    header.extend([0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1]);
+    header.push(0);
+    string(&mut header, file);
+    header.extend([0, 0, 0, 0]);
+
+    let mut program: Vec<u8> = Vec::new();
+    let mut addresses: Vec<(usize, usize)> = Vec::new();
+
+    for sequence in sequences {
+        let mut address = sequence.start;
+        let mut line = 1;
+
+        // DW_LNE_set_address
+        program.extend([0, 9, DW_LNE_SET_ADDRESS]);
+        addresses.push((program.len(), sequence.section));
+        program.extend(sequence.start.to_le_bytes());
+
+        for row in sequence.rows.iter().filter(|row| row.address < sequence.end) {
+            program.push(DW_LNS_ADVANCE_PC);
+            uleb128(&mut program, row.address - address);
+            program.push(DW_LNS_ADVANCE_LINE);
+            sleb128(&mut program, row.line as i64 - line as i64);
+            program.push(DW_LNS_COPY);
+
+            address = row.address;
+            line = row.line;
+        }
+
+        // the end of a sequence is the first address after it
+        program.push(DW_LNS_ADVANCE_PC);
+        uleb128(&mut program, sequence.end - address);
+        program.extend([0, 1, DW_LNE_END_SEQUENCE]);
+    }
+
+    let mut bytes: Vec<u8> = Vec::new();
+
+    // the program goes after [unit_length version header_length] and the header
+    for (at, section) in addresses {
+        relocations.push(Relocation { section: ".debug_line", at: (4 + 2 + 4 + header.len() + at) as u64, target: Target::Code(section), reloc: R_X86_64_64 });
+    }
+
+    bytes.extend(((2 + 4 + header.len() + program.len()) as u32).to_le_bytes());
+    bytes.extend(VERSION.to_le_bytes());
+    bytes.extend((header.len() as u32).to_le_bytes());
+    bytes.extend(header);
+    bytes.extend(program);
+    bytes
+}
+
+// a single compile unit without children, [code children (attribute form)..]
+fn debug_abbrev() -> Vec<u8> {
+    vec![
+        // DW_TAG_compile_unit DW_CHILDREN_no
+        1, 0x11, 0,
+        // DW_AT_producer DW_FORM_string, DW_AT_language DW_FORM_data2, DW_AT_name DW_FORM_string, DW_AT_comp_dir DW_FORM_string
+        0x25, 0x08, 0x13, 0x05, 0x03, 0x08, 0x1b, 0x08,
+        // DW_AT_stmt_list DW_FORM_sec_offset, DW_AT_low_pc DW_FORM_addr, DW_AT_ranges DW_FORM_sec_offset
+        0x10, 0x17, 0x11, 0x01, 0x55, 0x17,
+        0, 0, 0,
+    ]
+}
+
+// [unit_length version debug_abbrev_offset address_size] and the compile unit covering all code, its base address is zero so the ranges are addresses
+fn debug_info(file: &str, directory: &str, relocations: &mut Vec<Relocation>) -> Vec<u8> {
+    let mut unit: Vec<u8> = Vec::new();
+
+    unit.extend(VERSION.to_le_bytes());
+    relocations.push(Relocation { section: ".debug_info", at: 4 + unit.len() as u64, target: Target::Debug(".debug_abbrev"), reloc: R_X86_64_32 });
+    unit.extend(0u32.to_le_bytes());
+    unit.push(8);
+
+    unit.push(1);
+    string(&mut unit, concat!("fasm-rs ", env!("CARGO_PKG_VERSION")));
+    unit.extend(DW_LANG_MIPS_ASSEMBLER.to_le_bytes());
+    string(&mut unit, file);
+    string(&mut unit, directory);
+    relocations.push(Relocation { section: ".debug_info", at: 4 + unit.len() as u64, target: Target::Debug(".debug_line"), reloc: R_X86_64_32 });
+    unit.extend(0u32.to_le_bytes());
+    unit.extend(0u64.to_le_bytes());
+    relocations.push(Relocation { section: ".debug_info", at: 4 + unit.len() as u64, target: Target::Debug(".debug_ranges"), reloc: R_X86_64_32 });
+    unit.extend(0u32.to_le_bytes());
+
+    [(unit.len() as u32).to_le_bytes().to_vec(), unit].concat()
+}
+
+// a start and end address for every sequence, the list ends with two zeros
+fn debug_ranges(sequences: &[Sequence], relocations: &mut Vec<Relocation>) -> Vec<u8> {
+    let mut bytes: Vec<u8> = Vec::new();
+
+    for sequence in sequences {
+        for address in [sequence.start, sequence.end] {
+            relocations.push(Relocation { section: ".debug_ranges", at: bytes.len() as u64, target: Target::Code(sequence.section), reloc: R_X86_64_64 });
+            bytes.extend(address.to_le_bytes());
+        }
+    }
+
+    bytes.extend([0; 16]);
+    bytes
+}
+
+// `.debug_line` maps every instruction back to its line in `file`, relative to `directory`
+pub fn build(file: &str, directory: &str, sequences: &[Sequence]) -> (Sections, Vec<Relocation>) {
+    let mut relocations: Vec<Relocation> = Vec::new();
+
+    let sections = vec![
+        (String::from(".debug_abbrev"), debug_abbrev()),
+        (String::from(".debug_info"), debug_info(file, directory, &mut relocations)),
+        (String::from(".debug_line"), debug_line(file, sequences, &mut relocations)),
+        (String::from(".debug_ranges"), debug_ranges(sequences, &mut relocations)),
+    ];
+
+    (sections, relocations)
+}
+
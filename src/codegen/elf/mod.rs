@@ -0,0 +1,856 @@
+use super::object::{Object, Symbol, Target};
+use crate::error::Error;
+
+use std::io::Write;
+
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
+
+// relocation types @ x86-64 psabi
+pub const R_X86_64_64: u32 = 1;
+pub const R_X86_64_PC32: u32 = 2;
+pub const R_X86_64_PLT32: u32 = 4;
+pub const R_X86_64_GLOB_DAT: u32 = 6;
+pub const R_X86_64_RELATIVE: u32 = 8;
+pub const R_X86_64_32: u32 = 10;
+pub const R_X86_64_32S: u32 = 11;
+pub const R_X86_64_16: u32 = 12;
+pub const R_X86_64_PC16: u32 = 13;
+pub const R_X86_64_8: u32 = 14;
+
+const PAGE: u64 = 0x1000;
+
+const EHDR_SIZE: u64 = 64;
+const PHDR_SIZE: u64 = 56;
+const SHDR_SIZE: u64 = 64;
+
+const SYM_SIZE: u64 = 24;
+const RELA_SIZE: u64 = 24;
+const DYN_SIZE: u64 = 16;
+
+// a loadable segment, anything between the end of `data` and `size` is zero filled by the loader
+pub struct Segment {
+    pub name: String,
+    pub addr: u64,
+    pub data: Vec<u8>,
+    pub size: u64,
+    pub flags: u32,
+}
+
+pub struct Export {
+    pub name: String,
+    pub addr: u64,
+    pub size: u64,
+    pub function: bool,
+}
+
+// `symbol` indexes the imports, relocations without one are relative to the load address
+pub struct Relocation {
+    pub at: u64,
+    pub symbol: Option<usize>,
+    pub addend: i64,
+}
+
+// everything the dynamic linker needs goes into one writeable segment at `addr`, starting with a global offset table slot for every import
+pub struct Dynamic {
+    pub addr: u64,
+    pub pie: bool,
+    pub interpreter: Option<String>,
+    pub needed: Vec<String>,
+    pub imports: Vec<String>,
+    pub exports: Vec<Export>,
+    pub relocations: Vec<Relocation>,
+}
+
+// a table in the dynamic segment, it gets a section header of its own
+struct Table {
+    name: &'static str,
+    kind: u32,
+    flags: u64,
+    offset: u64,
+    size: u64,
+    link: Option<&'static str>,
+    info: u32,
+    entsize: u64,
+}
+
+struct Section {
+    name: u32,
+    kind: u32,
+    flags: u64,
+    addr: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    align: u64,
+    entsize: u64,
+}
+
+pub struct Executable {
+    pub entry: u64,
+    pub segments: Vec<Segment>,
+    pub dynamic: Option<Dynamic>,
+    pub debug: Vec<(String, Vec<u8>)>,
+}
+
+fn align(offset: u64, align: u64) -> u64 {
+    offset.div_ceil(align) * align
+}
+
+// https://refspecs.linuxfoundation.org/elf/gabi4+/ch5.dynamic.html#hash
+fn hash(name: &str) -> u32 {
+    let mut hash: u32 = 0;
+
+    for byte in name.bytes() {
+        hash = (hash << 4).wrapping_add(byte as u32);
+
+        let high = hash & 0xf000_0000;
+
+        hash ^= high >> 24;
+        hash &= !high;
+    }
+
+    hash
+}
+
+impl Dynamic {
+    fn build(&self, segments: &[Segment]) -> (Segment, Vec<Table>) {
+        let mut dynstr: Vec<u8> = vec![0];
+        let mut string = |name: &str| {
+            dynstr.extend(name.as_bytes());
+            dynstr.push(0);
+
+            (dynstr.len() - name.len() - 1) as u32
+        };
+
+        let imports: Vec<u32> = self.imports.iter().map(|name| string(name)).collect();
+        let exports: Vec<u32> = self.exports.iter().map(|export| string(&export.name)).collect();
+        let needed: Vec<u32> = self.needed.iter().map(|name| string(name)).collect();
+
+        // relocations in read only segments need the dynamic linker to make them writeable first
+        let textrel = self.relocations.iter().any(|relocation| {
+            segments.iter().any(|segment| (segment.addr..segment.addr + segment.size).contains(&relocation.at) && segment.flags & PF_W == 0)
+        });
+
+        let mut dynamic: Vec<(u64, u64)> = needed.iter().map(|name| (1, *name as u64)).collect();
+        let symbols = 1 + self.imports.len() + self.exports.len();
+
+        let got = 0;
+        let table = align(got + 8 * self.imports.len() as u64, 8);
+        let dynsym = table + DYN_SIZE * (needed.len() as u64 + 11 + textrel as u64);
+        let strtab = dynsym + SYM_SIZE * symbols as u64;
+        let hashtab = align(strtab + dynstr.len() as u64, 8);
+        let rela = hashtab + 4 * (2 + 2 * symbols as u64);
+        let relasz = RELA_SIZE * (self.imports.len() + self.relocations.len()) as u64;
+        let interp = rela + relasz;
+
+        // DT_HASH, DT_STRTAB, DT_SYMTAB, DT_STRSZ, DT_SYMENT, DT_RELA, DT_RELASZ, DT_RELAENT
+        dynamic.extend([(4, self.addr + hashtab), (5, self.addr + strtab), (6, self.addr + dynsym), (10, dynstr.len() as u64), (11, SYM_SIZE)]);
+        dynamic.extend([(7, self.addr + rela), (8, relasz), (9, RELA_SIZE)]);
+
+        if textrel {
+            // DT_TEXTREL
+            dynamic.push((22, 0));
+        }
+
+        // DT_FLAGS with DF_BIND_NOW and DF_TEXTREL, DT_FLAGS_1 with DF_1_NOW and DF_1_PIE, DT_NULL
+        dynamic.push((30, 8 | if textrel { 4 } else { 0 }));
+        dynamic.push((0x6fff_fffb, 1 | if self.pie { 0x0800_0000 } else { 0 }));
+        dynamic.push((0, 0));
+
+        let mut data: Vec<u8> = vec![0; table as usize];
+
+        for (tag, value) in &dynamic {
+            data.extend(tag.to_le_bytes());
+            data.extend(value.to_le_bytes());
+        }
+
+        // Elf64_Sym [st_name st_info st_other st_shndx st_value st_size], the first symbol is always null
+        data.extend([0; SYM_SIZE as usize]);
+
+        for name in &imports {
+            // STB_GLOBAL STT_NOTYPE, SHN_UNDEF
+            data.extend(name.to_le_bytes());
+            data.extend([0x10, 0, 0, 0]);
+            data.extend([0; 16]);
+        }
+
+        for (export, name) in self.exports.iter().zip(&exports) {
+            // the section headers of the segments come right after the null section
+            let section = segments.iter().position(|segment| (segment.addr..segment.addr + segment.size.max(1)).contains(&export.addr)).map_or(0xfff1, |index| index as u16 + 1);
+
+            // STB_GLOBAL STT_FUNC or STT_OBJECT
+            data.extend(name.to_le_bytes());
+            data.extend([0x10 | if export.function { 2 } else { 1 }, 0]);
+            data.extend(section.to_le_bytes());
+            data.extend(export.addr.to_le_bytes());
+            data.extend(export.size.to_le_bytes());
+        }
+
+        data.extend(&dynstr);
+        data.resize(hashtab as usize, 0);
+
+        let names: Vec<&str> = self.imports.iter().map(String::as_str).chain(self.exports.iter().map(|export| export.name.as_str())).collect();
+        let mut buckets = vec![0u32; symbols];
+        let mut chains = vec![0u32; symbols];
+
+        for (index, name) in names.iter().enumerate().map(|(index, name)| (index + 1, name)) {
+            let bucket = hash(name) as usize % symbols;
+
+            chains[index] = buckets[bucket];
+            buckets[bucket] = index as u32;
+        }
+
+        data.extend((symbols as u32).to_le_bytes());
+        data.extend((symbols as u32).to_le_bytes());
+        data.extend(buckets.iter().chain(&chains).flat_map(|entry| entry.to_le_bytes()));
+
+        // Elf64_Rela [r_offset r_info r_addend], every import is bound eagerly through its slot
+        for index in 0..self.imports.len() as u64 {
+            data.extend((self.addr + got + 8 * index).to_le_bytes());
+            data.extend(((index + 1) << 32 | R_X86_64_GLOB_DAT as u64).to_le_bytes());
+            data.extend(0i64.to_le_bytes());
+        }
+
+        for relocation in &self.relocations {
+            let info = match relocation.symbol {
+                Some(symbol) => (symbol as u64 + 1) << 32 | R_X86_64_64 as u64,
+                None => R_X86_64_RELATIVE as u64,
+            };
+
+            data.extend(relocation.at.to_le_bytes());
+            data.extend(info.to_le_bytes());
+            data.extend(relocation.addend.to_le_bytes());
+        }
+
+        if let Some(interpreter) = &self.interpreter {
+            data.extend(interpreter.as_bytes());
+            data.push(0);
+        }
+
+        let mut tables = vec![
+            // SHT_PROGBITS, SHT_DYNAMIC, SHT_DYNSYM, SHT_STRTAB, SHT_HASH, SHT_RELA
+            Table { name: ".got", kind: 1, flags: 3, offset: got, size: table - got, link: None, info: 0, entsize: 8 },
+            Table { name: ".dynamic", kind: 6, flags: 3, offset: table, size: dynsym - table, link: Some(".dynstr"), info: 0, entsize: DYN_SIZE },
+            Table { name: ".dynsym", kind: 11, flags: 2, offset: dynsym, size: strtab - dynsym, link: Some(".dynstr"), info: 1, entsize: SYM_SIZE },
+            Table { name: ".dynstr", kind: 3, flags: 2, offset: strtab, size: dynstr.len() as u64, link: None, info: 0, entsize: 0 },
+            Table { name: ".hash", kind: 5, flags: 2, offset: hashtab, size: rela - hashtab, link: Some(".dynsym"), info: 0, entsize: 4 },
+            Table { name: ".rela.dyn", kind: 4, flags: 2, offset: rela, size: relasz, link: Some(".dynsym"), info: 0, entsize: RELA_SIZE },
+        ];
+
+        if self.interpreter.is_some() {
+            tables.push(Table { name: ".interp", kind: 1, flags: 2, offset: interp, size: data.len() as u64 - interp, link: None, info: 0, entsize: 0 });
+        }
+
+        let segment = Segment {
+            name: String::from(".dynamic"),
+            addr: self.addr,
+            size: data.len() as u64,
+            data,
+            flags: PF_R | PF_W,
+        };
+
+        (segment, tables)
+    }
+}
+
+impl Executable {
+    pub fn new(entry: u64) -> Executable {
+        Executable {
+            entry,
+            segments: Vec::new(),
+            dynamic: None,
+            debug: Vec::new(),
+        }
+    }
+
+    fn write_section(bytes: &mut Vec<u8>, section: &Section) {
+        bytes.extend(section.name.to_le_bytes());
+        bytes.extend(section.kind.to_le_bytes());
+        bytes.extend(section.flags.to_le_bytes());
+        bytes.extend(section.addr.to_le_bytes());
+        bytes.extend(section.offset.to_le_bytes());
+        bytes.extend(section.size.to_le_bytes());
+        bytes.extend(section.link.to_le_bytes());
+        bytes.extend(section.info.to_le_bytes());
+        bytes.extend(section.align.to_le_bytes());
+        bytes.extend(section.entsize.to_le_bytes());
+    }
+
+    // [p_type p_flags p_offset p_vaddr p_paddr p_filesz p_memsz p_align]
+    fn write_program_header(bytes: &mut Vec<u8>, kind: u32, flags: u32, offset: u64, addr: u64, size: (u64, u64), align: u64) {
+        bytes.extend(kind.to_le_bytes());
+        bytes.extend(flags.to_le_bytes());
+        bytes.extend(offset.to_le_bytes());
+        bytes.extend(addr.to_le_bytes());
+        bytes.extend(addr.to_le_bytes());
+        bytes.extend(size.0.to_le_bytes());
+        bytes.extend(size.1.to_le_bytes());
+        bytes.extend(align.to_le_bytes());
+    }
+
+    // https://refspecs.linuxfoundation.org/elf/gabi4+/ch4.eheader.html
+    pub fn write(&self, fd: &mut impl Write) -> Result<(), Error> {
+        let built = self.dynamic.as_ref().map(|dynamic| dynamic.build(&self.segments));
+        let interp = built.iter().flat_map(|(_, tables)| tables).find(|table| table.name == ".interp");
+
+        // dynamic images map their own headers for the dynamic linker, [PT_PHDR PT_INTERP PT_LOAD .. PT_DYNAMIC]
+        let loads: Vec<&Segment> = self.segments.iter().chain(built.iter().map(|(segment, _)| segment)).collect();
+        let phnum = loads.len() as u64 + if built.is_some() { 3 + interp.is_some() as u64 } else { 0 };
+
+        let headers = EHDR_SIZE + PHDR_SIZE * phnum;
+        let mut offset = headers;
+        let mut offsets: Vec<u64> = Vec::new();
+
+        // the file offset of a segment has to match its address modulo the page size
+        for segment in &loads {
+            offsets.push(align(offset, PAGE) + segment.addr % PAGE);
+            offset = offsets[offsets.len() - 1] + segment.data.len() as u64;
+        }
+
+        let mut shstrtab: Vec<u8> = vec![0];
+        let mut sections: Vec<Section> = Vec::new();
+
+        for (segment, offset) in self.segments.iter().zip(&offsets) {
+            sections.push(Section {
+                name: shstrtab.len() as u32,
+                // SHT_PROGBITS, SHT_NOBITS
+                kind: if segment.data.is_empty() { 8 } else { 1 },
+                // SHF_ALLOC | SHF_WRITE | SHF_EXECINSTR
+                flags: 2 | if segment.flags & PF_W != 0 { 1 } else { 0 } | if segment.flags & PF_X != 0 { 4 } else { 0 },
+                addr: segment.addr,
+                offset: *offset,
+                size: segment.size.max(segment.data.len() as u64),
+                link: 0,
+                info: 0,
+                align: 1,
+                entsize: 0,
+            });
+
+            shstrtab.extend(segment.name.as_bytes());
+            shstrtab.push(0);
+        }
+
+        let mut dynamic = None;
+
+        if let Some((segment, tables)) = &built {
+            let start = offsets[offsets.len() - 1];
+
+            for table in tables {
+                // the first section header is the null section
+                let link = table.link.and_then(|link| tables.iter().position(|table| table.name == link)).map_or(0, |index| self.segments.len() + 1 + index);
+
+                sections.push(Section {
+                    name: shstrtab.len() as u32,
+                    kind: table.kind,
+                    flags: table.flags,
+                    addr: segment.addr + table.offset,
+                    offset: start + table.offset,
+                    size: table.size,
+                    link: link as u32,
+                    info: table.info,
+                    align: 8,
+                    entsize: table.entsize,
+                });
+
+                shstrtab.extend(table.name.as_bytes());
+                shstrtab.push(0);
+
+                if table.name == ".dynamic" {
+                    dynamic = Some((start + table.offset, segment.addr + table.offset, table.size));
+                }
+            }
+        }
+
+        // debug sections are not loaded and go right after the segments
+        for (name, data) in &self.debug {
+            sections.push(Section {
+                name: shstrtab.len() as u32,
+                // SHT_PROGBITS
+                kind: 1,
+                flags: 0,
+                addr: 0,
+                offset,
+                size: data.len() as u64,
+                link: 0,
+                info: 0,
+                align: 1,
+                entsize: 0,
+            });
+
+            shstrtab.extend(name.as_bytes());
+            shstrtab.push(0);
+            offset += data.len() as u64;
+        }
+
+        sections.push(Section {
+            name: shstrtab.len() as u32,
+            // SHT_STRTAB
+            kind: 3,
+            flags: 0,
+            addr: 0,
+            offset,
+            size: 0,
+            link: 0,
+            info: 0,
+            align: 1,
+            entsize: 0,
+        });
+
+        shstrtab.extend(b".shstrtab\0");
+
+        let shstrndx = sections.len();
+        let shoff = align(offset + shstrtab.len() as u64, 8);
+
+        sections[shstrndx - 1].size = shstrtab.len() as u64;
+
+        let mut bytes: Vec<u8> = Vec::new();
+
+        // e_ident [7F 'E' 'L' 'F' ELFCLASS64 ELFDATA2LSB EV_CURRENT ELFOSABI_SYSV]
+        bytes.extend([0x7f, b'E', b'L', b'F', 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        // e_type ET_EXEC or ET_DYN, e_machine EM_X86_64, e_version
+        bytes.extend(if built.is_some() { 3u16 } else { 2u16 }.to_le_bytes());
+        bytes.extend(0x3eu16.to_le_bytes());
+        bytes.extend(1u32.to_le_bytes());
+        bytes.extend(self.entry.to_le_bytes());
+        bytes.extend(EHDR_SIZE.to_le_bytes());
+        bytes.extend(shoff.to_le_bytes());
+        // e_flags
+        bytes.extend(0u32.to_le_bytes());
+        bytes.extend((EHDR_SIZE as u16).to_le_bytes());
+        bytes.extend((PHDR_SIZE as u16).to_le_bytes());
+        bytes.extend((phnum as u16).to_le_bytes());
+        bytes.extend((SHDR_SIZE as u16).to_le_bytes());
+        bytes.extend((sections.len() as u16 + 1).to_le_bytes());
+        bytes.extend((shstrndx as u16).to_le_bytes());
+
+        if let Some((segment, _)) = &built {
+            // PT_PHDR, the headers are loaded at address zero
+            Self::write_program_header(&mut bytes, 6, PF_R, EHDR_SIZE, EHDR_SIZE, (headers - EHDR_SIZE, headers - EHDR_SIZE), 8);
+
+            if let Some(interp) = interp {
+                // PT_INTERP
+                let start = offsets[offsets.len() - 1];
+
+                Self::write_program_header(&mut bytes, 3, PF_R, start + interp.offset, segment.addr + interp.offset, (interp.size, interp.size), 1);
+            }
+
+            // PT_LOAD
+            Self::write_program_header(&mut bytes, 1, PF_R, 0, 0, (headers, headers), PAGE);
+        }
+
+        for (segment, offset) in loads.iter().zip(&offsets) {
+            // PT_LOAD
+            Self::write_program_header(&mut bytes, 1, segment.flags, *offset, segment.addr, (segment.data.len() as u64, segment.size.max(segment.data.len() as u64)), PAGE);
+        }
+
+        if let Some((offset, addr, size)) = dynamic {
+            // PT_DYNAMIC
+            Self::write_program_header(&mut bytes, 2, PF_R | PF_W, offset, addr, (size, size), 8);
+        }
+
+        for (segment, offset) in loads.iter().zip(&offsets) {
+            bytes.resize(*offset as usize, 0);
+            bytes.extend(&segment.data);
+        }
+
+        for (_, data) in &self.debug {
+            bytes.extend(data);
+        }
+
+        bytes.extend(&shstrtab);
+        bytes.resize(shoff as usize, 0);
+
+        // the first section header is always the null section
+        bytes.extend([0; SHDR_SIZE as usize]);
+
+        for section in &sections {
+            Self::write_section(&mut bytes, section);
+        }
+
+        fd.write_all(&bytes)?;
+
+        Ok(())
+    }
+}
+
+// the size of the field a relocation patches
+fn field(reloc: u32) -> usize {
+    match reloc {
+        R_X86_64_64 => 8,
+        R_X86_64_16 | R_X86_64_PC16 => 2,
+        R_X86_64_8 => 1,
+        _ => 4,
+    }
+}
+
+fn string(strtab: &mut Vec<u8>, name: &str) -> u32 {
+    strtab.extend(name.as_bytes());
+    strtab.push(0);
+
+    (strtab.len() - name.len() - 1) as u32
+}
+
+impl Object {
+    // https://refspecs.linuxfoundation.org/elf/gabi4+/ch4.symtab.html
+    fn write_symbols(&self, strtab: &mut Vec<u8>) -> (Vec<u8>, Vec<&Symbol>, u32) {
+        // Elf64_Sym [st_name st_info st_other st_shndx st_value st_size], the null symbol and a STB_LOCAL STT_SECTION symbol for every section come first
+        let mut bytes: Vec<u8> = vec![0; SYM_SIZE as usize];
+
+        for index in 0..self.sections.len() {
+            bytes.extend([0, 0, 0, 0, 3, 0]);
+            bytes.extend((index as u16 + 1).to_le_bytes());
+            bytes.extend([0; 16]);
+        }
+
+        // local symbols go before global ones, external symbols are undefined globals
+        let (mut symbols, globals): (Vec<&Symbol>, Vec<&Symbol>) = self.symbols.iter().partition(|symbol| !symbol.public && symbol.section.is_some());
+        let first = 1 + (self.sections.len() + symbols.len()) as u32;
+
+        symbols.extend(globals);
+
+        for symbol in &symbols {
+            // STB_LOCAL or STB_GLOBAL with STT_FUNC or STT_OBJECT, STT_NOTYPE for undefined ones
+            let kind = match symbol.section {
+                Some(_) if symbol.function => 2,
+                Some(_) => 1,
+                None => 0,
+            };
+
+            let bind = if symbol.public || symbol.section.is_none() { 0x10 } else { 0 };
+
+            bytes.extend(string(strtab, &symbol.name).to_le_bytes());
+            bytes.extend([bind | kind, 0]);
+            bytes.extend(symbol.section.map_or(0, |section| section as u16 + 1).to_le_bytes());
+            bytes.extend(symbol.value.to_le_bytes());
+            bytes.extend(symbol.size.to_le_bytes());
+        }
+
+        (bytes, symbols, first)
+    }
+
+    // https://refspecs.linuxfoundation.org/elf/gabi4+/ch4.eheader.html
+    pub fn write_elf(&self, fd: &mut impl Write) -> Result<(), Error> {
+        let mut strtab: Vec<u8> = vec![0];
+        let (symtab, symbols, globals) = self.write_symbols(&mut strtab);
+        let locals = 1 + self.sections.len() as u64;
+
+        let mut data: Vec<Vec<u8>> = self.sections.iter().map(|section| section.data.clone()).collect();
+        let mut relocations: Vec<Vec<u8>> = vec![Vec::new(); self.sections.len()];
+
+        for relocation in &self.relocations {
+            let symbol = match &relocation.target {
+                Target::Section(section) => *section as u64 + 1,
+                Target::Symbol(name) => symbols.iter()
+                    .position(|symbol| symbol.name == *name)
+                    .map(|index| locals + index as u64)
+                    .ok_or(Error::NoSuchLabel(name.clone()))?,
+            };
+
+            // rela objects keep the addend in the relocation instead of the field, it is sign extended to 64 bits
+            let size = field(relocation.reloc);
+            let bytes = &mut data[relocation.section][relocation.at as usize..relocation.at as usize + size];
+            let mut addend = [0; 8];
+
+            addend[..size].copy_from_slice(bytes);
+            bytes.fill(0);
+
+            let shift = 64 - 8 * size as u32;
+            let addend = (i64::from_le_bytes(addend) << shift) >> shift;
+
+            // Elf64_Rela [r_offset r_info r_addend]
+            let bytes = &mut relocations[relocation.section];
+
+            bytes.extend(relocation.at.to_le_bytes());
+            bytes.extend((symbol << 32 | relocation.reloc as u64).to_le_bytes());
+            bytes.extend(addend.to_le_bytes());
+        }
+
+        let mut shstrtab: Vec<u8> = vec![0];
+        let mut sections: Vec<Section> = Vec::new();
+        let mut contents: Vec<(u64, &[u8])> = Vec::new();
+        let mut offset = EHDR_SIZE;
+
+        for (section, data) in self.sections.iter().zip(&data) {
+            offset = align(offset, section.align.max(1));
+
+            sections.push(Section {
+                name: string(&mut shstrtab, &section.name),
+                // SHT_PROGBITS, SHT_NOBITS for sections that only reserve space
+                kind: if data.is_empty() && section.size > 0 { 8 } else { 1 },
+                // SHF_ALLOC | SHF_WRITE | SHF_EXECINSTR, debug sections are not loaded
+                flags: if section.flags == 0 { 0 } else { 2 | if section.flags & PF_W != 0 { 1 } else { 0 } | if section.flags & PF_X != 0 { 4 } else { 0 } },
+                addr: 0,
+                offset,
+                size: section.size.max(data.len() as u64),
+                link: 0,
+                info: 0,
+                align: section.align.max(1),
+                entsize: 0,
+            });
+
+            contents.push((offset, data));
+            offset += data.len() as u64;
+        }
+
+        // the stack is not executable, SHT_PROGBITS
+        sections.push(Section { name: string(&mut shstrtab, ".note.GNU-stack"), kind: 1, flags: 0, addr: 0, offset, size: 0, link: 0, info: 0, align: 1, entsize: 0 });
+
+        let symtab_index = sections.len() as u32 + 1 + relocations.iter().filter(|relocations| !relocations.is_empty()).count() as u32;
+
+        for (index, relocations) in relocations.iter().enumerate().filter(|(_, relocations)| !relocations.is_empty()) {
+            offset = align(offset, 8);
+
+            // SHT_RELA with SHF_INFO_LINK, `info` is the section the relocations apply to
+            sections.push(Section {
+                name: string(&mut shstrtab, &[".rela", &self.sections[index].name].concat()),
+                kind: 4,
+                flags: 0x40,
+                addr: 0,
+                offset,
+                size: relocations.len() as u64,
+                link: symtab_index,
+                info: index as u32 + 1,
+                align: 8,
+                entsize: RELA_SIZE,
+            });
+
+            contents.push((offset, relocations));
+            offset += relocations.len() as u64;
+        }
+
+        offset = align(offset, 8);
+
+        // SHT_SYMTAB, `info` is the index of the first global symbol
+        sections.push(Section { name: string(&mut shstrtab, ".symtab"), kind: 2, flags: 0, addr: 0, offset, size: symtab.len() as u64, link: symtab_index + 1, info: globals, align: 8, entsize: SYM_SIZE });
+        contents.push((offset, &symtab));
+        offset += symtab.len() as u64;
+
+        // SHT_STRTAB
+        sections.push(Section { name: string(&mut shstrtab, ".strtab"), kind: 3, flags: 0, addr: 0, offset, size: strtab.len() as u64, link: 0, info: 0, align: 1, entsize: 0 });
+        contents.push((offset, &strtab));
+        offset += strtab.len() as u64;
+
+        let name = string(&mut shstrtab, ".shstrtab");
+
+        sections.push(Section { name, kind: 3, flags: 0, addr: 0, offset, size: shstrtab.len() as u64, link: 0, info: 0, align: 1, entsize: 0 });
+        contents.push((offset, &shstrtab));
+        offset += shstrtab.len() as u64;
+
+        let shoff = align(offset, 8);
+        let mut bytes: Vec<u8> = Vec::new();
+
+        // e_ident [7F 'E' 'L' 'F' ELFCLASS64 ELFDATA2LSB EV_CURRENT ELFOSABI_SYSV]
+        bytes.extend([0x7f, b'E', b'L', b'F', 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        // e_type ET_REL, e_machine EM_X86_64, e_version, e_entry, e_phoff
+        bytes.extend(1u16.to_le_bytes());
+        bytes.extend(0x3eu16.to_le_bytes());
+        bytes.extend(1u32.to_le_bytes());
+        bytes.extend([0; 16]);
+        bytes.extend(shoff.to_le_bytes());
+        // e_flags
+        bytes.extend(0u32.to_le_bytes());
+        bytes.extend((EHDR_SIZE as u16).to_le_bytes());
+        bytes.extend([0; 4]);
+        bytes.extend((SHDR_SIZE as u16).to_le_bytes());
+        bytes.extend((sections.len() as u16 + 1).to_le_bytes());
+        bytes.extend((sections.len() as u16).to_le_bytes());
+
+        for (offset, data) in contents {
+            bytes.resize(offset as usize, 0);
+            bytes.extend(data);
+        }
+
+        bytes.resize(shoff as usize, 0);
+
+        // the first section header is always the null section
+        bytes.extend([0; SHDR_SIZE as usize]);
+
+        for section in &sections {
+            Executable::write_section(&mut bytes, section);
+        }
+
+        fd.write_all(&bytes)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::object::{Relocation, Section};
+
+    fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+    }
+
+    fn string_at(bytes: &[u8], offset: usize) -> &str {
+        std::str::from_utf8(bytes[offset..].split(|byte| *byte == 0).next().unwrap()).unwrap()
+    }
+
+    // [name type flags offset size link info] of every section header
+    fn sections(bytes: &[u8]) -> Vec<(String, u32, u64, usize, usize, u32, u32)> {
+        let table = u64_at(bytes, 0x28) as usize;
+        let count = u16_at(bytes, 0x3c) as usize;
+        let names = table + u16_at(bytes, 0x3e) as usize * 64;
+        let names = u64_at(bytes, names + 0x18) as usize;
+
+        (0..count)
+            .map(|index| {
+                let header = table + index * 64;
+
+                (
+                    string_at(bytes, names + u32_at(bytes, header) as usize).to_string(),
+                    u32_at(bytes, header + 4),
+                    u64_at(bytes, header + 8),
+                    u64_at(bytes, header + 0x18) as usize,
+                    u64_at(bytes, header + 0x20) as usize,
+                    u32_at(bytes, header + 0x28),
+                    u32_at(bytes, header + 0x2c),
+                )
+            })
+            .collect()
+    }
+
+    fn object() -> Vec<u8> {
+        let symbol = |name: &str, section, value, size, function, public| Symbol { name: String::from(name), section, value, size, function, public };
+
+        // call puts, lea rdi, [rip + msg] and a local `loop` label between them
+        let object = Object {
+            sections: vec![
+                Section { name: String::from(".text"), data: vec![0xe8, 0xfc, 0xff, 0xff, 0xff, 0x48, 0x8d, 0x3d, 0x08, 0x00, 0x00, 0x00, 0xc3], size: 13, flags: PF_R | PF_X, align: 16 },
+                Section { name: String::from(".data"), data: b"hi\0".to_vec(), size: 3, flags: PF_R | PF_W, align: 1 },
+                Section { name: String::from(".bss"), data: Vec::new(), size: 0x40, flags: PF_R | PF_W, align: 1 },
+            ],
+            symbols: vec![
+                symbol("main", Some(0), 0, 5, true, true),
+                symbol("loop", Some(0), 5, 8, true, false),
+                symbol("msg", Some(1), 0, 3, false, false),
+                symbol("buffer", Some(2), 0, 0x40, false, true),
+                symbol("puts", None, 0, 0, false, false),
+            ],
+            relocations: vec![
+                Relocation { section: 0, at: 1, target: Target::Symbol(String::from("puts")), reloc: R_X86_64_PLT32 },
+                Relocation { section: 0, at: 8, target: Target::Section(1), reloc: R_X86_64_PC32 },
+            ],
+        };
+
+        let mut bytes = Vec::new();
+
+        object.write_elf(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn header() {
+        let bytes = object();
+
+        assert_eq!(&bytes[..4], b"\x7fELF");
+        // ET_REL without an entry or program headers
+        assert_eq!(u16_at(&bytes, 0x10), 1);
+        assert_eq!(u64_at(&bytes, 0x18), 0);
+        assert_eq!(u16_at(&bytes, 0x38), 0);
+    }
+
+    #[test]
+    fn section_table() {
+        let names: Vec<(String, u32, u64)> = sections(&object()).into_iter().map(|(name, kind, flags, ..)| (name, kind, flags)).collect();
+
+        assert_eq!(
+            names,
+            [
+                (String::new(), 0, 0),
+                (String::from(".text"), 1, 6),
+                (String::from(".data"), 1, 3),
+                // reserve only sections take no space in the file
+                (String::from(".bss"), 8, 3),
+                (String::from(".note.GNU-stack"), 1, 0),
+                (String::from(".rela.text"), 4, 0x40),
+                (String::from(".symtab"), 2, 0),
+                (String::from(".strtab"), 3, 0),
+                (String::from(".shstrtab"), 3, 0),
+            ]
+        );
+    }
+
+    // [name info shndx value size]
+    type Sym = (String, u8, u16, u64, u64);
+
+    // every symbol and the index of the first global
+    fn symbols(bytes: &[u8]) -> (Vec<Sym>, u32) {
+        let sections = sections(bytes);
+        let (_, _, _, offset, size, link, info) = sections[6];
+        let strtab = sections[link as usize].3;
+
+        let symbols = (0..size / SYM_SIZE as usize)
+            .map(|index| {
+                let symbol = offset + index * SYM_SIZE as usize;
+
+                (string_at(bytes, strtab + u32_at(bytes, symbol) as usize).to_string(), bytes[symbol + 4], u16_at(bytes, symbol + 6), u64_at(bytes, symbol + 8), u64_at(bytes, symbol + 16))
+            })
+            .collect();
+
+        (symbols, info)
+    }
+
+    #[test]
+    fn symbol_table() {
+        let (symbols, info) = symbols(&object());
+        let symbols: Vec<(&str, u8, u16, u64)> = symbols.iter().map(|(name, kind, section, value, _)| (name.as_str(), *kind, *section, *value)).collect();
+
+        // the null symbol, STT_SECTION symbols, locals and then globals with undefined ones last
+        assert_eq!(
+            symbols,
+            [
+                ("", 0, 0, 0),
+                ("", 3, 1, 0),
+                ("", 3, 2, 0),
+                ("", 3, 3, 0),
+                ("loop", 2, 1, 5),
+                ("msg", 1, 2, 0),
+                ("main", 0x12, 1, 0),
+                ("buffer", 0x11, 3, 0),
+                ("puts", 0x10, 0, 0),
+            ]
+        );
+
+        // `info` is the index of the first global
+        assert_eq!(info, 6);
+    }
+
+    #[test]
+    fn symbol_sizes() {
+        let sizes: Vec<(String, u64)> = symbols(&object()).0.into_iter().skip(4).map(|(name, _, _, _, size)| (name, size)).collect();
+
+        assert_eq!(sizes, [(String::from("loop"), 8), (String::from("msg"), 3), (String::from("main"), 5), (String::from("buffer"), 0x40), (String::from("puts"), 0)]);
+    }
+
+    #[test]
+    fn relocations() {
+        let bytes = object();
+        let sections = sections(&bytes);
+        let (_, _, _, offset, size, link, info) = sections[5];
+        let text = sections[1].3;
+
+        assert_eq!((size, link, info), (2 * RELA_SIZE as usize, 6, 1));
+
+        // [r_offset r_info r_addend], puts is symbol 8 and msg goes through the STT_SECTION symbol of .data
+        assert_eq!((u64_at(&bytes, offset), u64_at(&bytes, offset + 8), u64_at(&bytes, offset + 16) as i64), (1, 8 << 32 | R_X86_64_PLT32 as u64, -4));
+        assert_eq!((u64_at(&bytes, offset + 24), u64_at(&bytes, offset + 32), u64_at(&bytes, offset + 40) as i64), (8, 2 << 32 | R_X86_64_PC32 as u64, 8));
+
+        // the addends moved out of the fields
+        assert_eq!(&bytes[text..text + 13], [0xe8, 0, 0, 0, 0, 0x48, 0x8d, 0x3d, 0, 0, 0, 0, 0xc3]);
+    }
+}
+
@@ -0,0 +1,106 @@
+use super::vex::{Map, Prefix};
+
+// the disp8*N compression factor is derived from the tuple type of the instruction
+#[derive(Clone, Copy)]
+pub enum Tuple {
+    Full(i32),
+    FullMem,
+    Scalar(i32),
+}
+
+impl Tuple {
+    pub fn n(&self, length: i32, broadcast: bool) -> i32 {
+        match self {
+            Tuple::Full(element) if broadcast => *element,
+            Tuple::Full(_) | Tuple::FullMem => length,
+            Tuple::Scalar(element) => *element,
+        }
+    }
+}
+
+// evex fields are stored un-inverted like `Vex`, `vvvv` holds all 5 bits including V'
+pub struct Evex {
+    pub r: bool,
+    pub x: bool,
+    pub b: bool,
+    pub r2: bool,
+    pub map: Map,
+    pub w: bool,
+    pub vvvv: u8,
+    pub pp: Prefix,
+    pub z: bool,
+    pub ll: u8,
+    pub bcst: bool,
+    pub aaa: u8,
+}
+
+impl Evex {
+    pub fn new(map: Map, pp: Prefix, w: bool) -> Evex {
+        Evex {
+            r: false,
+            x: false,
+            b: false,
+            r2: false,
+            map,
+            w,
+            vvvv: 0,
+            pp,
+            z: false,
+            ll: 0,
+            bcst: false,
+            aaa: 0,
+        }
+    }
+
+    // section 2.7 @ intel programmers manual vol 2
+    pub fn encode(&self) -> Vec<u8> {
+        vec![
+            // 62 [R X B R' 0 0 m m] [W vvvv 1 pp] [z L'L b V' aaa]
+            0x62,
+            ((!self.r as u8) << 7) | ((!self.x as u8) << 6) | ((!self.b as u8) << 5) | ((!self.r2 as u8) << 4) | self.map as u8,
+            ((self.w as u8) << 7) | ((!self.vvvv & 0xf) << 3) | 0x4 | self.pp as u8,
+            ((self.z as u8) << 7) | ((self.ll & 3) << 5) | ((self.bcst as u8) << 4) | (((self.vvvv & 0x10) == 0) as u8) << 3 | (self.aaa & 7),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode() {
+        let mut evex = Evex::new(Map::Of, Prefix::None, false);
+
+        evex.vvvv = 1;
+        evex.ll = 2;
+
+        assert_eq!(evex.encode(), [0x62, 0xf1, 0x74, 0x48]);
+
+        // V' is the inverted fifth bit of vvvv, z and aaa come from the write mask
+        evex.vvvv = 17;
+        evex.z = true;
+        evex.aaa = 1;
+        evex.bcst = true;
+
+        assert_eq!(evex.encode(), [0x62, 0xf1, 0x74, 0xd1]);
+
+        let mut evex = Evex::new(Map::Of38, Prefix::P66, true);
+
+        evex.r = true;
+        evex.r2 = true;
+
+        assert_eq!(evex.encode(), [0x62, 0x62, 0xfd, 0x08]);
+    }
+
+    #[test]
+    fn disp8n() {
+        // full vectors compress by the vector length unless they broadcast an element
+        assert_eq!(Tuple::Full(4).n(64, false), 64);
+        assert_eq!(Tuple::Full(4).n(64, true), 4);
+        assert_eq!(Tuple::Full(8).n(32, true), 8);
+        assert_eq!(Tuple::FullMem.n(16, false), 16);
+        assert_eq!(Tuple::Scalar(4).n(64, false), 4);
+    }
+}
+
@@ -0,0 +1,167 @@
+use clap::ValueEnum;
+
+// how flat binaries are written, everything but `raw` is text
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy)]
+pub enum Flat {
+    Raw,
+    Ihex,
+    Srec,
+    C,
+    Rust,
+}
+
+impl Flat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Flat::Raw => ".bin",
+            Flat::Ihex => ".hex",
+            Flat::Srec => ".srec",
+            Flat::C => ".h",
+            Flat::Rust => ".rs",
+        }
+    }
+
+    // `address` is where the first byte is loaded and `name` is the stem of the output file
+    pub fn write(&self, bytes: &[u8], address: usize, name: &str) -> Vec<u8> {
+        match self {
+            Flat::Raw => bytes.to_vec(),
+            Flat::Ihex => intel_hex(bytes, address).into_bytes(),
+            Flat::Srec => srecord(bytes, address, name).into_bytes(),
+            Flat::C => c_array(bytes, name).into_bytes(),
+            Flat::Rust => rust_array(bytes, name).into_bytes(),
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+// the checksum makes the sum of all bytes in a record zero
+fn intel_record(kind: u8, address: u16, data: &[u8]) -> String {
+    let record = [&[data.len() as u8], &address.to_be_bytes()[..], &[kind], data].concat();
+    let checksum = record.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)).wrapping_neg();
+
+    format!(":{}{:02X}\n", hex(&record), checksum)
+}
+
+// https://en.wikipedia.org/wiki/Intel_HEX
+fn intel_hex(bytes: &[u8], address: usize) -> String {
+    let mut hex = String::new();
+    let mut offset = 0;
+    let mut upper = 0;
+
+    while offset < bytes.len() {
+        let address = address + offset;
+
+        // records cant cross into the next 64k, 04 sets the upper 16 bits of the address for the records after it
+        let length = (0x10000 - (address & 0xffff)).min(16).min(bytes.len() - offset);
+
+        if address >> 16 != upper {
+            upper = address >> 16;
+            hex.push_str(&intel_record(4, 0, &(upper as u16).to_be_bytes()));
+        }
+
+        // 00 data
+        hex.push_str(&intel_record(0, address as u16, &bytes[offset..offset + length]));
+        offset += length;
+    }
+
+    // 01 end of file
+    hex + &intel_record(1, 0, &[])
+}
+
+// the count covers the address, the data and the checksum, the checksum is the complement of the sum of all of them
+fn s_record(kind: u8, address: usize, width: usize, data: &[u8]) -> String {
+    let record = [&[(width + data.len() + 1) as u8], &address.to_be_bytes()[8 - width..], data].concat();
+    let checksum = !record.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+
+    format!("S{}{}{:02X}\n", kind, hex(&record), checksum)
+}
+
+// https://en.wikipedia.org/wiki/SREC_(file_format), the address width is picked once for the whole file
+fn srecord(bytes: &[u8], address: usize, name: &str) -> String {
+    let end = address + bytes.len();
+    let (data, terminator, width) = match end {
+        0..=0x10000 => (1, 9, 2),
+        0x10001..=0x1000000 => (2, 8, 3),
+        _ => (3, 7, 4),
+    };
+
+    // S0 header with the name of the image
+    let mut srec = s_record(0, 0, 2, name.as_bytes());
+
+    for (index, chunk) in bytes.chunks(32).enumerate() {
+        srec.push_str(&s_record(data, address + index * 32, width, chunk));
+    }
+
+    // S5 or S6 record count and a terminator with the start address
+    let count = bytes.chunks(32).len();
+
+    srec.push_str(&if count > 0xffff { s_record(6, count, 3, &[]) } else { s_record(5, count, 2, &[]) });
+    srec + &s_record(terminator, address, width, &[])
+}
+
+// identifiers can only have alphanumerics and underscores and cant start with a digit
+fn identifier(name: &str) -> String {
+    let name: String = name.chars().map(|char| if char.is_ascii_alphanumeric() { char } else { '_' }).collect();
+
+    if name.starts_with(|char: char| char.is_ascii_digit()) || name.is_empty() {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+fn array(bytes: &[u8]) -> String {
+    bytes.chunks(12)
+        .map(|chunk| format!("    {},\n", chunk.iter().map(|byte| format!("0x{:02x}", byte)).collect::<Vec<String>>().join(", ")))
+        .collect()
+}
+
+// the same layout as `xxd -i`
+fn c_array(bytes: &[u8], name: &str) -> String {
+    let name = identifier(name);
+
+    format!("unsigned char {}[] = {{\n{}}};\nunsigned int {}_len = {};\n", name, array(bytes), name, bytes.len())
+}
+
+fn rust_array(bytes: &[u8], name: &str) -> String {
+    format!("pub const {}: &[u8] = &[\n{}];\n", identifier(name).to_uppercase(), array(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intel_records() {
+        assert_eq!(intel_record(0, 0x10, b"address gap"), ":0B0010006164647265737320676170A7\n");
+        assert_eq!(intel_record(1, 0, &[]), ":00000001FF\n");
+    }
+
+    #[test]
+    fn intel_upper_address() {
+        // the record stops at the 64k boundary and 04 moves the rest into the next one
+        let hex = String::from_utf8(Flat::Ihex.write(&[1, 2, 3, 4], 0xfffe, "image")).unwrap();
+
+        assert_eq!(hex, ":02FFFE000102FE\n:020000040001F9\n:020000000304F7\n:00000001FF\n");
+    }
+
+    #[test]
+    fn s_records() {
+        assert_eq!(s_record(1, 0x7af0, 2, &[0x0a, 0x0a, 0x0d, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]), "S1137AF00A0A0D0000000000000000000000000061\n");
+        assert_eq!(s_record(0, 0, 2, b"hello     \0\0"), "S00F000068656C6C6F202020202000003C\n");
+        assert_eq!(s_record(5, 3, 2, &[]), "S5030003F9\n");
+        assert_eq!(s_record(9, 0, 2, &[]), "S9030000FC\n");
+    }
+
+    #[test]
+    fn s_record_width() {
+        // addresses past 64k take three bytes in S2 records and the S8 terminator
+        let srec = String::from_utf8(Flat::Srec.write(&[0xc3], 0x10000, "a")).unwrap();
+
+        assert_eq!(srec, "S0040000619A\nS205010000C336\nS5030001FB\nS804010000FA\n");
+    }
+}
+
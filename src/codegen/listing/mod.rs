@@ -0,0 +1,46 @@
+// bytes are listed 8 to a row, the rest continue on rows of their own
+const ROW: usize = 8;
+
+// an instruction and the bytes it was encoded to, `depth` is how many macro calls it was expanded from
+pub struct Entry {
+    pub address: usize,
+    pub bytes: Vec<u8>,
+    pub line: usize,
+    pub depth: usize,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<String>>().join(" ")
+}
+
+// [address: bytes source], macro expansions are indented under the call they came from
+pub fn write(source: &str, entries: &[Entry]) -> String {
+    let lines: Vec<&str> = source.lines().collect();
+    let width = if entries.iter().any(|entry| entry.address > u32::MAX as usize) { 16 } else { 8 };
+    let mut listing = String::new();
+    let mut indent = "";
+
+    for entry in entries {
+        let text = lines.get(entry.line.wrapping_sub(1)).copied().unwrap_or("").trim_end();
+
+        // expansions are indented one level deeper than the call they are under
+        let text = if entry.depth > 0 {
+            format!("{}{}{}", indent, "    ".repeat(entry.depth), text.trim_start())
+        } else {
+            indent = &text[..text.len() - text.trim_start().len()];
+            text.to_string()
+        };
+
+        let mut rows = entry.bytes.chunks(ROW);
+
+        listing.push_str(format!("{:0width$X}: {:<columns$} {}", entry.address, hex(rows.next().unwrap_or(&[])), text, width = width, columns = ROW * 3 - 1).trim_end());
+        listing.push('\n');
+
+        for (index, row) in rows.enumerate() {
+            listing.push_str(&format!("{:0width$X}: {}\n", entry.address + (index + 1) * ROW, hex(row), width = width));
+        }
+    }
+
+    listing
+}
+
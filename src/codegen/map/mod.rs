@@ -0,0 +1,71 @@
+use crate::parser::lexer::Span;
+use crate::json::{string, number};
+
+use clap::ValueEnum;
+
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy)]
+pub enum MapFormat {
+    Text,
+    Json,
+}
+
+// constants and macros have no section, address or size, externs only have an address when they are called through a stub
+pub struct Symbol {
+    pub name: String,
+    pub kind: &'static str,
+    pub section: Option<String>,
+    pub address: Option<usize>,
+    pub size: Option<usize>,
+    pub span: Span,
+}
+
+impl MapFormat {
+    pub fn write(&self, symbols: &[Symbol]) -> String {
+        match self {
+            MapFormat::Text => text(symbols),
+            MapFormat::Json => json(symbols),
+        }
+    }
+}
+
+fn text(symbols: &[Symbol]) -> String {
+    let width = if symbols.iter().any(|symbol| symbol.address.is_some_and(|address| address > u32::MAX as usize)) { 16 } else { 8 };
+
+    let rows: Vec<[String; 6]> = symbols.iter()
+        .map(|symbol| [
+            symbol.address.map(|address| format!("{:0width$x}", address, width = width)).unwrap_or_else(|| String::from("-")),
+            symbol.size.map(|size| format!("{:#x}", size)).unwrap_or_else(|| String::from("-")),
+            symbol.kind.to_string(),
+            symbol.section.clone().unwrap_or_else(|| String::from("-")),
+            symbol.name.clone(),
+            symbol.span.to_string(),
+        ])
+        .collect();
+
+    let header = [String::from("address"), String::from("size"), String::from("kind"), String::from("section"), String::from("name"), String::from("location")];
+    let widths: Vec<usize> = (0..header.len()).map(|column| std::iter::once(&header).chain(&rows).map(|row| row[column].len()).max().unwrap_or(0)).collect();
+
+    std::iter::once(&header)
+        .chain(&rows)
+        .map(|row| row.iter().zip(&widths).map(|(cell, width)| format!("{:<width$}", cell, width = width)).collect::<Vec<String>>().join("  ").trim_end().to_string() + "\n")
+        .collect()
+}
+
+fn json(symbols: &[Symbol]) -> String {
+    let symbols: Vec<String> = symbols.iter()
+        .map(|symbol| format!(
+            "    {{\"name\": {}, \"kind\": {}, \"section\": {}, \"address\": {}, \"size\": {}, \"file\": {}, \"line\": {}, \"column\": {}}}",
+            string(&symbol.name),
+            string(symbol.kind),
+            symbol.section.as_deref().map(string).unwrap_or_else(|| String::from("null")),
+            number(symbol.address),
+            number(symbol.size),
+            string(&symbol.span.file),
+            symbol.span.line,
+            symbol.span.start,
+        ))
+        .collect();
+
+    format!("{{\n  \"symbols\": [\n{}\n  ]\n}}\n", symbols.join(",\n"))
+}
+
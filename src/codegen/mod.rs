@@ -1,16 +1,52 @@
+mod evex;
+mod elf;
+mod pe;
+mod object;
+mod flat;
+mod dwarf;
+mod listing;
+mod map;
+mod vex;
+
 use crate::preprocessor::Preprocessor;
 use crate::parser::lexer::Register;
+use crate::parser::lexer::Span;
+use crate::log::{Diagnostics, Lint};
+use crate::error::Error;
 use crate::parser::ConstExpr;
+use crate::parser::Permissions;
+use crate::parser::Rounding;
+use crate::parser::Format;
+use crate::parser::Address;
+use crate::parser::Size;
 use crate::parser::Parser;
 use crate::parser::Value;
 use crate::parser::Inst;
+use crate::parser::Spanned;
+
+pub use flat::Flat;
+pub use map::MapFormat;
 
-use faerie::{ArtifactBuilder, Artifact};
+use evex::{Evex, Tuple};
+use elf::{Executable, Segment, Dynamic, Export};
+use vex::{Vex, Map, Prefix};
+
+use faerie::{ArtifactBuilder, Artifact, Link, Reloc, Decl};
+use faerie::artifact::DefinedDecl;
 use target_lexicon::triple;
 
+use std::collections::{HashMap, HashSet};
+use std::cell::RefCell;
 use std::process::Command;
 use std::str::FromStr;
-use std::fs::File;
+use std::os::unix::fs::PermissionsExt;
+use std::fs::{self, File};
+use std::io::Write;
+
+// relocation types @ mach-o/x86_64/reloc.h
+const X86_64_RELOC_UNSIGNED: u32 = 0;
+const X86_64_RELOC_SIGNED: u32 = 1;
+const X86_64_RELOC_BRANCH: u32 = 2;
 
 struct Opcode {
     opcode: u8,
@@ -26,22 +62,121 @@ impl Opcode {
     }
 }
 
+#[derive(Clone, Copy)]
+struct VectorOpcode {
+    opcode: u8,
+    map: Map,
+    pp: Prefix,
+    w: bool,
+    vex: bool,
+    tuple: Option<Tuple>,
+}
+
+impl VectorOpcode {
+    pub fn vex(opcode: u8, map: Map, pp: Prefix, w: bool) -> VectorOpcode {
+        VectorOpcode {
+            opcode,
+            map,
+            pp,
+            w,
+            vex: true,
+            tuple: None,
+        }
+    }
+
+    pub fn evex(opcode: u8, map: Map, pp: Prefix, w: bool, tuple: Tuple) -> VectorOpcode {
+        VectorOpcode {
+            opcode,
+            map,
+            pp,
+            w,
+            vex: false,
+            tuple: Some(tuple),
+        }
+    }
+
+    pub fn both(opcode: u8, map: Map, pp: Prefix, w: bool, tuple: Tuple) -> VectorOpcode {
+        VectorOpcode {
+            vex: true,
+            ..VectorOpcode::evex(opcode, map, pp, w, tuple)
+        }
+    }
+}
+
+#[derive(Default)]
+struct Decorations {
+    mask: Option<Register>,
+    zeroing: bool,
+    rounding: Option<Rounding>,
+}
+
+// the bytes of one section, a section made only of reserved space needs no room in the file
+#[derive(Default)]
+struct Image {
+    bytes: Vec<u8>,
+    reserved: usize,
+}
+
+impl Image {
+    fn is_bss(&self) -> bool {
+        self.reserved == self.bytes.len()
+    }
+}
+
+// a symbol reference in `buf`, relative ones are patched once the instruction is complete and object files relocate the rest
+#[derive(Clone)]
+struct Fixup {
+    symbol: String,
+    at: usize,
+    size: usize,
+    addend: i32,
+    relative: bool,
+    reloc: u32,
+}
+
+struct ModRm {
+    prefixes: Vec<u8>,
+    bytes: Vec<u8>,
+    x: bool,
+    b: bool,
+    fixup: Option<Fixup>,
+}
+
 pub struct Codegen {
     obj: Artifact,
     parser: Parser,
     pub preprocessor: Preprocessor,
     buf: Vec<u8>,
+    reserved: usize,
+    images: Vec<Image>,
+    definitions: HashMap<String, Vec<u8>>,
+    fixups: Vec<Fixup>,
+    relocations: Vec<elf::Relocation>,
+    objects: Vec<object::Relocation>,
+    homes: HashMap<String, usize>,
+    load: usize,
+    rows: Vec<(usize, dwarf::Row)>,
+    listing: Vec<listing::Entry>,
+    calls: Vec<(Span, String)>,
+    used: RefCell<HashSet<String>>,
+    jump: Option<Span>,
+    file: String,
+    plt: usize,
+    got: usize,
     label: String,
-    pub line: usize,
+    sizing: bool,
+    bits: u8,
+    pub span: Span,
+    operands: Vec<Span>,
+    pub diagnostics: Diagnostics,
 }
 
 impl Codegen {
-    pub fn new(file: &str) -> Result<Codegen, Box<dyn std::error::Error>> {
-        let mut parser = Parser::new(file)?;
+    pub fn new(file: &str, base: Option<usize>, diagnostics: Diagnostics) -> Result<Codegen, Error> {
+        let parser = Parser::new(file)?;
         let mut preprocessor = Preprocessor::new();
 
-        preprocessor.preprocess(&mut parser)?;
-        parser.lexer.rewind()?;
+        preprocessor.origin = base;
 
         Ok(Codegen {
             obj: ArtifactBuilder::new(triple!("x86_64-unknown-unknown-unknown-elf"))
@@ -49,9 +184,32 @@ impl Codegen {
                 .finish(),
             parser,
             buf: Vec::new(),
+            reserved: 0,
+            images: Vec::new(),
+            definitions: HashMap::new(),
+            fixups: Vec::new(),
+            relocations: Vec::new(),
+            objects: Vec::new(),
+            homes: HashMap::new(),
+            load: 0,
+            rows: Vec::new(),
+            listing: Vec::new(),
+            calls: Vec::new(),
+            used: RefCell::new(HashSet::new()),
+            jump: None,
+            file: file.to_string(),
+            plt: 0,
+            got: 0,
             preprocessor,
             label: String::new(),
-            line: 1,
+            sizing: false,
+            bits: 64,
+            span: Span {
+                file: file.into(),
+                ..Span::default()
+            },
+            operands: Vec::new(),
+            diagnostics,
         })
     }
 
@@ -63,213 +221,2393 @@ impl Codegen {
         }
     }
 
-    fn rm(reg: Register) -> u8 {
+    fn immediate(integer: i64, size: Size) -> Vec<u8> {
+        let length = match size {
+            Size::Byte => 1,
+            Size::Word => 2,
+            _ => 4,
+        };
+
+        Self::to_bytes(integer as i32)[..length].to_vec()
+    }
+
+    fn index(reg: Register) -> u8 {
         match reg {
-            Register::Eax => 0,
-            Register::Ebx => 3,
-            Register::Ecx => 1,
-            Register::Edx => 2,
-            Register::Esi => 6,
-            Register::Edi => 7,
-            Register::Esp => 4,
-            Register::Ebp => 5,
+            Register::Ax | Register::Eax | Register::Rax => 0,
+            Register::Bx | Register::Ebx | Register::Rbx => 3,
+            Register::Cx | Register::Ecx | Register::Rcx => 1,
+            Register::Dx | Register::Edx | Register::Rdx => 2,
+            Register::Si | Register::Esi | Register::Rsi => 6,
+            Register::Di | Register::Edi | Register::Rdi => 7,
+            Register::Sp | Register::Esp | Register::Rsp => 4,
+            Register::Bp | Register::Ebp | Register::Rbp | Register::Rip => 5,
+            Register::R8 => 8,
+            Register::R9 => 9,
+            Register::R10 => 10,
+            Register::R11 => 11,
+            Register::R12 => 12,
+            Register::R13 => 13,
+            Register::R14 => 14,
+            Register::R15 => 15,
+            Register::Xmm(index) | Register::Ymm(index) | Register::Zmm(index) | Register::K(index) | Register::St(index) => index,
+            Register::Es => 0,
+            Register::Cs => 1,
+            Register::Ss => 2,
+            Register::Ds => 3,
+            Register::Fs => 4,
+            Register::Gs => 5,
+            Register::Cr(index) | Register::Dr(index) => index,
         }
     }
 
-    // https://en.wikipedia.org/wiki/ModR/M
-    // page 44 @ intel programmers manual
-    fn format_modrm(mod_: u8, reg: u8, rm: u8) -> u8 {
-        (mod_ << 6) | (reg << 3) | rm
+    fn size(reg: Register) -> Option<Size> {
+        if reg.is_gpr16() {
+            Some(Size::Word)
+        } else if reg.is_gpr32() {
+            Some(Size::Dword)
+        } else if reg.is_gpr64() {
+            Some(Size::Qword)
+        } else {
+            None
+        }
     }
 
-    fn define_label(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        if !self.label.is_empty() {
-            self.obj.define(self.label.clone(), self.buf.clone())?;
-            self.buf.drain(..);
+    fn rm(&self, reg: Register) -> Result<u8, Error> {
+        match Self::size(reg) {
+            Some(Size::Qword) if self.bits != 64 => Err(Error::Use64Only(format!("`{}`", reg))),
+            Some(_) => Ok(Self::index(reg)),
+            None => Err(Error::NotGeneralPurpose(reg)),
         }
+    }
 
-        Ok(())
+    // a source that does not match the destination is the one that is wrong
+    fn operand_size(&mut self, lhs: Register, rhs: Register) -> Result<Size, Error> {
+        self.at(0, self.rm(lhs))?;
+        self.at(1, self.rm(rhs))?;
+
+        match (Self::size(lhs), Self::size(rhs)) {
+            (Some(lhs), Some(rhs)) if lhs == rhs => Ok(lhs),
+            _ => self.at(1, Err(Error::OperandSize(None))),
+        }
     }
 
-    fn encode_jcc(&mut self, opcode: &[u8], label: String) -> Result<(), Box<dyn std::error::Error>> {
-        if let Some(addr) = self.preprocessor.offsets.get(&label) {
-            self.preprocessor.offset += opcode.len() + 4;
+    // where the operand at `index` is written, instructions without it point at themselves
+    fn operand(&self, index: usize) -> Span {
+        self.operands.get(index).cloned().unwrap_or_else(|| self.span.clone())
+    }
 
-            self.buf.extend(&[opcode.to_vec(), Self::to_bytes((*addr as i32) - self.preprocessor.offset as i32)].concat());
+    // errors about one operand are reported at it, the next instruction points at itself again
+    fn at<T>(&mut self, index: usize, result: Result<T, Error>) -> Result<T, Error> {
+        if result.is_err() {
+            self.span = self.operand(index);
+        }
 
-            Ok(())
+        result
+    }
+
+    fn memory_size(address: &Address, size: Size) -> Result<Size, Error> {
+        if address.size.is_some_and(|address| address != size) {
+            Err(Error::OperandSize(None))
         } else {
-            Err(format!("no such label `{}`", label).into())
+            Ok(size)
         }
     }
 
-    fn encode_binary_expr(&mut self, lhs: Value, rhs: Value, opcodes: [Opcode; 3]) -> Result<(), Box<dyn std::error::Error>> {
-        if let Value::Register(rd) = lhs {
-            if let Value::Integer(id) = rhs {
-                if rd == Register::Eax {
-                    // [OPCODE] id
-                    self.buf.extend(&[vec![opcodes[0].opcode], Self::to_bytes(id)].concat());
-                    self.preprocessor.offset += 5;
-                } else {
-                    // [OPCODE] /[REG]
-                    self.buf.extend(&[vec![opcodes[1].opcode, Self::format_modrm(3, opcodes[1].reg, Self::rm(rd))], Self::to_bytes(id)].concat());
-                    self.preprocessor.offset += 6;
-                }
-            } else if let Value::Register(id) = rhs {
-                // [OPCODE] /r
-                self.buf.extend(&[opcodes[2].opcode, Self::format_modrm(3, Self::rm(id), Self::rm(rd))]);
-                self.preprocessor.offset += 2;
-            }
+    fn label_address(&self, label: &str) -> Option<usize> {
+        self.used.borrow_mut().insert(label.to_string());
 
-            Ok(())
+        // labels are only known after the sizing pass, a placeholder keeps the encoding the same length
+        let placeholder = self.sizing && self.preprocessor.is_label(label);
+
+        // position independent images call imports through their stub in the procedure linkage table
+        if let Some(index) = self.preprocessor.externs.iter().position(|extrn| extrn == label) {
+            Some(if self.has_stubs() { self.plt + 8 * index } else { 0 })
         } else {
-            Err("cant add into non-register".into())
+            self.preprocessor.offsets.get(label).copied().or(placeholder.then_some(self.preprocessor.offset))
         }
     }
 
-    fn constexpr(&self, value: &Value) -> Result<Value, Box<dyn std::error::Error>> {
-        match value {
-            Value::Integer(_) | Value::Register(_) => Ok(value.clone()),
-            Value::Const(ident) => {
-                let constant = self.preprocessor.consts.get(ident).ok_or(format!("no such constant `{}`", ident))?;
+    fn is_artifact(&self) -> bool {
+        self.preprocessor.format.is_faerie()
+    }
 
-                Ok(self.constexpr(constant)?)
-            },
-        }
+    // object files and position independent images cant use absolute addresses as they are
+    fn is_relocatable(&self) -> bool {
+        self.preprocessor.format.is_object() || self.preprocessor.format.is_dynamic()
     }
 
-    fn build_inst(&mut self, inst: Inst) -> Result<bool, Box<dyn std::error::Error>> {
-        match inst {
-            Inst::Label { ident } => {
-                self.define_label()?;
+    // images call imports through a stub that jumps through the slot the loader fills in
+    fn has_stubs(&self) -> bool {
+        self.preprocessor.format.is_dynamic() || self.preprocessor.format.is_pe()
+    }
 
-                self.label = ident;
-            },
-            Inst::Push { value } => {
-                if let Value::Integer(id) = self.constexpr(&value)? {
-                    // 68 id
-                    self.buf.extend(&[vec![0x68], Self::to_bytes(id)].concat());
-                    self.preprocessor.offset += 5;
-                } else if let Value::Register(rd) = self.constexpr(&value)? {
-                    // FF /6
-                    self.buf.extend(&[0xff, Self::format_modrm(3, 6, Self::rm(rd))]);
-                    self.preprocessor.offset += 2;
-                }
-            },
-            Inst::Pop { dest } => {
-                // 58+ rd
-                self.buf.extend(&[0x58 + Self::rm(dest)]);
-                self.preprocessor.offset += 1;
-            },
-            Inst::Mov { lhs, rhs } => {
-                if let Value::Register(rd) = self.constexpr(&lhs)? {
-                    if let Value::Integer(id) = self.constexpr(&rhs)? {
-                        // B8+ rd id
-                        self.buf.extend(&[vec![0xb8 + Self::rm(rd)], Self::to_bytes(id)].concat());
-                        self.preprocessor.offset += 5;
-                    } else if let Value::Register(id) = self.constexpr(&rhs)? {
-                        // 89 /r
-                        self.buf.extend(&[0x89, Self::format_modrm(3, Self::rm(id), Self::rm(rd))]);
-                        self.preprocessor.offset += 2;
-                    }
-                } else {
-                    return Err("cant move into non-register".into());
-                }
-            },
-            Inst::Add { lhs, rhs } => self.encode_binary_expr(lhs, rhs, [Opcode::new(0x05, 0), Opcode::new(0x81, 0), Opcode::new(0x01, 0)])?,
-            Inst::Sub { lhs, rhs } => self.encode_binary_expr(lhs, rhs, [Opcode::new(0x2d, 0), Opcode::new(0x81, 5), Opcode::new(0x29, 0)])?,
-            Inst::Mul { dest } => {
-                self.buf.extend(&[0xf7, Self::format_modrm(3, 4, Self::rm(dest))]);
-                self.preprocessor.offset += 2;
-            },
-            Inst::Cmp { lhs, rhs } => {
-                if let Value::Register(rd) = self.constexpr(&lhs)? {
-                    if let Value::Integer(id) = self.constexpr(&rhs)? {
-                        // 81 /7 id
-                        self.buf.extend(&[vec![0x81, Self::format_modrm(3, 7, Self::rm(rd))], Self::to_bytes(id)].concat());
-                        self.preprocessor.offset += 6;
-                    } else if let Value::Register(id) = self.constexpr(&rhs)? {
-                        // 39 /r
-                        self.buf.extend(&[0x39, Self::format_modrm(3, Self::rm(id), Self::rm(rd))]);
-                        self.preprocessor.offset += 2;
-                    }
-                } else {
-                    return Err("cant cmp non register".into());
-                }
-            },
-            Inst::Jmp { label } => self.encode_jcc(&[0xe9], label)?,
-            Inst::Je { label } => self.encode_jcc(&[0x0f, 0x84], label)?,
-            Inst::Jg { label } => self.encode_jcc(&[0x0f, 0x8f], label)?,
-            Inst::Jb { label } => self.encode_jcc(&[0x0f, 0x82], label)?,
-            Inst::Syscall => {
-                self.buf.extend(&[0x0f, 0x05]);
-                self.preprocessor.offset += 2;
-            },
-            Inst::Eof => {
-                self.define_label()?;
+    fn is_extern(&self, symbol: &str) -> bool {
+        self.preprocessor.externs.iter().any(|extrn| extrn == symbol)
+    }
 
-                return Ok(true);
-            },
-            Inst::ConstExpr(ConstExpr::Call { ident, args }) => {
-                let clone = self.preprocessor.macros.clone();
-                let macro_ = clone.get(&ident).ok_or::<Box<dyn std::error::Error>>(format!("no such macro `{}`", ident).into())?;
+    // the definition a symbol belongs to and its offset into it
+    fn definition(&self, symbol: &str) -> Result<(String, i32), Error> {
+        self.used.borrow_mut().insert(symbol.to_string());
 
-                if args.len() != macro_.args.len() {
-                    return Err(format!("expected {} arguments but got {}", macro_.args.len(), args.len()).into());
-                }
+        if self.is_extern(symbol) {
+            return Ok((symbol.to_string(), 0));
+        }
 
-                let constants = self.preprocessor.consts.clone();
-                {
-                    for (index, arg) in args.iter().enumerate() {
-                        self.preprocessor.consts.insert(macro_.args[index].clone(), arg.clone());
-                    }
+        let parent = self.preprocessor.parents.get(symbol).map(String::as_str).unwrap_or(symbol);
 
-                    for inst in &macro_.body {
-                        self.build_inst(inst.clone())?;
-                    }
-                }
+        match (self.preprocessor.offsets.get(symbol), self.preprocessor.offsets.get(parent)) {
+            (Some(offset), Some(start)) => Ok((parent.to_string(), (offset - start) as i32)),
+            _ => Err(Error::NoSuchLabel(symbol.to_string())),
+        }
+    }
 
-                self.preprocessor.consts = constants;
+    // the label an immediate refers to, only relocatable output needs to know about it
+    fn symbol(&self, value: &Value) -> Option<String> {
+        match value {
+            Value::Const(ident) if self.is_relocatable() && !self.sizing => match self.preprocessor.consts.get(ident) {
+                Some(constant) => {
+                    self.used.borrow_mut().insert(ident.clone());
+                    self.symbol(constant)
+                },
+                None => Some(ident.clone()),
             },
-            Inst::ConstExpr(_) => {},
+            _ => None,
         }
+    }
 
-        Ok(false)
+    // labels and the constants naming them, unlike `symbol` this is the same in both passes
+    fn is_address(&self, value: &Value) -> bool {
+        match value {
+            Value::Const(ident) => self.preprocessor.consts.get(ident).is_none_or(|constant| self.is_address(constant)),
+            _ => false,
+        }
     }
 
-    fn build(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        self.obj.declarations(self.preprocessor.labels.iter().cloned())?;
+    fn fixup(&mut self, symbol: String, size: usize, addend: i32, relative: bool, reloc: u32) {
+        if !self.sizing {
+            self.fixups.push(Fixup {
+                symbol,
+                at: self.buf.len() - size,
+                size,
+                addend,
+                relative,
+                reloc,
+            });
+        }
+    }
 
-        let mut inst = self.parser.next_inst()?;
+    fn relocate(&mut self, at: usize, to: &str, reloc: u32, addend: i32) -> Result<(), Error> {
+        if self.label.is_empty() {
+            return Err(Error::RelocationOutsideLabel);
+        }
 
-        loop {
-            self.line += 1;
+        let at = self.definitions.get(&self.label).map_or(0, Vec::len) + at;
 
-            if let Some(inst) = inst {
-                if self.build_inst(inst)? {
-                    break;
-                }
+        self.obj.link_with(Link { from: &self.label, to, at: at as u64 }, Reloc::Raw { reloc, addend })?;
+
+        Ok(())
+    }
+
+    // runs after every instruction so relative fixups know where the instruction ends
+    fn resolve_fixups(&mut self) -> Result<(), Error> {
+        for fixup in std::mem::take(&mut self.fixups) {
+            let (definition, offset) = self.definition(&fixup.symbol)?;
+            let local = match self.preprocessor.format {
+                Format::MachO64 => !self.is_extern(&fixup.symbol) && definition == self.label,
+                Format::Elf64 | Format::Ms64Coff => !self.is_extern(&fixup.symbol) && self.homes.get(&fixup.symbol) == Some(&self.preprocessor.section),
+                _ => true,
+            };
+
+            if fixup.relative && self.preprocessor.format.is_dynamic() && self.is_extern(&fixup.symbol) && fixup.reloc != elf::R_X86_64_PLT32 {
+                return Err(Error::ExternAddress(fixup.symbol.clone()));
             }
 
-            inst = self.parser.next_inst()?;
+            if fixup.relative && local {
+                let target = self.label_address(&fixup.symbol).ok_or(Error::NoSuchLabel(fixup.symbol.clone()))? as i32;
+                let value = target.wrapping_add(fixup.addend).wrapping_sub(self.preprocessor.offset as i32);
+
+                self.buf[fixup.at..fixup.at + fixup.size].copy_from_slice(&Self::to_bytes(value)[..fixup.size]);
+            } else if self.is_artifact() {
+                // the addend of a pc relative relocation is measured from the field instead of the end of the instruction
+                let addend = if fixup.relative { offset + fixup.addend - (self.buf.len() - fixup.at) as i32 } else { offset + fixup.addend };
+
+                self.relocate_macho(&fixup, &definition, addend)?;
+            } else if self.preprocessor.format.is_object() {
+                self.relocate_object(fixup)?;
+            } else if self.preprocessor.format.is_dynamic() {
+                self.relocate_dynamic(fixup)?;
+            }
         }
 
         Ok(())
     }
 
-    pub fn emit(&mut self, file: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let file = file.split('.').next().unwrap_or("object");
-        let fd = File::create([file, ".o"].concat())?;
+    // mach-o keeps the addend in the field, faerie only relocates code relative to `rip` and data with absolute addresses
+    fn relocate_macho(&mut self, fixup: &Fixup, definition: &str, addend: i32) -> Result<(), Error> {
+        let executable = self.preprocessor.sections[self.preprocessor.section].permissions.executable;
 
-        self.build()?;
-        self.obj.write(fd)?;
+        let (reloc, addend) = match fixup.reloc {
+            elf::R_X86_64_64 if !executable => (X86_64_RELOC_UNSIGNED, addend as i64),
+            // pc relative addends are measured from the end of the field
+            elf::R_X86_64_PC32 if executable => (X86_64_RELOC_SIGNED, addend as i64 + 4),
+            elf::R_X86_64_PLT32 if executable => (X86_64_RELOC_BRANCH, addend as i64 + 4),
+            _ => return Err(Error::MachOAddress(fixup.symbol.clone())),
+        };
 
-        Command::new("ld")
-            .args(["-o", file, &[file, ".o"].concat()])
-            .spawn()?;
+        self.buf[fixup.at..fixup.at + fixup.size].copy_from_slice(&addend.to_le_bytes()[..fixup.size]);
+        self.relocate(fixup.at, definition, reloc, 0)
+    }
+
+    // sections of elf and coff objects start at zero so offsets into them are label addresses, the field holds the addend
+    fn relocate_object(&mut self, fixup: Fixup) -> Result<(), Error> {
+        let at = self.preprocessor.offset - (self.buf.len() - fixup.at);
+
+        // addr32 is zero extended so it cant stand in for an address the processor sign extends
+        if self.preprocessor.format == Format::Ms64Coff && fixup.reloc == elf::R_X86_64_32S {
+            return Err(Error::SignExtended(fixup.symbol.clone()));
+        }
+
+        let (target, offset) = match self.homes.get(&fixup.symbol) {
+            Some(section) if !self.is_extern(&fixup.symbol) => (object::Target::Section(*section), self.label_address(&fixup.symbol).unwrap_or(0) as i64),
+            _ => (object::Target::Symbol(fixup.symbol.clone()), 0),
+        };
+
+        // pc relative addends are measured from the field, rel32 from the end of it
+        let end = if self.preprocessor.format == Format::Ms64Coff { 4 } else { 0 };
+        let addend = if fixup.relative { offset + fixup.addend as i64 - (self.buf.len() - fixup.at) as i64 + end } else { offset + fixup.addend as i64 };
+
+        self.buf[fixup.at..fixup.at + fixup.size].copy_from_slice(&addend.to_le_bytes()[..fixup.size]);
+
+        self.objects.push(object::Relocation {
+            section: self.preprocessor.section,
+            at: at as u64,
+            target,
+            reloc: fixup.reloc,
+        });
 
         Ok(())
     }
-}
 
+    // absolute addresses in position independent images are patched by the dynamic linker once it knows the load address
+    fn relocate_dynamic(&mut self, fixup: Fixup) -> Result<(), Error> {
+        if fixup.size != 8 {
+            return Err(Error::AbsoluteAddress(fixup.symbol.clone()));
+        }
+
+        let at = self.preprocessor.offset - (self.buf.len() - fixup.at);
+        let symbol = self.preprocessor.externs.iter().position(|extrn| *extrn == fixup.symbol);
+
+        let addend = match symbol {
+            Some(_) => fixup.addend as i64,
+            None => self.label_address(&fixup.symbol).ok_or(Error::NoSuchLabel(fixup.symbol.clone()))? as i64 + fixup.addend as i64,
+        };
+
+        self.buf[fixup.at..fixup.at + fixup.size].copy_from_slice(&addend.to_le_bytes());
+
+        self.relocations.push(elf::Relocation {
+            at: at as u64,
+            symbol,
+            addend,
+        });
+
+        Ok(())
+    }
+
+    fn absolute(size: Size) -> u32 {
+        match size {
+            Size::Byte => elf::R_X86_64_8,
+            Size::Word => elf::R_X86_64_16,
+            Size::Dword => elf::R_X86_64_32,
+            _ => elf::R_X86_64_32S,
+        }
+    }
+
+    // immediates of bytes, words and dwords can be written signed or unsigned, anything out of both ranges loses bits
+    fn is_truncated(integer: i64, size: Size) -> bool {
+        match size {
+            Size::Byte => !(-0x80..=0xff).contains(&integer),
+            Size::Word => !(-0x8000..=0xffff).contains(&integer),
+            Size::Dword => !(-0x8000_0000..=0xffff_ffff).contains(&integer),
+            _ => false,
+        }
+    }
+
+    fn warn_truncated(&mut self, index: usize, value: &Value, integer: i64, size: Size) {
+        if !self.sizing && self.symbol(value).is_none() && Self::is_truncated(integer, size) {
+            let bits = match size {
+                Size::Byte => 8,
+                Size::Word => 16,
+                _ => 32,
+            };
+
+            self.warn(Lint::ImmediateTruncated, self.operand(index), format!("immediate `{}` does not fit in {} bits and is truncated", integer, bits), Vec::new());
+        }
+    }
+
+    // `index` is the operand the immediate was written as
+    fn emit_immediate(&mut self, index: usize, value: &Value, id: i64, size: Size) -> Result<(), Error> {
+        // 64-bit operands sign extend a 32-bit immediate, anything else would load a different value
+        if size == Size::Qword && !self.sizing && self.symbol(value).is_none() && i32::try_from(id).is_err() {
+            return self.at(index, Err(Error::ImmediateRange(id)));
+        }
+
+        self.warn_truncated(index, value, id, size);
+
+        let bytes = Self::immediate(id, size);
+        let length = bytes.len();
+
+        self.preprocessor.offset += length;
+        self.buf.extend(bytes);
+
+        if let Some(symbol) = self.symbol(value) {
+            self.fixup(symbol, length, 0, false, Self::absolute(size));
+        }
+
+        Ok(())
+    }
+
+    fn emit_modrm(&mut self, bytes: Vec<u8>, fixup: Option<Fixup>) {
+        self.preprocessor.offset += bytes.len();
+        self.buf.extend(bytes);
+
+        if let Some(fixup) = fixup {
+            self.fixup(fixup.symbol, fixup.size, fixup.addend, fixup.relative, fixup.reloc);
+        }
+    }
+
+    // https://en.wikipedia.org/wiki/ModR/M
+    // page 44 @ intel programmers manual
+    fn format_modrm(mod_: u8, reg: u8, rm: u8) -> u8 {
+        (mod_ << 6) | ((reg & 7) << 3) | (rm & 7)
+    }
+
+    fn segment_prefix(segment: Register) -> Result<u8, Error> {
+        match segment {
+            Register::Es => Ok(0x26),
+            Register::Cs => Ok(0x2e),
+            Register::Ss => Ok(0x36),
+            Register::Ds => Ok(0x3e),
+            Register::Fs => Ok(0x64),
+            Register::Gs => Ok(0x65),
+            _ => Err(Error::NotSegment(segment)),
+        }
+    }
+
+    fn format_sib(scale: u8, index: u8, base: u8) -> u8 {
+        let ss = match scale {
+            2 => 1,
+            4 => 2,
+            8 => 3,
+            _ => 0,
+        };
+
+        Self::format_modrm(ss, index, base)
+    }
+
+    // `n` is the disp8*N compression factor, legacy and vex encodings use 1
+    fn encode_address(&self, reg: u8, address: &Address, n: i32) -> Result<ModRm, Error> {
+        let absolute = address.base.is_none() && address.index.is_none() && address.symbol.is_some();
+
+        // labels are addressed relative to `rip` in position independent code, coff and mach-o objects
+        let relative = self.preprocessor.format.is_dynamic() || matches!(self.preprocessor.format, Format::Ms64Coff | Format::MachO64);
+
+        if address.base == Some(Register::Rip) || (absolute && self.bits == 64 && relative) {
+            return self.encode_rip_address(reg, address);
+        }
+
+        let registers = address.base.iter().chain(address.index.iter());
+
+        let address_size = if registers.clone().next().is_none() {
+            self.bits
+        } else if registers.clone().all(|reg| reg.is_gpr64()) {
+            64
+        } else if registers.clone().all(|reg| reg.is_gpr32()) {
+            32
+        } else if registers.clone().all(|reg| reg.is_gpr16()) {
+            16
+        } else {
+            return Err(Error::MixedAddress);
+        };
+
+        match (self.bits, address_size) {
+            (64, 16) => return Err(Error::NotInUse64("16-bit addresses")),
+            (16 | 32, 64) => return Err(Error::Use64Only(String::from("64-bit addresses"))),
+            _ => {},
+        }
+
+        let symbol = match &address.symbol {
+            Some(symbol) => self.label_address(symbol).ok_or(Error::NoSuchLabel(symbol.clone()))? as i32,
+            None => 0,
+        };
+
+        let disp = address.disp.wrapping_add(symbol);
+
+        let mut modrm = ModRm {
+            prefixes: Vec::new(),
+            bytes: Vec::new(),
+            x: false,
+            b: false,
+            fixup: None,
+        };
+
+        if let Some(segment) = address.segment {
+            modrm.prefixes.push(Self::segment_prefix(segment)?);
+        }
+
+        if address_size != self.bits {
+            modrm.prefixes.push(0x67);
+        }
+
+        // the displacement always comes last, object files relocate it with the absolute address of the symbol
+        if let Some(symbol) = address.symbol.clone().filter(|_| self.is_relocatable()) {
+            let (size, reloc) = match address_size {
+                16 => (2, elf::R_X86_64_16),
+                32 => (4, elf::R_X86_64_32),
+                _ => (4, elf::R_X86_64_32S),
+            };
+
+            modrm.fixup = Some(Fixup {
+                symbol,
+                at: 0,
+                size,
+                addend: address.disp,
+                relative: false,
+                reloc,
+            });
+        }
+
+        if address_size == 16 {
+            modrm.bytes = Self::encode_address16(reg, address, disp, address.symbol.is_some())?;
+
+            return Ok(modrm);
+        }
+
+        if address.index.is_some_and(|index| Self::index(index) == 4) {
+            return Err(Error::StackIndex);
+        }
+
+        let index = address.index.map(Self::index);
+
+        modrm.x = index.is_some_and(|index| index >= 8);
+        modrm.b = address.base.is_some_and(|base| Self::index(base) >= 8);
+
+        if let Some(base) = address.base.map(Self::index) {
+            // symbols always take a full displacement so the size is the same in both passes
+            let compressed = (disp % n == 0 && address.symbol.is_none())
+                .then_some(disp / n)
+                .and_then(|disp| i8::try_from(disp).ok());
+
+            let mod_ = if disp == 0 && base & 7 != 5 && address.symbol.is_none() {
+                0
+            } else if compressed.is_some() {
+                1
+            } else {
+                2
+            };
+
+            if index.is_some() || base & 7 == 4 {
+                // [MODRM] [SIB]
+                modrm.bytes.extend(&[Self::format_modrm(mod_, reg, 4), Self::format_sib(address.scale, index.unwrap_or(4), base)]);
+            } else {
+                // [MODRM]
+                modrm.bytes.push(Self::format_modrm(mod_, reg, base));
+            }
+
+            match (mod_, compressed) {
+                (1, Some(disp)) => modrm.bytes.push(disp as u8),
+                (2, _) => modrm.bytes.extend(Self::to_bytes(disp)),
+                _ => {},
+            }
+        } else if index.is_none() && self.bits != 64 {
+            // [MODRM] disp32, rm 5 with mod 0 is rip relative in use64 but absolute otherwise
+            modrm.bytes.push(Self::format_modrm(0, reg, 5));
+            modrm.bytes.extend(Self::to_bytes(disp));
+        } else {
+            // [MODRM] [SIB] disp32, base 5 with mod 0 means no base register
+            modrm.bytes.extend(&[Self::format_modrm(0, reg, 4), Self::format_sib(address.scale, index.unwrap_or(4), 5)]);
+            modrm.bytes.extend(Self::to_bytes(disp));
+        }
+
+        Ok(modrm)
+    }
+
+    fn encode_rip_address(&self, reg: u8, address: &Address) -> Result<ModRm, Error> {
+        if self.bits != 64 {
+            return Err(Error::Use64Only(String::from("`rip` relative addresses")));
+        } else if address.index.is_some() {
+            return Err(Error::RipIndex);
+        }
+
+        let mut modrm = ModRm {
+            prefixes: Vec::new(),
+            // [MODRM] disp32, rm 5 with mod 0 is relative to the end of the instruction
+            bytes: [vec![Self::format_modrm(0, reg, 5)], Self::to_bytes(address.disp)].concat(),
+            x: false,
+            b: false,
+            fixup: address.symbol.clone().map(|symbol| Fixup {
+                symbol,
+                at: 0,
+                size: 4,
+                addend: address.disp,
+                relative: true,
+                reloc: elf::R_X86_64_PC32,
+            }),
+        };
+
+        if let Some(segment) = address.segment {
+            modrm.prefixes.push(Self::segment_prefix(segment)?);
+        }
+
+        Ok(modrm)
+    }
+
+    // table 2-1 @ intel programmers manual vol 2
+    fn encode_address16(reg: u8, address: &Address, disp: i32, symbol: bool) -> Result<Vec<u8>, Error> {
+        if address.scale != 1 {
+            return Err(Error::ScaledAddress16);
+        } else if !(-0x8000..=0xffff).contains(&disp) {
+            return Err(Error::Displacement16(disp));
+        }
+
+        let rm = match (address.base, address.index) {
+            (Some(Register::Bx), Some(Register::Si)) | (Some(Register::Si), Some(Register::Bx)) => 0,
+            (Some(Register::Bx), Some(Register::Di)) | (Some(Register::Di), Some(Register::Bx)) => 1,
+            (Some(Register::Bp), Some(Register::Si)) | (Some(Register::Si), Some(Register::Bp)) => 2,
+            (Some(Register::Bp), Some(Register::Di)) | (Some(Register::Di), Some(Register::Bp)) => 3,
+            (Some(Register::Si), None) => 4,
+            (Some(Register::Di), None) => 5,
+            (Some(Register::Bp), None) => 6,
+            (Some(Register::Bx), None) => 7,
+            (None, None) => {
+                // [MODRM] disp16, rm 6 with mod 0 means no base register
+                return Ok([vec![Self::format_modrm(0, reg, 6)], Self::immediate(disp.into(), Size::Word)].concat());
+            },
+            _ => return Err(Error::Address16),
+        };
+
+        if disp == 0 && rm != 6 && !symbol {
+            Ok(vec![Self::format_modrm(0, reg, rm)])
+        } else if i8::try_from(disp).is_ok() && !symbol {
+            Ok(vec![Self::format_modrm(1, reg, rm), disp as u8])
+        } else {
+            Ok([vec![Self::format_modrm(2, reg, rm)], Self::immediate(disp.into(), Size::Word)].concat())
+        }
+    }
+
+    fn encode_operand(&self, reg: u8, rm: &Value, n: i32) -> Result<ModRm, Error> {
+        match rm {
+            Value::Register(rm) => Ok(ModRm {
+                prefixes: Vec::new(),
+                bytes: vec![Self::format_modrm(3, reg, Self::index(*rm))],
+                // evex reuses X as the fifth bit of a register operand
+                x: Self::index(*rm) & 0x10 != 0,
+                b: Self::index(*rm) & 0x8 != 0,
+                fixup: None,
+            }),
+            Value::Memory(address) => self.encode_address(reg, address, n),
+            _ => Err(Error::ExpectedOperand("register or memory operand")),
+        }
+    }
+
+    fn flush(&mut self) {
+        if !self.sizing {
+            let section = self.preprocessor.section;
+
+            if self.images.len() <= section {
+                self.images.resize_with(section + 1, Image::default);
+            }
+
+            self.images[section].bytes.append(&mut self.buf);
+            self.images[section].reserved += self.reserved;
+        }
+
+        self.buf.clear();
+        self.reserved = 0;
+    }
+
+    // definitions are only handed to faerie at the end since resumed sections keep adding to them
+    fn define_label(&mut self) -> Result<(), Error> {
+        if !self.label.is_empty() {
+            // faerie gives zero-init definitions an empty section, so reserved space is written out as zeros
+            if !self.sizing && self.is_artifact() {
+                self.definitions.entry(self.label.clone()).or_default().extend(&self.buf);
+            }
+
+            self.flush();
+        }
+
+        Ok(())
+    }
+
+    fn switch_section(&mut self, name: Option<String>, permissions: Permissions) -> Result<(), Error> {
+        self.define_label()?;
+
+        // code between the section directive and the first label only ends up in the image
+        self.flush();
+        self.preprocessor.sections[self.preprocessor.section].definition = std::mem::take(&mut self.label);
+
+        self.preprocessor.enter(name, permissions);
+        self.label = self.preprocessor.sections[self.preprocessor.section].definition.clone();
+
+        Ok(())
+    }
+
+    fn encode_data(&self, value: &Value, size: Size) -> Result<Vec<u8>, Error> {
+        let length = match size {
+            Size::Byte => 1,
+            Size::Word => 2,
+            Size::Dword => 4,
+            _ => 8,
+        };
+
+        match self.constexpr(value)? {
+            // negative integers are sign extended and positive ones zero extended to the unit size
+            Value::Integer(integer) => Ok(integer.to_le_bytes()[..length].to_vec()),
+            // strings are padded with zeros to a multiple of the unit size
+            Value::String(string) => {
+                let mut bytes = string.into_bytes();
+
+                bytes.resize(bytes.len().div_ceil(length) * length, 0);
+
+                Ok(bytes)
+            },
+            _ => Err(Error::ExpectedOperand("integer, string or label in data definition")),
+        }
+    }
+
+    fn encode_jcc(&mut self, opcode: &[u8], label: String) -> Result<(), Error> {
+        if self.label_address(&label).is_some() {
+            let size = if self.bits == 16 { Size::Word } else { Size::Dword };
+            let length = Self::immediate(0, size).len();
+
+            let reloc = match size {
+                Size::Word => elf::R_X86_64_PC16,
+                _ if self.is_extern(&label) => elf::R_X86_64_PLT32,
+                _ => elf::R_X86_64_PC32,
+            };
+
+            self.preprocessor.offset += opcode.len() + length;
+
+            self.buf.extend(&[opcode.to_vec(), vec![0; length]].concat());
+            self.fixup(label, length, 0, true, reloc);
+
+            Ok(())
+        } else {
+            self.at(0, Err(Error::NoSuchLabel(label.to_string())))
+        }
+    }
+
+    fn encode_far_jmp(&mut self, selector: Value, label: String, size: Option<Size>) -> Result<(), Error> {
+        let selector = match self.at(0, self.constexpr(&selector))? {
+            Value::Integer(selector) if (0..=0xffff).contains(&selector) => selector,
+            _ => return self.at(0, Err(Error::ExpectedOperand("16-bit integer as far jump selector"))),
+        };
+
+        let addr = self.at(0, self.label_address(&label).ok_or(Error::NoSuchLabel(label.clone())))? as i64;
+        let size = size.unwrap_or(if self.bits == 16 { Size::Word } else { Size::Dword });
+
+        if self.bits == 64 {
+            return Err(Error::NotInUse64("far jumps to an immediate pointer"));
+        } else if size == Size::Word && addr > 0xffff {
+            return self.at(0, Err(Error::Label16(label.clone())));
+        }
+
+        // EA cd, EA cp
+        let bytes = [self.legacy_prefix(Some(size), 0)?, vec![0xea]].concat();
+
+        self.preprocessor.offset += bytes.len();
+        self.buf.extend(bytes);
+
+        self.emit_immediate(0, &Value::Const(label), addr, size)?;
+        self.emit_immediate(0, &Value::Integer(selector), selector, Size::Word)?;
+
+        Ok(())
+    }
+
+    fn encode_binary_expr(&mut self, lhs: Value, rhs: Value, opcodes: [Opcode; 3]) -> Result<(), Error> {
+        if let Value::Register(rd) = self.at(0, self.constexpr(&lhs))? {
+            match self.at(1, self.constexpr(&rhs))? {
+                Value::Integer(id) => {
+                    let size = self.at(0, Self::size(rd).ok_or(Error::NotGeneralPurpose(rd)))?;
+
+                    if Self::index(rd) == 0 {
+                        // [OPCODE] id
+                        let bytes = [self.legacy_prefix(Some(size), 0)?, vec![opcodes[0].opcode]].concat();
+
+                        self.preprocessor.offset += bytes.len();
+                        self.buf.extend(bytes);
+                    } else {
+                        // [OPCODE] /[REG]
+                        self.encode_legacy(&[opcodes[1].opcode], opcodes[1].reg, &Value::Register(rd), Some(size))?;
+                    }
+
+                    self.emit_immediate(1, &rhs, id, size)?;
+                },
+                Value::Register(id) => {
+                    // [OPCODE] /r
+                    let size = self.operand_size(rd, id)?;
+
+                    self.encode_legacy(&[opcodes[2].opcode], Self::index(id), &Value::Register(rd), Some(size))?;
+                },
+                _ => return self.at(1, Err(Error::ExpectedOperand("register or integer operand"))),
+            }
+
+            Ok(())
+        } else {
+            self.at(0, Err(Error::NotRegister("add")))
+        }
+    }
+
+    // 66 switches between 16 and 32-bit operands, REX.W selects 64-bit operands and is only available in use64
+    fn legacy_prefix(&self, size: Option<Size>, rex: u8) -> Result<Vec<u8>, Error> {
+        let mut bytes: Vec<u8> = Vec::new();
+
+        match (self.bits, size) {
+            (16, Some(Size::Dword)) | (32 | 64, Some(Size::Word)) => bytes.push(0x66),
+            (16 | 32, Some(Size::Qword)) => return Err(Error::Use64Only(String::from("64-bit operands"))),
+            _ => {},
+        }
+
+        let rex = rex | if size == Some(Size::Qword) { 0x8 } else { 0 };
+
+        if rex != 0 {
+            if self.bits != 64 {
+                return Err(Error::Use64Only(String::from("registers r8-r15")));
+            }
+
+            // REX [0100 W R X B]
+            bytes.push(0x40 | rex);
+        }
+
+        Ok(bytes)
+    }
+
+    fn encode_legacy(&mut self, opcode: &[u8], reg: u8, rm: &Value, size: Option<Size>) -> Result<(), Error> {
+        let modrm = self.encode_operand(reg, rm, 1)?;
+        let mut bytes: Vec<u8> = Vec::new();
+
+        bytes.extend(&modrm.prefixes);
+        bytes.extend(self.legacy_prefix(size, ((reg & 0x8) >> 1) | ((modrm.x as u8) << 1) | modrm.b as u8)?);
+        bytes.extend(opcode);
+        bytes.extend(modrm.bytes);
+
+        self.emit_modrm(bytes, modrm.fixup);
+
+        Ok(())
+    }
+
+    fn vector(&self, value: &Value) -> Result<Register, Error> {
+        match self.constexpr(value)? {
+            Value::Register(reg @ (Register::Xmm(_) | Register::Ymm(_) | Register::Zmm(_))) => Ok(reg),
+            _ => Err(Error::ExpectedOperand("xmm, ymm or zmm register")),
+        }
+    }
+
+    fn vector_length(reg: Register) -> u8 {
+        match reg {
+            Register::Ymm(_) => 1,
+            Register::Zmm(_) => 2,
+            _ => 0,
+        }
+    }
+
+    fn unmask(&self, value: &Value) -> Result<(Value, Decorations), Error> {
+        match value {
+            Value::Masked { value, mask, zeroing } => {
+                if mask.is_some_and(|mask| Self::index(mask) == 0) {
+                    return Err(Error::MaskK0);
+                } else if *zeroing && mask.is_none() {
+                    return Err(Error::ZeroingWithoutMask);
+                }
+
+                Ok((self.constexpr(value)?, Decorations {
+                    mask: *mask,
+                    zeroing: *zeroing,
+                    rounding: None,
+                }))
+            },
+            _ => Ok((self.constexpr(value)?, Decorations::default())),
+        }
+    }
+
+    // picks the 2 or 3 byte vex prefix when possible and falls back to evex when the operands need it
+    fn encode_vector(&mut self, op: VectorOpcode, l: u8, reg: Register, vvvv: Option<Register>, rm: &Value, decorations: Decorations) -> Result<(), Error> {
+        let broadcast = match rm {
+            Value::Memory(address) => address.broadcast,
+            _ => None,
+        };
+
+        let extended = [Some(reg), vvvv, if let Value::Register(rm) = rm { Some(*rm) } else { None }]
+            .iter()
+            .flatten()
+            .any(|reg| Self::index(*reg) >= 16);
+
+        let needs_evex = l == 2 || extended || decorations.mask.is_some() || broadcast.is_some() || decorations.rounding.is_some();
+
+        let mut bytes: Vec<u8> = Vec::new();
+        let modrm;
+
+        if needs_evex || !op.vex {
+            let tuple = op.tuple.ok_or(Error::NoEvexForm)?;
+            let length = 16 << l;
+
+            modrm = self.encode_operand(Self::index(reg), rm, tuple.n(length, broadcast.is_some()))?;
+
+            let mut evex = Evex::new(op.map, op.pp, op.w);
+
+            evex.r = Self::index(reg) & 0x8 != 0;
+            evex.r2 = Self::index(reg) & 0x10 != 0;
+            evex.x = modrm.x;
+            evex.b = modrm.b;
+            evex.vvvv = vvvv.map(Self::index).unwrap_or(0);
+            evex.ll = l;
+            evex.z = decorations.zeroing;
+            evex.aaa = decorations.mask.map(Self::index).unwrap_or(0);
+
+            if let Some(count) = broadcast {
+                match tuple {
+                    Tuple::Full(element) if element * count as i32 == length => evex.bcst = true,
+                    Tuple::Full(element) => return Err(Error::Broadcast(length / element)),
+                    _ => return Err(Error::NoBroadcast),
+                }
+            }
+
+            if let Some(rounding) = decorations.rounding {
+                if let Value::Memory(_) = rm {
+                    return Err(Error::RoundingRegisters);
+                }
+
+                // the rounding mode takes the place of the vector length, sae alone leaves it zero
+                evex.bcst = true;
+                evex.ll = match rounding {
+                    Rounding::Nearest | Rounding::Sae => 0,
+                    Rounding::Down => 1,
+                    Rounding::Up => 2,
+                    Rounding::Zero => 3,
+                };
+
+                if l != 2 {
+                    return Err(Error::RoundingZmm);
+                }
+            }
+
+            bytes.extend(&modrm.prefixes);
+
+            bytes.extend(evex.encode());
+        } else {
+            modrm = self.encode_operand(Self::index(reg), rm, 1)?;
+
+            let mut vex = Vex::new(op.map, op.pp, op.w);
+
+            vex.r = Self::index(reg) & 0x8 != 0;
+            vex.x = modrm.x;
+            vex.b = modrm.b;
+            vex.vvvv = vvvv.map(Self::index).unwrap_or(0);
+            vex.l = l == 1;
+
+            bytes.extend(&modrm.prefixes);
+
+            bytes.extend(vex.encode());
+        }
+
+        bytes.push(op.opcode);
+        bytes.extend(modrm.bytes);
+
+        self.emit_modrm(bytes, modrm.fixup);
+
+        Ok(())
+    }
+
+    fn encode_vector_ternary(&mut self, op: VectorOpcode, dest: Value, src1: Value, src2: Value, rounding: Option<Rounding>) -> Result<(), Error> {
+        let (dest, mut decorations) = self.at(0, self.unmask(&dest))?;
+        let dest = self.at(0, self.vector(&dest))?;
+        let src1 = self.at(1, self.vector(&src1))?;
+        let src2 = self.at(2, self.constexpr(&src2))?;
+
+        let width = std::mem::discriminant(&dest);
+
+        if std::mem::discriminant(&src1) != width {
+            return self.at(1, Err(Error::OperandSize(None)));
+        } else if let Value::Register(reg) = src2 {
+            if std::mem::discriminant(&self.at(2, self.vector(&src2))?) != width {
+                return self.at(2, Err(Error::OperandSize(Some(reg.to_string()))));
+            }
+        }
+
+        decorations.rounding = rounding;
+
+        self.encode_vector(op, Self::vector_length(dest), dest, Some(src1), &src2, decorations)
+    }
+
+    fn encode_vector_move(&mut self, load: VectorOpcode, store: VectorOpcode, lhs: Value, rhs: Value) -> Result<(), Error> {
+        let (lhs, decorations) = self.at(0, self.unmask(&lhs))?;
+        let rhs = self.at(1, self.constexpr(&rhs))?;
+
+        match (lhs, rhs) {
+            (dest @ Value::Register(_), rm) => {
+                let dest = self.at(0, self.vector(&dest))?;
+
+                if let Value::Register(_) = rm {
+                    let src = self.at(1, self.vector(&rm))?;
+
+                    if std::mem::discriminant(&src) != std::mem::discriminant(&dest) {
+                        return self.at(1, Err(Error::OperandSize(None)));
+                    }
+
+                    // the store form moves an extended source into modrm.reg so the 2-byte prefix still fits
+                    if store.vex && decorations.mask.is_none() && Self::index(src) & 0x18 == 0x8 && Self::index(dest) < 8 && Self::vector_length(dest) < 2 {
+                        return self.encode_vector(store, Self::vector_length(dest), src, None, &Value::Register(dest), decorations);
+                    }
+                }
+
+                self.encode_vector(load, Self::vector_length(dest), dest, None, &rm, decorations)
+            },
+            (rm @ Value::Memory(_), src) => {
+                let src = self.at(1, self.vector(&src))?;
+
+                if decorations.zeroing {
+                    return self.at(0, Err(Error::ZeroingMemory));
+                }
+
+                self.encode_vector(store, Self::vector_length(src), src, None, &rm, decorations)
+            },
+            _ => Err(Error::ExpectedOperand("register or memory operand")),
+        }
+    }
+
+    fn is_system(&self, value: &Value) -> bool {
+        matches!(self.constexpr(value), Ok(Value::Register(reg)) if reg.is_segment() || matches!(reg, Register::Cr(_) | Register::Dr(_)))
+    }
+
+    fn encode_system_mov(&mut self, lhs: Value, rhs: Value) -> Result<(), Error> {
+        // control and debug registers are always moved as the native register size
+        let native = if self.bits == 64 { Size::Qword } else { Size::Dword };
+
+        match (self.at(0, self.constexpr(&lhs))?, self.at(1, self.constexpr(&rhs))?) {
+            // 0F 22 /r, 0F 23 /r
+            (Value::Register(dest @ (Register::Cr(_) | Register::Dr(_))), Value::Register(src)) if Self::size(src) == Some(native) => {
+                let opcode = if let Register::Cr(_) = dest { 0x22 } else { 0x23 };
+
+                self.encode_legacy(&[0x0f, opcode], Self::index(dest), &Value::Register(src), None)
+            },
+            // 0F 20 /r, 0F 21 /r
+            (Value::Register(dest), Value::Register(src @ (Register::Cr(_) | Register::Dr(_)))) if Self::size(dest) == Some(native) => {
+                let opcode = if let Register::Cr(_) = src { 0x20 } else { 0x21 };
+
+                self.encode_legacy(&[0x0f, opcode], Self::index(src), &Value::Register(dest), None)
+            },
+            (Value::Register(Register::Cr(_) | Register::Dr(_)), _) | (_, Value::Register(Register::Cr(_) | Register::Dr(_))) => {
+                Err(Error::ControlRegister { bits: if self.bits == 64 { 64 } else { 32 }, mode: self.bits })
+            },
+            (Value::Register(Register::Cs), _) => self.at(0, Err(Error::LoadCs)),
+            // 8E /r
+            (Value::Register(dest), rm @ (Value::Register(_) | Value::Memory(_))) if dest.is_segment() => {
+                if let Value::Register(src) = rm {
+                    self.at(1, self.rm(src))?;
+                }
+
+                self.encode_legacy(&[0x8e], Self::index(dest), &rm, None)
+            },
+            // 8C /r
+            (rm @ (Value::Register(_) | Value::Memory(_)), Value::Register(src)) if src.is_segment() => {
+                // a register destination is zero extended to its own size, which needs the operand size prefix or REX.W
+                let size = match rm {
+                    Value::Register(dest) if Self::size(dest) == Some(Size::Byte) => return self.at(0, Err(Error::OperandSize(Some(dest.to_string())))),
+                    Value::Register(dest) => {
+                        self.at(0, self.rm(dest))?;
+                        Self::size(dest)
+                    },
+                    _ => None,
+                };
+
+                self.encode_legacy(&[0x8c], Self::index(src), &rm, size)
+            },
+            _ => Err(Error::InvalidOperands("segment register moves")),
+        }
+    }
+
+    fn encode_system_memory(&mut self, opcode: &[u8], reg: u8, src: Value) -> Result<(), Error> {
+        let result = match self.constexpr(&src) {
+            Ok(src @ Value::Memory(_)) => self.encode_legacy(opcode, reg, &src, None),
+            Ok(_) => Err(Error::ExpectedOperand("memory operand")),
+            Err(err) => Err(err),
+        };
+
+        self.at(0, result)
+    }
+
+    fn fpu(&self, value: &Value) -> Result<u8, Error> {
+        match self.constexpr(value)? {
+            Value::Register(Register::St(index)) => Ok(index),
+            _ => Err(Error::ExpectedOperand("fpu stack register")),
+        }
+    }
+
+    // memory forms are picked by operand size as `(size, opcode, /digit)`, they are always the only operand
+    fn encode_fpu_memory(&mut self, value: &Value, forms: &[(Size, u8, u8)]) -> Result<(), Error> {
+        let value = self.at(0, self.constexpr(value))?;
+
+        if let Value::Memory(address) = &value {
+            let size = match (address.size, forms) {
+                (Some(size), _) => size,
+                (None, [(size, _, _)]) => *size,
+                (None, _) => return self.at(0, Err(Error::SizeNotSpecified)),
+            };
+
+            let (_, opcode, reg) = self.at(0, forms.iter()
+                .find(|(form, _, _)| *form == size)
+                .ok_or(Error::InvalidSize(size)))?;
+            let result = self.encode_legacy(&[*opcode], *reg, &value, None);
+
+            self.at(0, result)
+        } else {
+            self.at(0, Err(Error::ExpectedOperand("memory operand")))
+        }
+    }
+
+    // `reg` is the /digit of the D8 form, the reversed ST(i), ST(0) forms swap fsub/fsubr and fdiv/fdivr
+    fn encode_fpu_arith(&mut self, operands: Vec<Value>, reg: u8, pop: bool) -> Result<(), Error> {
+        let operands = operands.iter().enumerate().map(|(index, operand)| self.at(index, self.constexpr(operand))).collect::<Result<Vec<Value>, _>>()?;
+        let reversed = if reg >= 4 { reg ^ 1 } else { reg };
+
+        let bytes = match (operands.as_slice(), pop) {
+            // DE C0+r+1
+            ([], true) => vec![0xde, 0xc1 + (reversed << 3)],
+            ([memory @ Value::Memory(_)], false) => return self.encode_fpu_memory(memory, &[(Size::Dword, 0xd8, reg), (Size::Qword, 0xdc, reg)]),
+            // D8 C0+r+i
+            ([Value::Register(Register::St(0)), Value::Register(Register::St(index))], false) => vec![0xd8, 0xc0 + (reg << 3) + index],
+            // DC C0+r+i, DE C0+r+i
+            ([Value::Register(Register::St(index)), Value::Register(Register::St(0))], _) => vec![if pop { 0xde } else { 0xdc }, 0xc0 + (reversed << 3) + index],
+            _ => return Err(Error::InvalidOperands("fpu instructions")),
+        };
+
+        self.preprocessor.offset += bytes.len();
+        self.buf.extend(bytes);
+
+        Ok(())
+    }
+
+    fn encode_fpu_stack(&mut self, operands: Vec<Value>, opcode: [u8; 2]) -> Result<(), Error> {
+        let index = match operands.as_slice() {
+            [] => 1,
+            [src] => self.at(0, self.fpu(src))?,
+            [dest, src] if self.at(0, self.fpu(dest))? == 0 => self.at(1, self.fpu(src))?,
+            _ => return Err(Error::InvalidOperands("fpu instructions")),
+        };
+
+        self.buf.extend(&[opcode[0], opcode[1] + index]);
+        self.preprocessor.offset += 2;
+
+        Ok(())
+    }
+
+    fn constexpr(&self, value: &Value) -> Result<Value, Error> {
+        match value {
+            Value::Integer(_) | Value::Register(_) | Value::Memory(_) | Value::String(_) => Ok(value.clone()),
+            Value::Const(ident) => {
+                if let Some(constant) = self.preprocessor.consts.get(ident) {
+                    self.used.borrow_mut().insert(ident.clone());
+
+                    Ok(self.constexpr(constant)?)
+                } else if let Some(addr) = self.label_address(ident) {
+                    Ok(Value::Integer(addr as i64))
+                } else {
+                    Err(Error::NoSuchConstant(ident.clone()))
+                }
+            },
+            Value::Masked { .. } => Err(Error::MaskNotDestination),
+            Value::Rounding(_) => Err(Error::RoundingNotLast),
+        }
+    }
+
+    fn build_inst(&mut self, inst: Inst) -> Result<bool, Error> {
+        match inst {
+            Inst::Label { ident } => {
+                if self.sizing {
+                    self.preprocessor.offsets.insert(ident.clone(), self.preprocessor.offset);
+                    self.homes.insert(ident.clone(), self.preprocessor.section);
+                }
+
+                // labels inside a definition are only offsets into it
+                if !self.preprocessor.parents.contains_key(&ident) {
+                    self.define_label()?;
+
+                    self.label = ident;
+                }
+            },
+            Inst::Push { value } => {
+                if let Value::Integer(id) = self.at(0, self.constexpr(&value))? {
+                    // 68 id, the immediate is sign extended in use64
+                    let size = match self.bits {
+                        16 => Size::Word,
+                        32 => Size::Dword,
+                        _ => Size::Qword,
+                    };
+
+                    self.buf.push(0x68);
+                    self.preprocessor.offset += 1;
+                    self.emit_immediate(0, &value, id, size)?;
+                } else if let Value::Register(rd) = self.constexpr(&value)? {
+                    // FF /6, pushes are 64-bit by default in use64
+                    self.at(0, self.rm(rd))?;
+                    self.encode_legacy(&[0xff], 6, &Value::Register(rd), Self::size(rd).filter(|size| *size != Size::Qword))?;
+                }
+            },
+            Inst::Pop { dest } => {
+                // 58+ rd
+                self.at(0, self.rm(dest))?;
+
+                let bytes = [self.legacy_prefix(Self::size(dest).filter(|size| *size != Size::Qword), (Self::index(dest) & 0x8) >> 3)?, vec![0x58 + (Self::index(dest) & 7)]].concat();
+
+                self.preprocessor.offset += bytes.len();
+                self.buf.extend(bytes);
+            },
+            Inst::Mov { lhs, rhs } if self.is_system(&lhs) || self.is_system(&rhs) => self.encode_system_mov(lhs, rhs)?,
+            Inst::Mov { lhs, rhs } => {
+                if let Value::Register(rd) = self.at(0, self.constexpr(&lhs))? {
+                    self.at(0, self.rm(rd))?;
+
+                    let size = self.at(0, Self::size(rd).ok_or(Error::NotGeneralPurpose(rd)))?;
+
+                    if let Value::Integer(id) = self.at(1, self.constexpr(&rhs))? {
+                        // coff objects only relocate full 64-bit addresses into registers, integers outside the sign extended 32 bits need all 64 as well
+                        let full = size == Size::Qword && if self.is_address(&rhs) { self.preprocessor.format == Format::Ms64Coff } else { i32::try_from(id).is_err() };
+
+                        if size == Size::Qword && !full {
+                            // REX.W C7 /0 id
+                            self.encode_legacy(&[0xc7], 0, &Value::Register(rd), Some(size))?;
+                        } else {
+                            // B8+ rd id, REX.W B8+ rd io
+                            let bytes = [self.legacy_prefix(Some(size), (Self::index(rd) & 0x8) >> 3)?, vec![0xb8 + (Self::index(rd) & 7)]].concat();
+
+                            self.preprocessor.offset += bytes.len();
+                            self.buf.extend(bytes);
+                        }
+
+                        if full {
+                            self.preprocessor.offset += 8;
+                            self.buf.extend(id.to_le_bytes());
+
+                            if let Some(symbol) = self.symbol(&rhs) {
+                                self.fixup(symbol, 8, 0, false, elf::R_X86_64_64);
+                            }
+                        } else {
+                            self.emit_immediate(1, &rhs, id, size)?;
+                        }
+                    } else if let Value::Register(id) = self.constexpr(&rhs)? {
+                        // 89 /r
+                        let size = self.operand_size(rd, id)?;
+
+                        self.encode_legacy(&[0x89], Self::index(id), &Value::Register(rd), Some(size))?;
+                    } else if let Value::Memory(address) = self.constexpr(&rhs)? {
+                        // 8B /r
+                        let size = self.at(1, Self::memory_size(&address, size))?;
+                        let result = self.encode_legacy(&[0x8b], Self::index(rd), &Value::Memory(address), Some(size));
+
+                        self.at(1, result)?;
+                    }
+                } else if let Value::Memory(address) = self.constexpr(&lhs)? {
+                    if let Value::Register(id) = self.at(1, self.constexpr(&rhs))? {
+                        // 89 /r
+                        self.at(1, self.rm(id))?;
+
+                        let size = self.at(1, Self::size(id).ok_or(Error::NotGeneralPurpose(id)))?;
+                        let size = self.at(0, Self::memory_size(&address, size))?;
+                        let result = self.encode_legacy(&[0x89], Self::index(id), &Value::Memory(address), Some(size));
+
+                        self.at(0, result)?;
+                    } else if let (Value::Integer(id), Some(size)) = (self.constexpr(&rhs)?, address.size) {
+                        // C6 /0 ib, C7 /0 id
+                        let opcode = match size {
+                            Size::Byte => 0xc6,
+                            Size::Tword => return self.at(0, Err(Error::OperandSize(None))),
+                            _ => 0xc7,
+                        };
+
+                        let result = self.encode_legacy(&[opcode], 0, &Value::Memory(address), Some(size).filter(|size| *size != Size::Byte));
+
+                        self.at(0, result)?;
+                        self.emit_immediate(1, &rhs, id, size)?;
+                    } else {
+                        return self.at(0, Err(Error::SizeNotSpecified));
+                    }
+                } else {
+                    return self.at(0, Err(Error::NotRegister("mov")));
+                }
+            },
+            Inst::Add { lhs, rhs } => self.encode_binary_expr(lhs, rhs, [Opcode::new(0x05, 0), Opcode::new(0x81, 0), Opcode::new(0x01, 0)])?,
+            Inst::Sub { lhs, rhs } => self.encode_binary_expr(lhs, rhs, [Opcode::new(0x2d, 0), Opcode::new(0x81, 5), Opcode::new(0x29, 0)])?,
+            Inst::Mul { dest } => {
+                // F7 /4
+                self.at(0, self.rm(dest))?;
+                self.encode_legacy(&[0xf7], 4, &Value::Register(dest), Self::size(dest))?;
+            },
+            Inst::Lea { dest, src } => {
+                // 8D /r
+                match (self.at(0, self.constexpr(&dest))?, self.at(1, self.constexpr(&src))?) {
+                    (Value::Register(rd), src @ Value::Memory(_)) => {
+                        self.at(0, self.rm(rd))?;
+
+                        let result = self.encode_legacy(&[0x8d], Self::index(rd), &src, Self::size(rd));
+
+                        self.at(1, result)?;
+                    },
+                    _ => return Err(Error::ExpectedOperand("register and memory operand")),
+                }
+            },
+            Inst::Cmp { lhs, rhs } => {
+                if let Value::Register(rd) = self.at(0, self.constexpr(&lhs))? {
+                    if let Value::Integer(id) = self.at(1, self.constexpr(&rhs))? {
+                        // 81 /7 id
+                        self.at(0, self.rm(rd))?;
+
+                        let size = self.at(0, Self::size(rd).ok_or(Error::NotGeneralPurpose(rd)))?;
+
+                        self.encode_legacy(&[0x81], 7, &Value::Register(rd), Some(size))?;
+                        self.emit_immediate(1, &rhs, id, size)?;
+                    } else if let Value::Register(id) = self.constexpr(&rhs)? {
+                        // 39 /r
+                        let size = self.operand_size(rd, id)?;
+
+                        self.encode_legacy(&[0x39], Self::index(id), &Value::Register(rd), Some(size))?;
+                    }
+                } else {
+                    return self.at(0, Err(Error::NotRegister("cmp")));
+                }
+            },
+            Inst::Jmp { label } => self.encode_jcc(&[0xe9], label)?,
+            // E8 cd
+            Inst::Call { label } => self.encode_jcc(&[0xe8], label)?,
+            Inst::Ret => {
+                self.buf.push(0xc3);
+                self.preprocessor.offset += 1;
+            },
+            Inst::JmpFar { selector, label, size } => self.encode_far_jmp(selector, label, size)?,
+            Inst::Je { label } => self.encode_jcc(&[0x0f, 0x84], label)?,
+            Inst::Jg { label } => self.encode_jcc(&[0x0f, 0x8f], label)?,
+            Inst::Jb { label } => self.encode_jcc(&[0x0f, 0x82], label)?,
+            Inst::Syscall => {
+                self.buf.extend(&[0x0f, 0x05]);
+                self.preprocessor.offset += 2;
+            },
+            Inst::Vaddps { dest, src1, src2, rounding } => {
+                if rounding == Some(Rounding::Sae) {
+                    return Err(Error::RoundingNotSae("vaddps"));
+                }
+
+                self.encode_vector_ternary(VectorOpcode::both(0x58, Map::Of, Prefix::None, false, Tuple::Full(4)), dest, src1, src2, rounding)?
+            },
+            Inst::Vpxor { dest, src1, src2 } => self.encode_vector_ternary(VectorOpcode::vex(0xef, Map::Of, Prefix::P66, false), dest, src1, src2, None)?,
+            Inst::Vpshufb { dest, src1, src2 } => self.encode_vector_ternary(VectorOpcode::both(0x00, Map::Of38, Prefix::P66, false, Tuple::FullMem), dest, src1, src2, None)?,
+            Inst::Vfmadd231ps { dest, src1, src2, rounding } => {
+                if rounding == Some(Rounding::Sae) {
+                    return Err(Error::RoundingNotSae("vfmadd231ps"));
+                }
+
+                self.encode_vector_ternary(VectorOpcode::both(0xb8, Map::Of38, Prefix::P66, false, Tuple::Full(4)), dest, src1, src2, rounding)?
+            },
+            Inst::Vmovdqu { lhs, rhs } => {
+                // VEX.F3.0F 6F /r, VEX.F3.0F 7F /r
+                self.encode_vector_move(VectorOpcode::vex(0x6f, Map::Of, Prefix::Pf3, false), VectorOpcode::vex(0x7f, Map::Of, Prefix::Pf3, false), lhs, rhs)?
+            },
+            Inst::Vbroadcastss { dest, src } => {
+                // VEX.66.0F38.W0 18 /r
+                let (dest, decorations) = self.at(0, self.unmask(&dest))?;
+                let dest = self.at(0, self.vector(&dest))?;
+                let src = self.at(1, self.constexpr(&src))?;
+
+                if let Value::Register(reg) = src {
+                    if !matches!(reg, Register::Xmm(_)) {
+                        return self.at(1, Err(Error::BroadcastSource(reg)));
+                    }
+                }
+
+                self.encode_vector(VectorOpcode::both(0x18, Map::Of38, Prefix::P66, false, Tuple::Scalar(4)), Self::vector_length(dest), dest, None, &src, decorations)?;
+            },
+            Inst::Vzeroupper => {
+                // VEX.128.0F 77
+                self.buf.extend(&[Vex::new(Map::Of, Prefix::None, false).encode(), vec![0x77]].concat());
+                self.preprocessor.offset += 3;
+            },
+            Inst::Vmaxps { dest, src1, src2, rounding } => {
+                if rounding.is_some_and(|rounding| rounding != Rounding::Sae) {
+                    return Err(Error::SaeOnly("vmaxps"));
+                }
+
+                self.encode_vector_ternary(VectorOpcode::both(0x5f, Map::Of, Prefix::None, false, Tuple::Full(4)), dest, src1, src2, rounding)?
+            },
+            Inst::Vpxord { dest, src1, src2 } => self.encode_vector_ternary(VectorOpcode::evex(0xef, Map::Of, Prefix::P66, false, Tuple::Full(4)), dest, src1, src2, None)?,
+            Inst::Vpxorq { dest, src1, src2 } => self.encode_vector_ternary(VectorOpcode::evex(0xef, Map::Of, Prefix::P66, true, Tuple::Full(8)), dest, src1, src2, None)?,
+            Inst::Vpcmpeqd { dest, src1, src2 } => {
+                let (mask, decorations) = self.at(0, self.unmask(&dest))?;
+
+                if let Value::Register(reg @ Register::K(_)) = mask {
+                    // EVEX.66.0F.W0 76 /r
+                    let src1 = self.at(1, self.vector(&src1))?;
+                    let src2 = self.at(2, self.constexpr(&src2))?;
+
+                    if decorations.zeroing {
+                        return self.at(0, Err(Error::ZeroingMask));
+                    }
+
+                    self.encode_vector(VectorOpcode::evex(0x76, Map::Of, Prefix::P66, false, Tuple::Full(4)), Self::vector_length(src1), reg, Some(src1), &src2, decorations)?;
+                } else {
+                    // VEX.66.0F 76 /r
+                    self.encode_vector_ternary(VectorOpcode::vex(0x76, Map::Of, Prefix::P66, false), dest, src1, src2, None)?;
+                }
+            },
+            Inst::Vmovdqu32 { lhs, rhs } => {
+                // EVEX.F3.0F.W0 6F /r, EVEX.F3.0F.W0 7F /r
+                self.encode_vector_move(VectorOpcode::evex(0x6f, Map::Of, Prefix::Pf3, false, Tuple::FullMem), VectorOpcode::evex(0x7f, Map::Of, Prefix::Pf3, false, Tuple::FullMem), lhs, rhs)?
+            },
+            Inst::Vmovdqu64 { lhs, rhs } => {
+                // EVEX.F3.0F.W1 6F /r, EVEX.F3.0F.W1 7F /r
+                self.encode_vector_move(VectorOpcode::evex(0x6f, Map::Of, Prefix::Pf3, true, Tuple::FullMem), VectorOpcode::evex(0x7f, Map::Of, Prefix::Pf3, true, Tuple::FullMem), lhs, rhs)?
+            },
+            Inst::Kmovw { lhs, rhs } => {
+                // VEX.L0.0F.W0 90 /r, 91 /r, 92 /r, 93 /r
+                let opcode = |opcode| VectorOpcode::vex(opcode, Map::Of, Prefix::None, false);
+
+                match (self.at(0, self.constexpr(&lhs))?, self.at(1, self.constexpr(&rhs))?) {
+                    (Value::Register(dest @ Register::K(_)), rm @ (Value::Register(Register::K(_)) | Value::Memory(_))) => {
+                        self.encode_vector(opcode(0x90), 0, dest, None, &rm, Decorations::default())?
+                    },
+                    (rm @ Value::Memory(_), Value::Register(src @ Register::K(_))) => {
+                        self.encode_vector(opcode(0x91), 0, src, None, &rm, Decorations::default())?
+                    },
+                    (Value::Register(dest @ Register::K(_)), Value::Register(src)) if src.is_gpr32() => {
+                        self.encode_vector(opcode(0x92), 0, dest, None, &Value::Register(src), Decorations::default())?
+                    },
+                    (Value::Register(dest), Value::Register(src @ Register::K(_))) if dest.is_gpr32() => {
+                        self.encode_vector(opcode(0x93), 0, dest, None, &Value::Register(src), Decorations::default())?
+                    },
+                    _ => return Err(Error::InvalidOperands("`kmovw`")),
+                }
+            },
+            Inst::Fld { src } => {
+                if let Ok(index) = self.fpu(&src) {
+                    // D9 C0+i
+                    self.buf.extend(&[0xd9, 0xc0 + index]);
+                    self.preprocessor.offset += 2;
+                } else {
+                    // D9 /0, DD /0, DB /5
+                    self.encode_fpu_memory(&src, &[(Size::Dword, 0xd9, 0), (Size::Qword, 0xdd, 0), (Size::Tword, 0xdb, 5)])?;
+                }
+            },
+            Inst::Fst { dest } => {
+                if let Ok(index) = self.fpu(&dest) {
+                    // DD D0+i
+                    self.buf.extend(&[0xdd, 0xd0 + index]);
+                    self.preprocessor.offset += 2;
+                } else {
+                    // D9 /2, DD /2
+                    self.encode_fpu_memory(&dest, &[(Size::Dword, 0xd9, 2), (Size::Qword, 0xdd, 2)])?;
+                }
+            },
+            Inst::Fstp { dest } => {
+                if let Ok(index) = self.fpu(&dest) {
+                    // DD D8+i
+                    self.buf.extend(&[0xdd, 0xd8 + index]);
+                    self.preprocessor.offset += 2;
+                } else {
+                    // D9 /3, DD /3, DB /7
+                    self.encode_fpu_memory(&dest, &[(Size::Dword, 0xd9, 3), (Size::Qword, 0xdd, 3), (Size::Tword, 0xdb, 7)])?;
+                }
+            },
+            // DF /0, DB /0, DF /5
+            Inst::Fild { src } => self.encode_fpu_memory(&src, &[(Size::Word, 0xdf, 0), (Size::Dword, 0xdb, 0), (Size::Qword, 0xdf, 5)])?,
+            // DF /3, DB /3, DF /7
+            Inst::Fistp { dest } => self.encode_fpu_memory(&dest, &[(Size::Word, 0xdf, 3), (Size::Dword, 0xdb, 3), (Size::Qword, 0xdf, 7)])?,
+            Inst::Fadd { operands } => self.encode_fpu_arith(operands, 0, false)?,
+            Inst::Faddp { operands } => self.encode_fpu_arith(operands, 0, true)?,
+            Inst::Fmul { operands } => self.encode_fpu_arith(operands, 1, false)?,
+            Inst::Fmulp { operands } => self.encode_fpu_arith(operands, 1, true)?,
+            Inst::Fsub { operands } => self.encode_fpu_arith(operands, 4, false)?,
+            Inst::Fsubp { operands } => self.encode_fpu_arith(operands, 4, true)?,
+            Inst::Fdiv { operands } => self.encode_fpu_arith(operands, 6, false)?,
+            Inst::Fdivp { operands } => self.encode_fpu_arith(operands, 6, true)?,
+            // D9 C8+i
+            Inst::Fxch { operands } => self.encode_fpu_stack(operands, [0xd9, 0xc8])?,
+            // DB F0+i
+            Inst::Fcomi { operands } => self.encode_fpu_stack(operands, [0xdb, 0xf0])?,
+            Inst::Fsqrt => {
+                self.buf.extend(&[0xd9, 0xfa]);
+                self.preprocessor.offset += 2;
+            },
+            Inst::Fsin => {
+                self.buf.extend(&[0xd9, 0xfe]);
+                self.preprocessor.offset += 2;
+            },
+            Inst::Fcos => {
+                self.buf.extend(&[0xd9, 0xff]);
+                self.preprocessor.offset += 2;
+            },
+            Inst::Finit => {
+                // 9B DB E3, fwait followed by fninit
+                self.buf.extend(&[0x9b, 0xdb, 0xe3]);
+                self.preprocessor.offset += 3;
+            },
+            // D9 /5
+            Inst::Fldcw { src } => self.encode_fpu_memory(&src, &[(Size::Word, 0xd9, 5)])?,
+            // D9 /7
+            Inst::Fnstcw { dest } => self.encode_fpu_memory(&dest, &[(Size::Word, 0xd9, 7)])?,
+            // 0F 01 /2
+            Inst::Lgdt { src } => self.encode_system_memory(&[0x0f, 0x01], 2, src)?,
+            // 0F 01 /3
+            Inst::Lidt { src } => self.encode_system_memory(&[0x0f, 0x01], 3, src)?,
+            // 0F 01 /7
+            Inst::Invlpg { src } => self.encode_system_memory(&[0x0f, 0x01], 7, src)?,
+            Inst::Ltr { src } => {
+                // 0F 00 /3
+                let src = self.at(0, self.constexpr(&src))?;
+
+                if let Value::Register(reg) = src {
+                    self.at(0, self.rm(reg))?;
+                }
+
+                let result = self.encode_legacy(&[0x0f, 0x00], 3, &src, None);
+
+                self.at(0, result)?;
+            },
+            Inst::Wrmsr => {
+                self.buf.extend(&[0x0f, 0x30]);
+                self.preprocessor.offset += 2;
+            },
+            Inst::Rdmsr => {
+                self.buf.extend(&[0x0f, 0x32]);
+                self.preprocessor.offset += 2;
+            },
+            Inst::Iretq | Inst::Swapgs if self.bits != 64 => return Err(Error::Use64Only(String::from("`iretq` and `swapgs`"))),
+            Inst::Iretq => {
+                // REX.W CF
+                self.buf.extend(&[0x48, 0xcf]);
+                self.preprocessor.offset += 2;
+            },
+            Inst::Swapgs => {
+                self.buf.extend(&[0x0f, 0x01, 0xf8]);
+                self.preprocessor.offset += 3;
+            },
+            Inst::Use { bits } => self.bits = bits,
+            Inst::Format { .. } | Inst::Entry { .. } | Inst::Public { .. } | Inst::Extrn { .. } | Inst::Size { .. } | Inst::Needed { .. } | Inst::Import { .. } => {},
+            Inst::Align { value } => {
+                let align = self.at(0, self.constexpr(&value).and_then(|value| self.preprocessor.alignment(&value)))?;
+                let permissions = self.preprocessor.sections[self.preprocessor.section].permissions;
+
+                // symbols in object files are placed at a multiple of their alignment so padding is relative to them
+                let start = if self.is_artifact() { self.label_address(&self.label).unwrap_or(0) } else { 0 };
+                let padding = (align - (self.preprocessor.offset - start) % align) % align;
+
+                // code is padded with nops
+                if permissions.executable {
+                    self.buf.resize(self.buf.len() + padding, 0x90);
+                } else {
+                    self.buf.resize(self.buf.len() + padding, 0);
+                    self.reserved += padding;
+                }
+
+                self.preprocessor.offset += padding;
+
+                // coff sections are aligned to the largest alignment in them
+                let section = &mut self.preprocessor.sections[self.preprocessor.section];
+
+                section.align = section.align.max(align);
+            },
+            Inst::Org { origin } => {
+                if self.preprocessor.format != Format::Binary {
+                    return Err(Error::Org);
+                }
+
+                match self.at(0, self.constexpr(&origin))? {
+                    Value::Integer(origin) => self.preprocessor.offset = origin as u64 as usize,
+                    _ => return self.at(0, Err(Error::ExpectedOperand("integer as origin"))),
+                }
+
+                // flat binaries are loaded at the origin they start with
+                if self.buf.is_empty() && self.images.iter().all(|image| image.bytes.is_empty()) {
+                    self.load = self.preprocessor.offset;
+                }
+            },
+            Inst::Section { name, permissions } => self.switch_section(Some(name), permissions)?,
+            Inst::Segment { permissions } => self.switch_section(None, permissions)?,
+            Inst::Data { label, size, values } => {
+                if let Some(ident) = label {
+                    self.build_inst(Inst::Label { ident })?;
+                }
+
+                for (index, value) in values.into_iter().enumerate() {
+                    let bytes = self.at(index, self.encode_data(&value, size))?;
+
+                    if let Value::Integer(integer) = self.constexpr(&value)? {
+                        self.warn_truncated(index, &value, integer, size);
+                    }
+
+                    let length = bytes.len();
+
+                    self.preprocessor.offset += length;
+                    self.buf.extend(bytes);
+
+                    if let Some(symbol) = self.symbol(&value) {
+                        let reloc = if size == Size::Qword { elf::R_X86_64_64 } else { Self::absolute(size) };
+
+                        self.fixup(symbol, length, 0, false, reloc);
+                    }
+                }
+            },
+            Inst::Reserve { label, size, count } => {
+                if let Some(ident) = label {
+                    self.build_inst(Inst::Label { ident })?;
+                }
+
+                let count = match self.at(0, self.constexpr(&count))? {
+                    Value::Integer(count) if count >= 0 => count as usize,
+                    _ => return self.at(0, Err(Error::ExpectedOperand("positive integer as reserve count"))),
+                };
+
+                let length = count * self.encode_data(&Value::Integer(0), size)?.len();
+
+                self.buf.resize(self.buf.len() + length, 0);
+                self.reserved += length;
+                self.preprocessor.offset += length;
+            },
+            Inst::Eof => {
+                self.define_label()?;
+
+                // code before the first label is never defined as a symbol but still belongs in the image
+                self.flush();
+
+                for (label, bytes) in std::mem::take(&mut self.definitions) {
+                    self.obj.define(label, bytes)?;
+                }
+
+                return Ok(true);
+            },
+            Inst::ConstExpr(ConstExpr::Call { ident, args }) => {
+                self.used.borrow_mut().insert(ident.clone());
+
+                let clone = self.preprocessor.macros.clone();
+                // calls start with the name of the macro
+                let name = Span { end: self.span.start + ident.chars().count(), ..self.span.clone() };
+                let macro_ = clone.get(&ident).ok_or(Error::NoSuchMacro(ident.clone())).inspect_err(|_| self.span = name)?;
+
+                if args.len() != macro_.args.len() {
+                    return Err(Error::ArgumentCount { expected: macro_.args.len(), got: args.len() });
+                }
+
+                let constants = self.preprocessor.consts.clone();
+                {
+                    for (index, arg) in args.iter().enumerate() {
+                        self.preprocessor.consts.insert(macro_.args[index].clone(), arg.clone());
+                    }
+
+                    let (span, operands) = (self.span.clone(), self.operands.clone());
+
+                    // errors in the body are reported with every call they were expanded from
+                    self.calls.push((span.clone(), ident.clone()));
+
+                    for spanned in &macro_.body {
+                        self.build_line(spanned.clone())?;
+                    }
+
+                    self.calls.pop();
+                    self.span = span;
+                    self.operands = operands;
+                }
+
+                self.preprocessor.consts = constants;
+            },
+            Inst::ConstExpr(_) => {},
+        }
+
+        self.resolve_fixups()?;
+
+        Ok(false)
+    }
+
+    // errors are reported with the macros they were expanded from and the next line is built, `Err` only stops at the error limit
+    fn build_line(&mut self, spanned: Spanned) -> Result<bool, Error> {
+        let eof = spanned.inst == Inst::Eof;
+        let depth = self.calls.len();
+
+        match self.build_spanned(spanned) {
+            Ok(eof) => Ok(eof),
+            Err(err) => {
+                self.diagnostics.report(self.span.clone(), err, self.notes())?;
+                self.calls.truncate(depth);
+
+                Ok(eof)
+            },
+        }
+    }
+
+    // the line of every instruction in code goes into the debug info, later instructions at the same address replace it
+    fn build_spanned(&mut self, Spanned { span, operands, inst }: Spanned) -> Result<bool, Error> {
+        let section = self.preprocessor.section;
+        let address = self.preprocessor.offset as u64;
+        let line = span.line;
+
+        self.span = span;
+        self.operands = operands;
+
+        if !self.sizing {
+            self.reachable(&inst);
+        }
+
+        if !self.sizing && self.preprocessor.sections[section].permissions.executable {
+            if self.rows.last().is_some_and(|(last, row)| *last == section && row.address == address) {
+                self.rows.pop();
+            }
+
+            self.rows.push((section, dwarf::Row { address, line }));
+        }
+
+        // macro definitions and the end of the file have no place in the listing, calls list their expansion under them
+        if self.sizing || matches!(inst, Inst::ConstExpr(ConstExpr::Macro { .. }) | Inst::Eof) {
+            return self.build_inst(inst);
+        }
+
+        let call = matches!(inst, Inst::ConstExpr(ConstExpr::Call { .. }));
+        let index = self.listing.len();
+        let emitted = self.emitted();
+
+        self.listing.push(listing::Entry { address: address as usize, bytes: Vec::new(), line, depth: self.calls.len() });
+
+        let eof = self.build_inst(inst)?;
+
+        // labels flush the buffer before anything is appended so the bytes of an instruction are always at the end of it
+        if !call {
+            let length = (self.emitted() - emitted).min(self.buf.len());
+
+            self.listing[index].bytes = self.buf[self.buf.len() - length..].to_vec();
+        }
+
+        Ok(eof)
+    }
+
+    fn warn(&mut self, lint: Lint, span: Span, message: String, mut notes: Vec<(Span, String)>) {
+        notes.extend(self.notes());
+
+        self.diagnostics.warn(lint, span, message, notes);
+    }
+
+    // code after a jump is only reachable through a label, data and directives after it are fine
+    fn reachable(&mut self, inst: &Inst) {
+        match inst {
+            Inst::Label { .. } | Inst::Data { label: Some(_), .. } | Inst::Reserve { label: Some(_), .. } | Inst::Section { .. } | Inst::Segment { .. } => self.jump = None,
+            Inst::Data { .. } | Inst::Reserve { .. } | Inst::Align { .. } | Inst::Org { .. } | Inst::Use { .. } | Inst::ConstExpr(_) | Inst::Eof => {},
+            Inst::Format { .. } | Inst::Entry { .. } | Inst::Public { .. } | Inst::Extrn { .. } | Inst::Size { .. } | Inst::Needed { .. } | Inst::Import { .. } => {},
+            _ => {
+                if let Some(jump) = self.jump.take() {
+                    self.warn(Lint::UnreachableCode, self.span.clone(), String::from("unreachable code after `jmp`"), vec![(jump, String::from("any code after this jump is unreachable"))]);
+                }
+
+                if matches!(inst, Inst::Jmp { .. } | Inst::JmpFar { .. }) {
+                    self.jump = Some(self.span.clone());
+                }
+            },
+        }
+    }
+
+    // labels the entry point or other files refer to are used even if nothing in this file refers to them
+    fn lint_unused(&mut self) {
+        let used = self.used.borrow().clone();
+        let entry = self.entry();
+
+        let mut unused: Vec<(Lint, &str, String)> = Vec::new();
+
+        unused.extend(self.homes.keys().filter(|label| **label != entry && !self.preprocessor.publics.contains(label)).map(|label| (Lint::UnusedLabel, "label", label.clone())));
+        unused.extend(self.preprocessor.consts.keys().map(|constant| (Lint::UnusedConstant, "constant", constant.clone())));
+        unused.extend(self.preprocessor.macros.keys().map(|macro_| (Lint::UnusedMacro, "macro", macro_.clone())));
+
+        for (lint, kind, ident) in unused.into_iter().filter(|(_, _, ident)| !used.contains(ident)) {
+            if let Some(span) = self.preprocessor.spans.get(&ident).cloned() {
+                self.diagnostics.warn(lint, span, format!("{} `{}` is never used", kind, ident), Vec::new());
+            }
+        }
+    }
+
+    // arguments are constants while the macro is expanded and hide any constant with their name
+    fn lint_shadowed(&mut self) {
+        for (ident, macro_) in &self.preprocessor.macros {
+            for arg in macro_.args.iter().filter(|arg| self.preprocessor.consts.contains_key(*arg)) {
+                let notes = vec![(self.preprocessor.spans[arg].clone(), String::from("constant defined here"))];
+
+                self.diagnostics.warn(Lint::ShadowedConstant, self.preprocessor.spans[ident].clone(), format!("argument `{}` of macro `{}` shadows the constant `{}`", arg, ident, arg), notes);
+            }
+        }
+    }
+
+    // the macros an error was expanded from, innermost first
+    pub fn notes(&self) -> Vec<(Span, String)> {
+        let mut notes: Vec<(Span, String)> = Vec::new();
+
+        for (span, ident) in self.calls.iter().rev() {
+            if let Some(definition) = self.preprocessor.spans.get(ident) {
+                notes.push((definition.clone(), format!("macro `{}` defined here", ident)));
+            }
+
+            notes.push((span.clone(), String::from("called from here")));
+        }
+
+        notes
+    }
+
+    // every byte appended to the buffer so far, including the ones flushed to images
+    fn emitted(&self) -> usize {
+        self.images.iter().map(|image| image.bytes.len()).sum::<usize>() + self.buf.len()
+    }
+
+    fn assemble(&mut self) -> Result<(), Error> {
+        self.bits = 64;
+        self.label = self.preprocessor.sections[self.preprocessor.section].definition.clone();
+
+        loop {
+            // lines that dont parse were reported while scanning
+            if let Ok(Some(spanned)) = self.parser.next_inst() {
+                if self.build_line(spanned)? {
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // every section of code is a sequence of rows for `.debug_line`
+    fn debug_info(&self) -> Result<(dwarf::Sections, Vec<dwarf::Relocation>), Error> {
+        let mut sequences: Vec<dwarf::Sequence> = Vec::new();
+
+        for (index, section) in self.preprocessor.sections.iter().enumerate().filter(|(_, section)| section.permissions.executable && section.offset > section.start) {
+            sequences.push(dwarf::Sequence {
+                section: index,
+                start: section.start as u64,
+                end: section.offset as u64,
+                rows: self.rows.iter().filter(|(section, _)| *section == index).map(|(_, row)| dwarf::Row { address: row.address, line: row.line }).collect(),
+            });
+        }
+
+        let directory = std::env::current_dir()?;
+
+        Ok(dwarf::build(&self.file, &directory.to_string_lossy(), &sequences))
+    }
+
+    // labels are sized up to the next label in their section unless `size` says otherwise
+    fn symbol_size(&self, ident: &str) -> Result<usize, Error> {
+        let section = self.homes[ident];
+        let address = self.preprocessor.offsets[ident];
+        let next = self.homes.iter()
+            .filter(|(other, home)| **home == section && self.preprocessor.offsets[*other] > address)
+            .map(|(other, _)| self.preprocessor.offsets[other])
+            .fold(self.preprocessor.sections[section].offset, usize::min);
+
+        match self.preprocessor.sizes.get(ident).map(|value| self.constexpr(value)).transpose()? {
+            Some(Value::Integer(size)) if size >= 0 => Ok(size as usize),
+            Some(_) => Err(Error::SymbolSize(ident.to_string())),
+            None => Ok(next - address),
+        }
+    }
+
+    fn symbols(&self) -> Result<Vec<map::Symbol>, Error> {
+        let mut symbols: Vec<map::Symbol> = Vec::new();
+
+        for (ident, section) in &self.homes {
+            let address = self.preprocessor.offsets[ident];
+            let size = self.symbol_size(ident)?;
+            let section = &self.preprocessor.sections[*section];
+
+            symbols.push(map::Symbol {
+                name: ident.clone(),
+                kind: if section.permissions.executable { "code" } else { "data" },
+                section: Some(if section.name.is_empty() { format!("segment {:#x}", section.start) } else { section.name.clone() }),
+                address: Some(address),
+                size: Some(size),
+                span: self.preprocessor.spans.get(ident).cloned().unwrap_or_else(|| self.span.clone()),
+            });
+        }
+
+        for (index, ident) in self.preprocessor.externs.iter().enumerate() {
+            symbols.push(map::Symbol {
+                name: ident.clone(),
+                kind: "extern",
+                section: None,
+                address: self.has_stubs().then_some(self.plt + 8 * index),
+                size: None,
+                span: self.preprocessor.spans.get(ident).cloned().unwrap_or_else(|| self.span.clone()),
+            });
+        }
+
+        for (ident, kind) in self.preprocessor.consts.keys().map(|ident| (ident, "constant")).chain(self.preprocessor.macros.keys().map(|ident| (ident, "macro"))) {
+            symbols.push(map::Symbol {
+                name: ident.clone(),
+                kind,
+                section: None,
+                address: None,
+                size: None,
+                span: self.preprocessor.spans.get(ident).cloned().unwrap_or_else(|| self.span.clone()),
+            });
+        }
+
+        // constants and macros have no address and go after everything else in the order they were defined
+        symbols.sort_by_key(|symbol| (symbol.address.is_none(), symbol.address, symbol.span.line));
+
+        Ok(symbols)
+    }
+
+    // scanning happens before anything is built since the format decides what faerie writes
+    fn preprocess(&mut self) -> Result<(), Error> {
+        self.preprocessor.preprocess(&mut self.parser, &mut self.diagnostics)?;
+        self.parser.lexer.rewind()?;
+
+        // faerie picks the object format from the target, mach-o symbols get their leading underscore from it
+        let target = match self.preprocessor.format {
+            Format::MachO64 => triple!("x86_64-apple-darwin"),
+            _ => triple!("x86_64-unknown-unknown-unknown-elf"),
+        };
+
+        self.obj = ArtifactBuilder::new(target)
+            .name(self.file.clone())
+            .finish();
+
+        Ok(())
+    }
+
+    // the next pass would only report the same errors again
+    fn check(&self) -> Result<(), Error> {
+        match self.diagnostics.errors.len() {
+            0 => Ok(()),
+            errors => Err(Error::Reported(errors)),
+        }
+    }
+
+    fn build(&mut self) -> Result<(), Error> {
+        self.preprocess()?;
+        self.lint_shadowed();
+
+        if self.is_artifact() {
+            self.obj.declarations(self.preprocessor.labels.iter().cloned())?;
+        }
+
+        // the sizing pass runs the encoder once to find the address of every label
+        self.sizing = true;
+        self.assemble()?;
+        self.check()?;
+
+        // import stubs go on the page after the image and their slots in the dynamic segment on the page after that
+        if self.has_stubs() {
+            let end = self.preprocessor.sections.iter().map(|section| section.offset).fold(self.preprocessor.offset, usize::max);
+
+            self.plt = end.div_ceil(0x1000) * 0x1000;
+            self.got = (self.plt + 8 * self.preprocessor.externs.len()).div_ceil(0x1000) * 0x1000;
+        }
+
+        self.parser.lexer.rewind()?;
+        self.preprocessor.reset();
+        self.buf.clear();
+        self.reserved = 0;
+        self.load = self.preprocessor.base();
+
+        self.sizing = false;
+        self.assemble()?;
+        self.check()?;
+
+        self.preprocessor.sections[self.preprocessor.section].offset = self.preprocessor.offset;
+
+        self.lint_unused();
+        self.check()
+    }
+
+    fn flags(permissions: Permissions) -> u32 {
+        elf::PF_R | if permissions.writeable { elf::PF_W } else { 0 } | if permissions.executable { elf::PF_X } else { 0 }
+    }
+
+    // segments are named after what they hold
+    fn segment_name(permissions: Permissions, image: &Image) -> &'static str {
+        match permissions {
+            Permissions { executable: true, .. } => ".text",
+            Permissions { writeable: true, .. } if image.is_bss() => ".bss",
+            Permissions { writeable: true, .. } => ".data",
+            _ => ".rodata",
+        }
+    }
+
+    fn segments(&self) -> Vec<Segment> {
+        let mut segments = Vec::new();
+
+        for (section, image) in self.preprocessor.sections.iter().zip(&self.images).filter(|(_, image)| !image.bytes.is_empty()) {
+            segments.push(Segment {
+                name: String::from(Self::segment_name(section.permissions, image)),
+                addr: section.start as u64,
+                data: if image.is_bss() { Vec::new() } else { image.bytes.clone() },
+                size: image.bytes.len() as u64,
+                flags: Self::flags(section.permissions),
+            });
+        }
+
+        segments
+    }
+
+    // [FF 25 disp32] jumps through the slot of the import, padded to 8 bytes with int3
+    fn stubs(&self, slot: impl Fn(usize) -> usize) -> Segment {
+        let data: Vec<u8> = (0..self.preprocessor.externs.len())
+            .flat_map(|index| {
+                let disp = slot(index) as i32 - (self.plt + 8 * index + 6) as i32;
+
+                [vec![0xff, 0x25], Self::to_bytes(disp), vec![0xcc, 0xcc]].concat()
+            })
+            .collect();
+
+        Segment {
+            name: String::from(".plt"),
+            addr: self.plt as u64,
+            size: data.len() as u64,
+            data,
+            flags: elf::PF_R | elf::PF_X,
+        }
+    }
+
+    // the linker script places every segment by its own name, later ones with the same permissions get their index
+    fn section_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        let mut taken: Vec<String> = Vec::new();
+
+        for (index, section) in self.preprocessor.sections.iter().enumerate() {
+            let name = match self.images.get(index) {
+                Some(image) if section.name.is_empty() => String::from(Self::segment_name(section.permissions, image)),
+                _ => section.name.clone(),
+            };
+
+            // empty sections are left out of the script so they dont take a name
+            let name = if taken.contains(&name) { format!("{}.{}", name, index) } else { name };
+
+            if self.images.get(index).is_some_and(|image| !image.bytes.is_empty()) {
+                taken.push(name.clone());
+            }
+
+            names.push(name);
+        }
+
+        names
+    }
+
+    // sections keep their names and every label is a symbol, executables linked by `ld` have a section for every segment
+    fn object(&mut self) -> Result<object::Object, Error> {
+        let names = self.section_names();
+        let sections = self.preprocessor.sections.iter().zip(names).enumerate()
+            .map(|(index, (section, name))| {
+                let image = self.images.get(index);
+
+                object::Section {
+                    name,
+                    data: image.filter(|image| !image.is_bss()).map_or(Vec::new(), |image| image.bytes.clone()),
+                    size: image.map_or(0, |image| image.bytes.len() as u64),
+                    flags: Self::flags(section.permissions),
+                    align: section.align as u64,
+                }
+            })
+            .collect();
+
+        let mut symbols = Vec::new();
+
+        for (label, _) in self.preprocessor.labels.iter().filter(|(label, _)| !self.is_extern(label)) {
+            let home = self.homes.get(label).copied();
+
+            // the linker looks for the entry point among the global symbols
+            symbols.push(object::Symbol {
+                name: label.clone(),
+                section: home,
+                value: (self.preprocessor.offsets.get(label).copied().unwrap_or(0) - home.map_or(0, |home| self.preprocessor.sections[home].start)) as u64,
+                size: self.symbol_size(label)? as u64,
+                function: home.is_some_and(|section| self.preprocessor.sections[section].permissions.executable),
+                public: self.preprocessor.publics.contains(label) || (*label == self.entry() && (self.preprocessor.format.is_image() || self.preprocessor.entry.is_some())),
+            });
+        }
+
+        symbols.extend(self.preprocessor.externs.iter().map(|extrn| object::Symbol {
+            name: extrn.clone(),
+            section: None,
+            value: 0,
+            size: 0,
+            function: true,
+            public: true,
+        }));
+
+        Ok(object::Object {
+            sections,
+            symbols,
+            relocations: std::mem::take(&mut self.objects),
+        })
+    }
+
+    // debug sections are not loaded, in objects their addresses are relocated against the sections of code
+    fn attach_debug(&self, object: &mut object::Object) -> Result<(), Error> {
+        let (sections, relocations) = self.debug_info()?;
+        let start = object.sections.len();
+
+        let index = |name: &str| start + sections.iter().position(|(other, _)| other == name).unwrap_or(0);
+
+        // executables linked by `ld` already have the final addresses of their code
+        for relocation in relocations.into_iter().filter(|_| self.preprocessor.format.is_object()) {
+            object.relocations.push(object::Relocation {
+                section: index(relocation.section),
+                at: relocation.at,
+                target: object::Target::Section(match relocation.target {
+                    dwarf::Target::Code(section) => section,
+                    dwarf::Target::Debug(name) => index(name),
+                }),
+                reloc: relocation.reloc,
+            });
+        }
+
+        for (name, data) in sections {
+            object.sections.push(object::Section {
+                name,
+                size: data.len() as u64,
+                data,
+                flags: 0,
+                align: 1,
+            });
+        }
+
+        Ok(())
+    }
+
+    // images are loaded one page below their first section where the headers go
+    fn write_pe(&mut self, file: &str) -> Result<(), Error> {
+        let base = self.preprocessor.base() as u64 - 0x1000;
+        let entry = *self.preprocessor.offsets.get(&self.entry()).ok_or(Error::UndefinedEntry(self.entry()))? as u64;
+
+        let mut segments = self.segments();
+        let imports = (!self.preprocessor.imports.is_empty()).then(|| pe::Imports::new(self.got as u64, base, &self.preprocessor.imports));
+
+        if let Some(imports) = &imports {
+            segments.push(self.stubs(|index| imports.slots[&self.preprocessor.externs[index]] as usize));
+        }
+
+        let image = pe::Image {
+            base,
+            entry,
+            gui: self.preprocessor.format == Format::Pe64Gui,
+            segments,
+            imports,
+        };
+
+        image.write(&mut File::create(file)?)
+    }
+
+    fn write_executable(&mut self, file: &str) -> Result<(), Error> {
+        // shared libraries only have an entry point if they name one
+        let entry = match self.preprocessor.offsets.get(&self.entry()) {
+            Some(entry) => *entry as u64,
+            None if self.preprocessor.format == Format::Elf64Shared => 0,
+            None => return Err(Error::UndefinedEntry(self.entry())),
+        };
+
+        let mut executable = Executable::new(entry);
+
+        executable.segments = self.segments();
+        executable.debug = self.debug_info()?.0;
+
+        if self.preprocessor.format.is_dynamic() {
+            executable.dynamic = Some(self.dynamic(&mut executable.segments)?);
+        }
+
+        executable.write(&mut File::create(file)?)?;
+
+        fs::set_permissions(file, fs::Permissions::from_mode(0o755))?;
+
+        Ok(())
+    }
+
+    fn dynamic(&mut self, segments: &mut Vec<Segment>) -> Result<Dynamic, Error> {
+        if !self.preprocessor.externs.is_empty() {
+            segments.push(self.stubs(|index| self.got + 8 * index));
+        }
+
+        let mut exports = Vec::new();
+
+        for public in &self.preprocessor.publics {
+            let addr = self.preprocessor.offsets[public];
+            let size = self.symbol_size(public)?;
+
+            exports.push(Export {
+                name: public.clone(),
+                addr: addr as u64,
+                size: size as u64,
+                function: self.preprocessor.labels.iter().any(|(label, decl)| label == public && matches!(decl, Decl::Defined(DefinedDecl::Function(_)))),
+            });
+        }
+
+        let pie = self.preprocessor.format == Format::Elf64Pie;
+
+        Ok(Dynamic {
+            addr: self.got as u64,
+            pie,
+            interpreter: pie.then(|| String::from("/lib64/ld-linux-x86-64.so.2")),
+            needed: self.preprocessor.needed.clone(),
+            imports: self.preprocessor.externs.clone(),
+            exports,
+            relocations: std::mem::take(&mut self.relocations),
+        })
+    }
+
+    // `_start` is the entry point unless the `entry` directive names another label
+    fn entry(&self) -> String {
+        self.preprocessor.entry.clone().unwrap_or(String::from("_start"))
+    }
+
+    // executables keep the address of every segment through a linker script, elf objects are placed by `ld` itself
+    fn link(&self, file: &str) -> Result<(), Error> {
+        let mut args: Vec<String> = vec![String::from("-e"), self.entry()];
+
+        if self.preprocessor.format == Format::Elf64Executable {
+            let mut script = String::from("SECTIONS\n{\n");
+
+            let sections = self.preprocessor.sections.iter().zip(&self.images).zip(self.section_names());
+
+            for ((section, _), name) in sections.filter(|((_, image), _)| !image.bytes.is_empty()) {
+                script.push_str(&format!("  {} {:#x} : {{ *({}) }}\n", name, section.start, name));
+            }
+
+            script.push_str("}\n");
+
+            File::create([file, ".ld"].concat())?.write_all(script.as_bytes())?;
+            args.extend([String::from("-T"), [file, ".ld"].concat()]);
+        }
+
+        let status = Command::new("ld")
+            .args(args)
+            .args(["-o", file, &[file, ".o"].concat()])
+            .status()
+            .map_err(Error::Linker)?;
+
+        if !status.success() {
+            return Err(Error::LinkFailed(status));
+        }
+
+        Ok(())
+    }
+
+    // `ld` links the object file instead of writing the executable directly
+    pub fn emit(&mut self, file: &str, ld: bool, flat: Flat, listing: Option<&str>, map: Option<(&str, MapFormat)>) -> Result<(), Error> {
+        let source = file;
+        let file = file.split('.').next().unwrap_or("object");
+
+        self.build()?;
+
+        // nothing is written for runs that are rejected
+        if flat != Flat::Raw && self.preprocessor.format != Format::Binary {
+            return Err(Error::WrongFormat { directive: "`--emit`", formats: "`format binary`", help: None });
+        }
+
+        if ld && !matches!(self.preprocessor.format, Format::Elf64 | Format::Elf64Executable) {
+            let help = if self.preprocessor.format == Format::MachO64 { Some("Mach-O objects are linked with `ld64` or `clang` on macOS") } else { None };
+
+            return Err(Error::WrongFormat { directive: "`--ld`", formats: "`format ELF64 executable` and `ELF64`", help });
+        }
+
+        if let Some(listing) = listing {
+            File::create(listing)?.write_all(listing::write(&fs::read_to_string(source)?, &self.listing).as_bytes())?;
+        }
+
+        if let Some((map, format)) = map {
+            File::create(map)?.write_all(format.write(&self.symbols()?).as_bytes())?;
+        }
+
+        match self.preprocessor.format {
+            Format::Binary => {
+                let bytes: Vec<u8> = self.images.iter().flat_map(|image| image.bytes.clone()).collect();
+                let name = file.rsplit('/').next().unwrap_or(file);
+
+                File::create([file, flat.extension()].concat())?.write_all(&flat.write(&bytes, self.load, name))?;
+            },
+            Format::Elf64 => {
+                let mut object = self.object()?;
+
+                self.attach_debug(&mut object)?;
+                object.write_elf(&mut File::create([file, ".o"].concat())?)?;
+
+                if ld {
+                    self.link(file)?;
+                }
+            },
+            Format::MachO64 => {
+                File::create([file, ".o"].concat())?.write_all(&self.obj.emit()?)?;
+            },
+            Format::Elf64Executable if ld => {
+                let mut object = self.object()?;
+
+                self.attach_debug(&mut object)?;
+                object.write_elf(&mut File::create([file, ".o"].concat())?)?;
+                self.link(file)?;
+            },
+            Format::Elf64Executable | Format::Elf64Pie => self.write_executable(file)?,
+            Format::Elf64Shared => self.write_executable(&[file, ".so"].concat())?,
+            Format::Pe64 | Format::Pe64Gui => self.write_pe(&[file, ".exe"].concat())?,
+            Format::Ms64Coff => self.object()?.write_coff(&mut File::create([file, ".obj"].concat())?)?,
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::Lints;
+
+    // a file of its own in the temporary directory, outputs go next to it
+    fn path(name: &str) -> String {
+        std::env::temp_dir().join(format!("fasm-rs-{}-{}", std::process::id(), name)).to_string_lossy().into_owned()
+    }
+
+    fn new(path: &str, source: &str) -> Codegen {
+        fs::write(path, source).unwrap();
+
+        let diagnostics = Diagnostics::new(20, Lints { warn: Vec::new(), allow: Vec::new(), deny: false });
+
+        Codegen::new(path, None, diagnostics).unwrap()
+    }
+
+    // builds `source` as a flat binary
+    fn codegen(name: &str, source: &str) -> Codegen {
+        let path = path(&[name, ".fasm"].concat());
+        let mut codegen = new(&path, &format!("format binary\n{}\n", source));
+        let _ = codegen.build();
+
+        fs::remove_file(path).unwrap();
+        codegen
+    }
+
+    fn assemble(name: &str, source: &str) -> Vec<u8> {
+        let codegen = codegen(name, source);
+
+        assert_eq!(codegen.diagnostics.errors, []);
+        codegen.images.iter().flat_map(|image| image.bytes.clone()).collect()
+    }
+
+    fn errors(name: &str, source: &str) -> Vec<&'static str> {
+        codegen(name, source).diagnostics.errors.iter().filter_map(|error| error.code).collect()
+    }
+
+    fn warnings(name: &str, source: &str) -> Vec<Lint> {
+        codegen(name, source).diagnostics.warnings.iter().filter_map(|warning| warning.lint).collect()
+    }
+
+    // `(line, start, end)` of every error and warning, the source starts on line 2
+    fn carets(name: &str, source: &str) -> Vec<(usize, usize, usize)> {
+        let codegen = codegen(name, source);
+
+        codegen.diagnostics.errors.iter().chain(&codegen.diagnostics.warnings).map(|diagnostic| (diagnostic.span.line, diagnostic.span.start, diagnostic.span.end)).collect()
+    }
+
+    #[test]
+    fn vex() {
+        // C5 when only R is needed, C4 for the 0F38 map, W or an extended base
+        assert_eq!(assemble("vex-c5", "vaddps ymm0, ymm1, ymm2"), [0xc5, 0xf4, 0x58, 0xc2]);
+        assert_eq!(assemble("vex-c4", "vfmadd231ps ymm0, ymm1, ymm2"), [0xc4, 0xe2, 0x75, 0xb8, 0xc2]);
+        assert_eq!(assemble("vex-rex", "vaddps ymm8, ymm9, [r10 + 32]"), [0xc4, 0x41, 0x34, 0x58, 0x42, 0x20]);
+    }
+
+    #[test]
+    fn evex() {
+        assert_eq!(assemble("evex", "vaddps zmm0, zmm1, zmm2"), [0x62, 0xf1, 0x74, 0x48, 0x58, 0xc2]);
+        assert_eq!(assemble("evex-mask", "vaddps zmm0{k1}{z}, zmm1, zmm2"), [0x62, 0xf1, 0x74, 0xc9, 0x58, 0xc2]);
+    }
+
+    #[test]
+    fn rounding() {
+        // the rounding mode goes into L'L next to EVEX.b
+        assert_eq!(assemble("rn-sae", "vaddps zmm0, zmm1, zmm2, {rn-sae}"), [0x62, 0xf1, 0x74, 0x18, 0x58, 0xc2]);
+        assert_eq!(assemble("rd-sae", "vaddps zmm0, zmm1, zmm2, {rd-sae}"), [0x62, 0xf1, 0x74, 0x38, 0x58, 0xc2]);
+        assert_eq!(assemble("ru-sae", "vaddps zmm0, zmm1, zmm2, {ru-sae}"), [0x62, 0xf1, 0x74, 0x58, 0x58, 0xc2]);
+        assert_eq!(assemble("rz-sae", "vaddps zmm0, zmm1, zmm2, {rz-sae}"), [0x62, 0xf1, 0x74, 0x78, 0x58, 0xc2]);
+    }
+
+    #[test]
+    fn disp8n() {
+        // full vectors scale the displacement by 64, broadcasts and scalars by their element
+        assert_eq!(assemble("disp8-full", "vaddps zmm0, zmm1, [rax + 64]"), [0x62, 0xf1, 0x74, 0x48, 0x58, 0x40, 0x01]);
+        assert_eq!(assemble("disp8-broadcast", "vaddps zmm0, zmm1, [rax + 4]{1to16}"), [0x62, 0xf1, 0x74, 0x58, 0x58, 0x40, 0x01]);
+        assert_eq!(assemble("disp8-scalar", "vbroadcastss zmm0, [rax + 8]"), [0x62, 0xf2, 0x7d, 0x48, 0x18, 0x40, 0x02]);
+
+        // displacements that are not a multiple of N take all 32 bits
+        assert_eq!(assemble("disp32", "vaddps zmm0, zmm1, [rax + 65]"), [0x62, 0xf1, 0x74, 0x48, 0x58, 0x80, 0x41, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn data() {
+        assert_eq!(assemble("dd-sign", "dd 0x80000000, 0xffffffff, -1"), [0x00, 0x00, 0x00, 0x80, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(assemble("dq-zero-extend", "dq 0x80000000, 0xffffffff"), [0x00, 0x00, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00]);
+        assert_eq!(assemble("dq-sign-extend", "dq -0x80000000, 0xffffffffffffffff"), [0x00, 0x00, 0x00, 0x80, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn wide_immediates() {
+        // `mov` into a 64-bit register takes all 64 bits when the sign extended 32 bits would change the value
+        assert_eq!(assemble("mov-imm32", "mov rax, -0x80000000"), [0x48, 0xc7, 0xc0, 0x00, 0x00, 0x00, 0x80]);
+        assert_eq!(assemble("mov-imm64", "mov rax, 0xffffffff"), [0x48, 0xb8, 0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00]);
+        assert_eq!(assemble("mov-high", "mov r9, 0x123456789abcdef0"), [0x49, 0xb9, 0xf0, 0xde, 0xbc, 0x9a, 0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(assemble("mov-dword", "mov eax, 0xffffffff"), [0xb8, 0xff, 0xff, 0xff, 0xff]);
+
+        // nothing else has a 64-bit immediate
+        assert_eq!(errors("push-wide", "push 0x80000000"), ["E0345"]);
+        assert_eq!(errors("add-wide", "add rax, 0xffffffff"), ["E0345"]);
+        assert_eq!(errors("store-wide", "mov qword [rax], 0x80000000"), ["E0345"]);
+        assert_eq!(errors("disp-wide", "mov eax, [rax + 0x100000000]"), ["E0120"]);
+    }
+
+    #[test]
+    fn truncated_immediates() {
+        assert_eq!(warnings("dword-fits", "mov eax, 0xffffffff\nmov ebx, -0x80000000\ndd 0xffffffff, -0x80000000"), []);
+        assert_eq!(warnings("dword-truncated", "mov eax, 0x100000000\nmov ebx, -0x80000001\ndd 0x100000000"), [Lint::ImmediateTruncated; 3]);
+        assert_eq!(warnings("word-truncated", "dw 0x10000\ndb -0x81"), [Lint::ImmediateTruncated; 2]);
+    }
+
+    #[test]
+    fn operand_spans() {
+        // an operand written twice is pointed at where the error is about
+        assert_eq!(carets("broadcast-source", "vbroadcastss ymm1, ymm1"), [(2, 20, 24)]);
+        assert_eq!(carets("use64-source", "use32\nmov eax, rbx"), [(3, 10, 13)]);
+        assert_eq!(carets("macro-operand", "macro m a\n{\nmov eax, a\n}\nm rbx"), [(4, 10, 11)]);
+        assert_eq!(carets("data-truncated", "dd 0x100000000, 0x100000000\ndd 1, 0x100000000"), [(2, 4, 15), (2, 17, 28), (3, 7, 18)]);
+
+        // parse errors point at the token they are about
+        assert_eq!(carets("second-comma", "lea eax, [rbx], 1"), [(2, 15, 16)]);
+        assert_eq!(carets("address-term", "mov eax, [rbx + rcx * 3 + rcx * 3]"), [(2, 23, 24)]);
+        assert_eq!(carets("unknown-instruction", "bogus ]\nbogus eax"), [(2, 1, 6), (3, 1, 6)]);
+    }
+
+    #[test]
+    fn rejected_listing() {
+        let (source, listing) = (path("rejected-listing.fasm"), path("rejected-listing.lst"));
+        let mut codegen = new(&source, "format ELF64\nsection '.text' executable\nret\n");
+
+        assert!(matches!(codegen.emit(&source, false, Flat::Ihex, Some(&listing), None), Err(Error::WrongFormat { directive: "`--emit`", .. })));
+        assert!(!std::path::Path::new(&listing).exists());
+
+        fs::remove_file(source).unwrap();
+    }
+
+    #[test]
+    fn macho_ld() {
+        let source = path("macho-ld.fasm");
+        let mut codegen = new(&source, "format MachO64\nsection '.text' executable\nret\n");
+
+        assert!(matches!(codegen.emit(&source, true, Flat::Raw, None, None), Err(Error::WrongFormat { directive: "`--ld`", .. })));
+        assert!(!std::path::Path::new(&path("macho-ld.o")).exists());
+
+        fs::remove_file(source).unwrap();
+    }
+
+    #[test]
+    fn rejected_map() {
+        let (source, map) = (path("rejected-map.fasm"), path("rejected-map.map"));
+        let mut codegen = new(&source, "format PE64\nsegment readable executable\n_start:\nret\n");
+
+        assert!(matches!(codegen.emit(&source, true, Flat::Raw, None, Some((&map, MapFormat::Text))), Err(Error::WrongFormat { directive: "`--ld`", .. })));
+        assert!(!std::path::Path::new(&map).exists());
+
+        fs::remove_file(source).unwrap();
+    }
+}
 
@@ -0,0 +1,41 @@
+// a section of an object file, `flags` are the same as for elf segments and sections without any are not loaded
+pub struct Section {
+    pub name: String,
+    pub data: Vec<u8>,
+    pub size: u64,
+    pub flags: u32,
+    pub align: u64,
+}
+
+// relocations either point into a section of the object or at a symbol from another one
+pub enum Target {
+    Section(usize),
+    Symbol(String),
+}
+
+// the field at `at` holds the addend, the linker adds the address of the target to it
+pub struct Relocation {
+    pub section: usize,
+    pub at: u64,
+    pub target: Target,
+    pub reloc: u32,
+}
+
+// symbols without a section are external
+pub struct Symbol {
+    pub name: String,
+    pub section: Option<usize>,
+    pub value: u64,
+    pub size: u64,
+    pub function: bool,
+    pub public: bool,
+}
+
+// elf and coff objects are written from the same sections, symbols and relocations, only mach-o goes through faerie
+// since faerie gives sections with our own names no SHF_ALLOC and makes the symbols inside them local, untyped and unsized
+pub struct Object {
+    pub sections: Vec<Section>,
+    pub symbols: Vec<Symbol>,
+    pub relocations: Vec<Relocation>,
+}
+
@@ -0,0 +1,624 @@
+use super::elf::{Segment, PF_W, PF_X};
+use super::object::{Object, Target};
+use super::elf;
+use crate::error::Error;
+
+use std::collections::HashMap;
+use std::io::Write;
+
+// section characteristics @ pe format specification
+const SCN_CNT_CODE: u32 = 0x20;
+const SCN_CNT_INITIALIZED_DATA: u32 = 0x40;
+const SCN_CNT_UNINITIALIZED_DATA: u32 = 0x80;
+const SCN_MEM_EXECUTE: u32 = 0x2000_0000;
+const SCN_MEM_READ: u32 = 0x4000_0000;
+const SCN_MEM_WRITE: u32 = 0x8000_0000;
+
+// relocation types @ pe format specification, x64 processors
+const REL_AMD64_ADDR64: u16 = 1;
+const REL_AMD64_ADDR32: u16 = 2;
+const REL_AMD64_REL32: u16 = 4;
+
+const MACHINE_AMD64: u16 = 0x8664;
+
+const SECTION_ALIGN: u64 = 0x1000;
+const FILE_ALIGN: u64 = 0x200;
+
+const DOS_SIZE: u64 = 64;
+const COFF_SIZE: u64 = 20;
+const OPTIONAL_SIZE: u64 = 240;
+const SECTION_SIZE: u64 = 40;
+const SYMBOL_SIZE: u64 = 18;
+const RELOC_SIZE: u64 = 10;
+
+// the import directory of an image, the loader fills the address table with the address of every imported function
+pub struct Imports {
+    pub addr: u64,
+    pub data: Vec<u8>,
+    pub slots: HashMap<String, u64>,
+    directory: (u64, u64),
+    table: (u64, u64),
+}
+
+pub struct Image {
+    pub base: u64,
+    pub entry: u64,
+    pub gui: bool,
+    pub segments: Vec<Segment>,
+    pub imports: Option<Imports>,
+}
+
+fn align(offset: u64, align: u64) -> u64 {
+    offset.div_ceil(align) * align
+}
+
+fn characteristics(flags: u32, bss: bool) -> u32 {
+    let contents = if flags & PF_X != 0 {
+        SCN_CNT_CODE | SCN_MEM_EXECUTE
+    } else if bss {
+        SCN_CNT_UNINITIALIZED_DATA
+    } else {
+        SCN_CNT_INITIALIZED_DATA
+    };
+
+    contents | SCN_MEM_READ | if flags & PF_W != 0 { SCN_MEM_WRITE } else { 0 }
+}
+
+// the elf relocation types used by the encoder map onto their coff counterparts, addr32 is zero extended
+fn relocation_type(reloc: u32) -> Result<u16, Error> {
+    match reloc {
+        elf::R_X86_64_64 => Ok(REL_AMD64_ADDR64),
+        elf::R_X86_64_32 => Ok(REL_AMD64_ADDR32),
+        elf::R_X86_64_PC32 | elf::R_X86_64_PLT32 => Ok(REL_AMD64_REL32),
+        _ => Err(Error::CoffRelocation),
+    }
+}
+
+// [Name VirtualSize VirtualAddress SizeOfRawData PointerToRawData PointerToRelocations PointerToLinenumbers NumberOfRelocations NumberOfLinenumbers Characteristics]
+fn write_section(bytes: &mut Vec<u8>, name: [u8; 8], addr: (u64, u64), raw: (u64, u64), relocations: (u64, u64), characteristics: u32) {
+    bytes.extend(name);
+    bytes.extend((addr.1 as u32).to_le_bytes());
+    bytes.extend((addr.0 as u32).to_le_bytes());
+    bytes.extend((raw.1 as u32).to_le_bytes());
+    bytes.extend((raw.0 as u32).to_le_bytes());
+    bytes.extend((relocations.0 as u32).to_le_bytes());
+    bytes.extend(0u32.to_le_bytes());
+    bytes.extend((relocations.1 as u16).to_le_bytes());
+    bytes.extend(0u16.to_le_bytes());
+    bytes.extend(characteristics.to_le_bytes());
+}
+
+// [Machine NumberOfSections TimeDateStamp PointerToSymbolTable NumberOfSymbols SizeOfOptionalHeader Characteristics]
+fn write_header(bytes: &mut Vec<u8>, sections: usize, symbols: (u64, u64), optional: u64, characteristics: u16) {
+    bytes.extend(MACHINE_AMD64.to_le_bytes());
+    bytes.extend((sections as u16).to_le_bytes());
+    bytes.extend(0u32.to_le_bytes());
+    bytes.extend((symbols.0 as u32).to_le_bytes());
+    bytes.extend((symbols.1 as u32).to_le_bytes());
+    bytes.extend((optional as u16).to_le_bytes());
+    bytes.extend(characteristics.to_le_bytes());
+}
+
+// names longer than 8 bytes are an offset into the string table of objects, images cut them off
+fn name(name: &str, strtab: Option<&mut Vec<u8>>) -> [u8; 8] {
+    let mut bytes = [0; 8];
+
+    match strtab {
+        Some(strtab) if name.len() > 8 => {
+            let offset = format!("/{}", strtab.len());
+
+            bytes[..offset.len()].copy_from_slice(offset.as_bytes());
+            strtab.extend(name.as_bytes());
+            strtab.push(0);
+        },
+        _ => {
+            let length = name.len().min(8);
+
+            bytes[..length].copy_from_slice(&name.as_bytes()[..length]);
+        },
+    }
+
+    bytes
+}
+
+impl Object {
+    fn symbol_index(&self, target: &Target) -> Result<u32, Error> {
+        // every section has a symbol and an auxiliary record in front of the other symbols
+        match target {
+            Target::Section(section) => Ok(*section as u32 * 2),
+            Target::Symbol(symbol) => self.symbols.iter()
+                .position(|other| other.name == *symbol)
+                .map(|index| (self.sections.len() * 2 + index) as u32)
+                .ok_or(Error::NoSuchLabel(symbol.clone())),
+        }
+    }
+
+    // [Name Value SectionNumber Type StorageClass NumberOfAuxSymbols]
+    fn write_symbol(bytes: &mut Vec<u8>, strtab: &mut Vec<u8>, symbol: &str, (section, value): (i16, u64), kind: u16, class: u8, aux: u8) {
+        if symbol.len() > 8 {
+            bytes.extend(0u32.to_le_bytes());
+            bytes.extend((strtab.len() as u32).to_le_bytes());
+            strtab.extend(symbol.as_bytes());
+            strtab.push(0);
+        } else {
+            bytes.extend(name(symbol, None));
+        }
+
+        bytes.extend((value as u32).to_le_bytes());
+        bytes.extend(section.to_le_bytes());
+        bytes.extend(kind.to_le_bytes());
+        bytes.extend([class, aux]);
+    }
+
+    // https://learn.microsoft.com/en-us/windows/win32/debug/pe-format#coff-file-header-object-and-image
+    pub fn write_coff(&self, fd: &mut impl Write) -> Result<(), Error> {
+        // the string table starts with its own size
+        let mut strtab: Vec<u8> = vec![0; 4];
+        let mut relocations: Vec<Vec<u8>> = vec![Vec::new(); self.sections.len()];
+
+        for relocation in &self.relocations {
+            let bytes = &mut relocations[relocation.section];
+
+            bytes.extend((relocation.at as u32).to_le_bytes());
+            bytes.extend(self.symbol_index(&relocation.target)?.to_le_bytes());
+            bytes.extend(relocation_type(relocation.reloc)?.to_le_bytes());
+        }
+
+        let mut offset = COFF_SIZE + SECTION_SIZE * self.sections.len() as u64;
+        let mut headers: Vec<u8> = Vec::new();
+        let mut data: Vec<u8> = Vec::new();
+
+        for (section, relocations) in self.sections.iter().zip(&relocations) {
+            let count = relocations.len() as u64 / RELOC_SIZE;
+
+            if count > u16::MAX as u64 {
+                return Err(Error::TooManyRelocations(section.name.clone()));
+            }
+
+            // alignments up to 8192 are encoded as their logarithm plus one
+            let align = (section.align.clamp(1, 8192).trailing_zeros() + 1) << 20;
+            let raw = if section.data.is_empty() { (0, section.size) } else { (offset, section.data.len() as u64) };
+            let relocs = if count == 0 { (0, 0) } else { (offset + section.data.len() as u64, count) };
+
+            write_section(&mut headers, name(&section.name, Some(&mut strtab)), (0, 0), raw, relocs, characteristics(section.flags, section.data.is_empty()) | align);
+
+            data.extend(&section.data);
+            data.extend(relocations);
+            offset += (section.data.len() + relocations.len()) as u64;
+        }
+
+        let mut symbols: Vec<u8> = Vec::new();
+
+        for (index, (section, relocations)) in self.sections.iter().zip(&relocations).enumerate() {
+            // IMAGE_SYM_CLASS_STATIC with a section definition record [Length NumberOfRelocations NumberOfLinenumbers CheckSum Number Selection]
+            Self::write_symbol(&mut symbols, &mut strtab, &section.name, (index as i16 + 1, 0), 0, 3, 1);
+
+            symbols.extend((section.size.max(section.data.len() as u64) as u32).to_le_bytes());
+            symbols.extend(((relocations.len() as u64 / RELOC_SIZE) as u16).to_le_bytes());
+            symbols.extend([0; 12]);
+        }
+
+        for symbol in &self.symbols {
+            // IMAGE_SYM_CLASS_EXTERNAL or IMAGE_SYM_CLASS_STATIC, functions have the complex type 0x20
+            let section = symbol.section.map_or(0, |section| section as i16 + 1);
+            let class = if symbol.public || symbol.section.is_none() { 2 } else { 3 };
+
+            Self::write_symbol(&mut symbols, &mut strtab, &symbol.name, (section, symbol.value), if symbol.function { 0x20 } else { 0 }, class, 0);
+        }
+
+        let count = symbols.len() as u64 / SYMBOL_SIZE;
+        let length = strtab.len() as u32;
+
+        strtab[..4].copy_from_slice(&length.to_le_bytes());
+
+        let mut bytes: Vec<u8> = Vec::new();
+
+        write_header(&mut bytes, self.sections.len(), (offset, count), 0, 0);
+
+        bytes.extend(headers);
+        bytes.extend(data);
+        bytes.extend(symbols);
+        bytes.extend(strtab);
+
+        fd.write_all(&bytes)?;
+
+        Ok(())
+    }
+}
+
+impl Imports {
+    // [import directory] [lookup tables] [address tables] [hint/name entries] [library names], every table ends with a null entry
+    pub fn new(addr: u64, base: u64, imports: &[(String, String)]) -> Imports {
+        let mut libraries: Vec<(&str, Vec<&str>)> = Vec::new();
+
+        for (symbol, library) in imports {
+            match libraries.iter_mut().find(|(name, _)| *name == library) {
+                Some((_, symbols)) => symbols.push(symbol),
+                None => libraries.push((library, vec![symbol])),
+            }
+        }
+
+        let rva = addr - base;
+        let tables = (libraries.len() as u64 + 1) * 20;
+        let length = (imports.len() + libraries.len()) as u64 * 8;
+        let names = tables + length * 2;
+
+        let mut hints: Vec<u8> = Vec::new();
+        let mut lookup: Vec<u8> = Vec::new();
+        let mut directory: Vec<u8> = Vec::new();
+        let mut slots = HashMap::new();
+
+        for (_, symbols) in &libraries {
+            let table = tables + lookup.len() as u64;
+
+            for symbol in symbols {
+                // the address table is a copy of the lookup table right after it
+                slots.insert(symbol.to_string(), addr + tables + length + lookup.len() as u64);
+                lookup.extend((rva + names + hints.len() as u64).to_le_bytes());
+
+                // the hint is only a guess at the index into the export table of the library
+                hints.extend(0u16.to_le_bytes());
+                hints.extend(symbol.as_bytes());
+                hints.push(0);
+                hints.resize(align(hints.len() as u64, 2) as usize, 0);
+            }
+
+            lookup.extend(0u64.to_le_bytes());
+
+            // [OriginalFirstThunk TimeDateStamp ForwarderChain Name FirstThunk]
+            directory.extend(((rva + table) as u32).to_le_bytes());
+            directory.extend([0; 8]);
+            directory.extend(0u32.to_le_bytes());
+            directory.extend(((rva + table + length) as u32).to_le_bytes());
+        }
+
+        let mut strings: Vec<u8> = Vec::new();
+
+        for (index, (library, _)) in libraries.iter().enumerate() {
+            let name = rva + names + hints.len() as u64 + strings.len() as u64;
+
+            directory[index * 20 + 12..index * 20 + 16].copy_from_slice(&(name as u32).to_le_bytes());
+            strings.extend(library.as_bytes());
+            strings.push(0);
+        }
+
+        directory.resize(tables as usize, 0);
+
+        Imports {
+            addr,
+            data: [directory, lookup.clone(), lookup, hints, strings].concat(),
+            slots,
+            directory: (rva, tables),
+            table: (rva + tables + length, length),
+        }
+    }
+}
+
+impl Image {
+    // [Magic MajorLinkerVersion MinorLinkerVersion SizeOfCode SizeOfInitializedData SizeOfUninitializedData AddressOfEntryPoint BaseOfCode ImageBase ..]
+    fn write_optional_header(&self, bytes: &mut Vec<u8>, sections: &[(&Segment, u64, u64)], headers: u64) {
+        let size = |kind: u32| sections.iter()
+            .filter(|(segment, _, _)| characteristics(segment.flags, segment.data.is_empty()) & kind != 0)
+            .map(|(segment, _, raw)| if kind == SCN_CNT_UNINITIALIZED_DATA { segment.size } else { *raw })
+            .sum::<u64>() as u32;
+
+        let code = sections.iter().find(|(segment, _, _)| segment.flags & PF_X != 0).map_or(0, |(segment, _, _)| segment.addr - self.base);
+        let end = sections.iter().map(|(segment, _, _)| segment.addr + segment.size.max(segment.data.len() as u64)).max().unwrap_or(self.base);
+
+        bytes.extend(0x20bu16.to_le_bytes());
+        bytes.extend([1, 0]);
+        bytes.extend(size(SCN_CNT_CODE).to_le_bytes());
+        bytes.extend(size(SCN_CNT_INITIALIZED_DATA).to_le_bytes());
+        bytes.extend(size(SCN_CNT_UNINITIALIZED_DATA).to_le_bytes());
+        bytes.extend(((self.entry - self.base) as u32).to_le_bytes());
+        bytes.extend((code as u32).to_le_bytes());
+        bytes.extend(self.base.to_le_bytes());
+        bytes.extend((SECTION_ALIGN as u32).to_le_bytes());
+        bytes.extend((FILE_ALIGN as u32).to_le_bytes());
+
+        // operating system, image and subsystem versions, windows vista is the oldest one that matters
+        bytes.extend([6u16, 0, 0, 0, 6, 0].iter().flat_map(|version| version.to_le_bytes()));
+        bytes.extend(0u32.to_le_bytes());
+        bytes.extend((align(end - self.base, SECTION_ALIGN) as u32).to_le_bytes());
+        bytes.extend((headers as u32).to_le_bytes());
+        bytes.extend(0u32.to_le_bytes());
+
+        // IMAGE_SUBSYSTEM_WINDOWS_GUI or IMAGE_SUBSYSTEM_WINDOWS_CUI, IMAGE_DLLCHARACTERISTICS_NX_COMPAT | IMAGE_DLLCHARACTERISTICS_TERMINAL_SERVER_AWARE
+        bytes.extend(if self.gui { 2u16 } else { 3u16 }.to_le_bytes());
+        bytes.extend(0x8100u16.to_le_bytes());
+
+        // stack and heap reserve and commit
+        bytes.extend([0x100000u64, 0x1000, 0x100000, 0x1000].iter().flat_map(|size| size.to_le_bytes()));
+        bytes.extend(0u32.to_le_bytes());
+
+        // data directories, only the import directory and the import address table are used
+        let mut directories = [(0u64, 0u64); 16];
+
+        if let Some(imports) = &self.imports {
+            directories[1] = imports.directory;
+            directories[12] = imports.table;
+        }
+
+        bytes.extend(16u32.to_le_bytes());
+        bytes.extend(directories.iter().flat_map(|(rva, size)| [(*rva as u32).to_le_bytes(), (*size as u32).to_le_bytes()]).flatten());
+    }
+
+    // https://learn.microsoft.com/en-us/windows/win32/debug/pe-format
+    pub fn write(&self, fd: &mut impl Write) -> Result<(), Error> {
+        let idata = self.imports.as_ref().map(|imports| Segment {
+            name: String::from(".idata"),
+            addr: imports.addr,
+            data: imports.data.clone(),
+            size: imports.data.len() as u64,
+            flags: elf::PF_R | PF_W,
+        });
+
+        let segments: Vec<&Segment> = self.segments.iter().chain(idata.iter()).collect();
+        let headers = align(DOS_SIZE + 4 + COFF_SIZE + OPTIONAL_SIZE + SECTION_SIZE * segments.len() as u64, FILE_ALIGN);
+
+        let mut offset = headers;
+        let mut sections: Vec<(&Segment, u64, u64)> = Vec::new();
+
+        // raw data is padded to the file alignment, uninitialized sections have none
+        for segment in segments {
+            let raw = align(segment.data.len() as u64, FILE_ALIGN);
+
+            sections.push((segment, if raw == 0 { 0 } else { offset }, raw));
+            offset += raw;
+        }
+
+        let mut bytes: Vec<u8> = Vec::new();
+
+        // the dos header only needs its magic and e_lfanew pointing right after it
+        bytes.extend(b"MZ");
+        bytes.resize(0x3c, 0);
+        bytes.extend((DOS_SIZE as u32).to_le_bytes());
+        bytes.extend(b"PE\0\0");
+
+        // IMAGE_FILE_RELOCS_STRIPPED | IMAGE_FILE_EXECUTABLE_IMAGE | IMAGE_FILE_LARGE_ADDRESS_AWARE, the image is always loaded at its base
+        write_header(&mut bytes, sections.len(), (0, 0), OPTIONAL_SIZE, 0x23);
+
+        self.write_optional_header(&mut bytes, &sections, headers);
+
+        for (segment, offset, raw) in &sections {
+            let size = segment.size.max(segment.data.len() as u64);
+
+            write_section(&mut bytes, name(&segment.name, None), (segment.addr - self.base, size), (*offset, *raw), (0, 0), characteristics(segment.flags, segment.data.is_empty()));
+        }
+
+        for (segment, offset, _) in sections.iter().filter(|(segment, _, _)| !segment.data.is_empty()) {
+            bytes.resize(*offset as usize, 0);
+            bytes.extend(&segment.data);
+        }
+
+        bytes.resize(offset as usize, 0);
+
+        fd.write_all(&bytes)?;
+
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::object::{Relocation, Section, Symbol};
+
+    fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+    }
+
+    fn string_at(bytes: &[u8], offset: usize) -> &str {
+        std::str::from_utf8(bytes[offset..].split(|byte| *byte == 0).next().unwrap()).unwrap()
+    }
+
+    // [name virtual_size virtual_address raw_size raw_offset characteristics] of every section header
+    fn sections(bytes: &[u8], table: usize, count: usize) -> Vec<(String, u32, u32, u32, u32, u32)> {
+        (0..count)
+            .map(|index| {
+                let header = table + index * SECTION_SIZE as usize;
+
+                (string_at(&bytes[header..header + 8], 0).to_string(), u32_at(bytes, header + 8), u32_at(bytes, header + 12), u32_at(bytes, header + 16), u32_at(bytes, header + 20), u32_at(bytes, header + 36))
+            })
+            .collect()
+    }
+
+    fn image() -> Vec<u8> {
+        let base = 0x40_0000;
+        let imports = [(String::from("ExitProcess"), String::from("kernel32.dll")), (String::from("GetStdHandle"), String::from("kernel32.dll")), (String::from("MessageBoxA"), String::from("user32.dll"))];
+
+        let image = Image {
+            base,
+            entry: 0x40_1004,
+            gui: false,
+            segments: vec![
+                Segment { name: String::from(".text"), addr: 0x40_1000, data: vec![0xc3; 0x10], size: 0x10, flags: elf::PF_R | PF_X },
+                Segment { name: String::from(".bss"), addr: 0x40_2000, data: Vec::new(), size: 0x100, flags: elf::PF_R | PF_W },
+            ],
+            imports: Some(Imports::new(0x40_3000, base, &imports)),
+        };
+
+        let mut bytes = Vec::new();
+
+        image.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn image_headers() {
+        let bytes = image();
+        let pe = u32_at(&bytes, 0x3c) as usize;
+
+        assert_eq!(&bytes[..2], b"MZ");
+        assert_eq!(&bytes[pe..pe + 4], b"PE\0\0");
+
+        // machine, sections, optional header size and IMAGE_FILE_RELOCS_STRIPPED | IMAGE_FILE_EXECUTABLE_IMAGE | IMAGE_FILE_LARGE_ADDRESS_AWARE
+        let coff = pe + 4;
+
+        assert_eq!(u16_at(&bytes, coff), MACHINE_AMD64);
+        assert_eq!(u16_at(&bytes, coff + 2), 3);
+        assert_eq!(u16_at(&bytes, coff + 16), OPTIONAL_SIZE as u16);
+        assert_eq!(u16_at(&bytes, coff + 18), 0x23);
+
+        // PE32+ magic, entry point, base of code, image base, alignments and the size of the image up to the end of .idata
+        let optional = coff + COFF_SIZE as usize;
+
+        assert_eq!(u16_at(&bytes, optional), 0x20b);
+        assert_eq!(u32_at(&bytes, optional + 16), 0x1004);
+        assert_eq!(u32_at(&bytes, optional + 20), 0x1000);
+        assert_eq!(u64_at(&bytes, optional + 24), 0x40_0000);
+        assert_eq!(u32_at(&bytes, optional + 32), SECTION_ALIGN as u32);
+        assert_eq!(u32_at(&bytes, optional + 36), FILE_ALIGN as u32);
+        assert_eq!(u32_at(&bytes, optional + 56), 0x4000);
+        assert_eq!(u16_at(&bytes, optional + 68), 3);
+        assert_eq!(u32_at(&bytes, optional + 108), 16);
+    }
+
+    #[test]
+    fn image_sections() {
+        let bytes = image();
+        let table = 0x3c + 4 + 4 + COFF_SIZE as usize + OPTIONAL_SIZE as usize;
+        let sections = sections(&bytes, table, 3);
+
+        assert_eq!(sections[0], (String::from(".text"), 0x10, 0x1000, 0x200, 0x200, SCN_CNT_CODE | SCN_MEM_EXECUTE | SCN_MEM_READ));
+        assert_eq!(sections[1], (String::from(".bss"), 0x100, 0x2000, 0, 0, SCN_CNT_UNINITIALIZED_DATA | SCN_MEM_READ | SCN_MEM_WRITE));
+        assert_eq!((sections[2].0.as_str(), sections[2].2, sections[2].4), (".idata", 0x3000, 0x400));
+        assert_eq!(&bytes[0x200..0x210], &[0xc3; 0x10]);
+    }
+
+    #[test]
+    fn import_directory() {
+        let bytes = image();
+        let optional = 0x40 + 4 + COFF_SIZE as usize;
+        let directories = optional + 112;
+
+        // the import directory and the import address table are in .idata, which is at rva 0x3000 and file offset 0x400
+        let offset = |rva: u32| (rva - 0x3000 + 0x400) as usize;
+        let directory = u32_at(&bytes, directories + 8);
+
+        assert_eq!(directory, 0x3000);
+        assert_eq!(u32_at(&bytes, directories + 12), 3 * 20);
+
+        let mut imported: Vec<(String, Vec<String>)> = Vec::new();
+
+        for descriptor in (offset(directory)..).step_by(20).take_while(|descriptor| u32_at(&bytes, *descriptor) != 0) {
+            let (lookup, library, table) = (u32_at(&bytes, descriptor), u32_at(&bytes, descriptor + 12), u32_at(&bytes, descriptor + 16));
+            let mut names = Vec::new();
+
+            for (index, entry) in (offset(lookup)..).step_by(8).take_while(|entry| u64_at(&bytes, *entry) != 0).enumerate() {
+                // the address table starts as a copy of the lookup table and every entry points at a hint and a name
+                assert_eq!(u64_at(&bytes, entry), u64_at(&bytes, offset(table) + index * 8));
+
+                names.push(string_at(&bytes, offset(u64_at(&bytes, entry) as u32) + 2).to_string());
+            }
+
+            imported.push((string_at(&bytes, offset(library)).to_string(), names));
+        }
+
+        assert_eq!(imported, vec![
+            (String::from("kernel32.dll"), vec![String::from("ExitProcess"), String::from("GetStdHandle")]),
+            (String::from("user32.dll"), vec![String::from("MessageBoxA")]),
+        ]);
+
+        // the address table directory covers both address tables with their null entries
+        assert_eq!(u32_at(&bytes, directories + 12 * 8 + 4), 5 * 8);
+    }
+
+    #[test]
+    fn base_relocations() {
+        let bytes = image();
+        let coff = 0x40 + 4;
+        let directories = coff + COFF_SIZE as usize + 112;
+
+        // the image is always loaded at its base, so it has no base relocation directory and says so
+        assert_eq!(u16_at(&bytes, coff + 18) & 1, 1);
+        assert_eq!((u32_at(&bytes, directories + 5 * 8), u32_at(&bytes, directories + 5 * 8 + 4)), (0, 0));
+        assert_eq!(u16_at(&bytes, coff + COFF_SIZE as usize + 70) & 0x40, 0);
+    }
+
+    fn object() -> Vec<u8> {
+        let object = Object {
+            sections: vec![
+                Section { name: String::from(".text"), data: vec![0xe8, 0, 0, 0, 0, 0x48, 0xb8, 8, 0, 0, 0, 0, 0, 0, 0, 0xc3], size: 16, flags: elf::PF_R | PF_X, align: 16 },
+                Section { name: String::from(".bss.counters"), data: Vec::new(), size: 8, flags: elf::PF_R | PF_W, align: 8 },
+            ],
+            symbols: vec![
+                Symbol { name: String::from("main"), section: Some(0), value: 0, size: 16, function: true, public: true },
+                Symbol { name: String::from("counter"), section: Some(1), value: 0, size: 8, function: false, public: false },
+                Symbol { name: String::from("ExitProcess"), section: None, value: 0, size: 0, function: true, public: true },
+            ],
+            relocations: vec![
+                Relocation { section: 0, at: 1, target: Target::Symbol(String::from("ExitProcess")), reloc: elf::R_X86_64_PLT32 },
+                Relocation { section: 0, at: 7, target: Target::Section(1), reloc: elf::R_X86_64_64 },
+            ],
+        };
+
+        let mut bytes = Vec::new();
+
+        object.write_coff(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn object_sections() {
+        let bytes = object();
+
+        assert_eq!(u16_at(&bytes, 0), MACHINE_AMD64);
+        assert_eq!(u16_at(&bytes, 2), 2);
+        assert_eq!(u16_at(&bytes, 16), 0);
+
+        let sections = sections(&bytes, COFF_SIZE as usize, 2);
+        let table = u32_at(&bytes, 8) as usize;
+        let strtab = table + u32_at(&bytes, 12) as usize * SYMBOL_SIZE as usize;
+
+        // alignment is encoded as its logarithm plus one in bits 20 to 23, 16 bytes for code and 8 for the counters
+        assert_eq!(sections[0].5, SCN_CNT_CODE | SCN_MEM_EXECUTE | SCN_MEM_READ | 5 << 20);
+        assert_eq!(sections[1].5, SCN_CNT_UNINITIALIZED_DATA | SCN_MEM_READ | SCN_MEM_WRITE | 4 << 20);
+        assert_eq!(&bytes[sections[0].4 as usize..sections[0].4 as usize + 16], &[0xe8, 0, 0, 0, 0, 0x48, 0xb8, 8, 0, 0, 0, 0, 0, 0, 0, 0xc3]);
+        assert_eq!((sections[1].3, sections[1].4), (8, 0));
+
+        // names longer than 8 bytes are an offset into the string table
+        assert_eq!(sections[1].0, "/4");
+        assert_eq!(string_at(&bytes, strtab + 4), ".bss.counters");
+    }
+
+    #[test]
+    fn object_relocations() {
+        let bytes = object();
+        let header = COFF_SIZE as usize;
+        let (relocations, count) = (u32_at(&bytes, header + 24) as usize, u16_at(&bytes, header + 32));
+
+        assert_eq!(count, 2);
+
+        // [VirtualAddress SymbolTableIndex Type], every section takes a symbol and an auxiliary record before the labels
+        let entries: Vec<(u32, u32, u16)> = (0..2)
+            .map(|index| relocations + index * RELOC_SIZE as usize)
+            .map(|entry| (u32_at(&bytes, entry), u32_at(&bytes, entry + 4), u16_at(&bytes, entry + 8)))
+            .collect();
+
+        assert_eq!(entries, vec![(1, 6, REL_AMD64_REL32), (7, 2, REL_AMD64_ADDR64)]);
+
+        let table = u32_at(&bytes, 8) as usize;
+        let symbol = |index: usize| table + index * SYMBOL_SIZE as usize;
+
+        // IMAGE_SYM_CLASS_EXTERNAL for public and external symbols, IMAGE_SYM_CLASS_STATIC for the rest
+        assert_eq!(u32_at(&bytes, 12), 7);
+        assert_eq!((&bytes[symbol(4)..symbol(4) + 4], u16_at(&bytes, symbol(4) + 12), u16_at(&bytes, symbol(4) + 14), bytes[symbol(4) + 16]), (&b"main"[..], 1, 0x20, 2));
+        assert_eq!((u16_at(&bytes, symbol(5) + 12), bytes[symbol(5) + 16]), (2, 3));
+        assert_eq!((u16_at(&bytes, symbol(6) + 12), bytes[symbol(6) + 16]), (0, 2));
+    }
+
+    #[test]
+    fn sign_extended_addresses() {
+        assert_eq!(relocation_type(elf::R_X86_64_32).unwrap(), REL_AMD64_ADDR32);
+        assert!(relocation_type(elf::R_X86_64_32S).is_err());
+    }
+}
+
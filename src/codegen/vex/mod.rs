@@ -0,0 +1,98 @@
+#[derive(Clone, Copy, PartialEq)]
+pub enum Map {
+    Of = 1,
+    Of38 = 2,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Prefix {
+    None = 0,
+    P66 = 1,
+    Pf3 = 2,
+}
+
+// vex fields are stored un-inverted, `encode` takes care of the one's complement
+pub struct Vex {
+    pub r: bool,
+    pub x: bool,
+    pub b: bool,
+    pub map: Map,
+    pub w: bool,
+    pub vvvv: u8,
+    pub l: bool,
+    pub pp: Prefix,
+}
+
+impl Vex {
+    pub fn new(map: Map, pp: Prefix, w: bool) -> Vex {
+        Vex {
+            r: false,
+            x: false,
+            b: false,
+            map,
+            w,
+            vvvv: 0,
+            l: false,
+            pp,
+        }
+    }
+
+    // https://en.wikipedia.org/wiki/VEX_prefix
+    // section 2.3 @ intel programmers manual vol 2
+    pub fn encode(&self) -> Vec<u8> {
+        let tail = ((!self.vvvv & 0xf) << 3) | ((self.l as u8) << 2) | self.pp as u8;
+
+        if !self.x && !self.b && !self.w && self.map == Map::Of {
+            // C5 [R vvvv L pp]
+            vec![0xc5, ((!self.r as u8) << 7) | tail]
+        } else {
+            // C4 [R X B mmmmm] [W vvvv L pp]
+            vec![
+                0xc4,
+                ((!self.r as u8) << 7) | ((!self.x as u8) << 6) | ((!self.b as u8) << 5) | self.map as u8,
+                ((self.w as u8) << 7) | tail,
+            ]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn two_byte_form() {
+        let mut vex = Vex::new(Map::Of, Prefix::None, false);
+
+        vex.vvvv = 1;
+        vex.l = true;
+
+        assert_eq!(vex.encode(), [0xc5, 0xf4]);
+
+        vex.r = true;
+
+        assert_eq!(vex.encode(), [0xc5, 0x74]);
+    }
+
+    #[test]
+    fn three_byte_form() {
+        let mut vex = Vex::new(Map::Of38, Prefix::P66, false);
+
+        vex.vvvv = 1;
+        vex.l = true;
+
+        assert_eq!(vex.encode(), [0xc4, 0xe2, 0x75]);
+
+        // W, X and B only fit in the three byte form
+        for (field, expected) in [(0, [0xc4, 0xe1, 0xfc]), (1, [0xc4, 0xa1, 0x7c]), (2, [0xc4, 0xc1, 0x7c])] {
+            let mut vex = Vex::new(Map::Of, Prefix::None, field == 0);
+
+            vex.x = field == 1;
+            vex.b = field == 2;
+            vex.l = true;
+
+            assert_eq!(vex.encode(), expected);
+        }
+    }
+}
+
@@ -0,0 +1,480 @@
+use crate::parser::lexer::{Keyword, Register, Token};
+use crate::parser::Size;
+
+use std::process::ExitStatus;
+use std::fmt;
+use std::io;
+
+// every error the assembler reports, grouped by the stage that finds it, the span is attached when it is reported
+#[derive(Debug)]
+pub enum Error {
+    // lexer
+    UnterminatedString,
+
+    // parser
+    Expected(&'static str),
+    UnexpectedToken(Token),
+    NoSuchInstruction(String),
+    TooManyRegisters,
+    TooManyIndexes,
+    InvalidScale(i64),
+    EmptyTerm,
+    InvalidTerm(Vec<Token>),
+    NoSuchRounding(String),
+    InvalidBroadcast(String),
+    BroadcastNeedsMemory,
+    InvalidDecoration(Token),
+    MemoryOnly(Keyword),
+    EmptyExpression,
+    InvalidExpression,
+    Permission(Token),
+    OperandCount { expected: usize, got: usize },
+    Usage { message: &'static str, usage: &'static str },
+    UnknownFormat(String),
+    Displacement(i64),
+
+    // preprocessor
+    WrongFormat { directive: &'static str, formats: &'static str, help: Option<&'static str> },
+    Duplicate(&'static str),
+    UndefinedPublic(String),
+    UndefinedEntry(String),
+    ExternDefined(String),
+    NotASymbol(String),
+    Alignment,
+    UnalignedBase(usize),
+    ZeroBase,
+    PeBase(usize),
+    Org,
+
+    // codegen
+    NoSuchLabel(String),
+    NoSuchConstant(String),
+    NoSuchMacro(String),
+    ArgumentCount { expected: usize, got: usize },
+    OperandSize(Option<String>),
+    SizeNotSpecified,
+    ExpectedOperand(&'static str),
+    NotGeneralPurpose(Register),
+    NotSegment(Register),
+    InvalidOperands(&'static str),
+    NotRegister(&'static str),
+    LoadCs,
+    InvalidSize(Size),
+    SymbolSize(String),
+    Use64Only(String),
+    NotInUse64(&'static str),
+    ControlRegister { bits: u8, mode: u8 },
+    MixedAddress,
+    StackIndex,
+    RipIndex,
+    ScaledAddress16,
+    Address16,
+    Displacement16(i32),
+    Label16(String),
+    MaskK0,
+    ZeroingWithoutMask,
+    ZeroingMemory,
+    ZeroingMask,
+    MaskNotDestination,
+    RoundingNotLast,
+    NoEvexForm,
+    Broadcast(i32),
+    NoBroadcast,
+    RoundingRegisters,
+    RoundingZmm,
+    RoundingNotSae(&'static str),
+    SaeOnly(&'static str),
+    BroadcastSource(Register),
+    RelocationOutsideLabel,
+    ExternAddress(String),
+    MachOAddress(String),
+    AbsoluteAddress(String),
+    CoffRelocation,
+    SignExtended(String),
+    ImmediateRange(i64),
+
+    // output
+    Io(io::Error),
+    Artifact(faerie::ArtifactError),
+    TooManyRelocations(String),
+    Linker(io::Error),
+    LinkFailed(ExitStatus),
+
+    // a run stops with these once its errors are reported, they are never shown themselves
+    Reported(usize),
+    Limit(usize),
+}
+
+impl Error {
+    // codes are stable, the hundreds digit is the stage
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::UnterminatedString => "E0001",
+            Error::Expected(_) => "E0101",
+            Error::UnexpectedToken(_) => "E0102",
+            Error::NoSuchInstruction(_) => "E0103",
+            Error::TooManyRegisters => "E0104",
+            Error::TooManyIndexes => "E0105",
+            Error::InvalidScale(_) => "E0106",
+            Error::EmptyTerm => "E0107",
+            Error::InvalidTerm(_) => "E0108",
+            Error::NoSuchRounding(_) => "E0109",
+            Error::InvalidBroadcast(_) => "E0110",
+            Error::BroadcastNeedsMemory => "E0111",
+            Error::InvalidDecoration(_) => "E0112",
+            Error::MemoryOnly(_) => "E0113",
+            Error::EmptyExpression => "E0114",
+            Error::InvalidExpression => "E0115",
+            Error::Permission(_) => "E0116",
+            Error::OperandCount { .. } => "E0117",
+            Error::Usage { .. } => "E0118",
+            Error::UnknownFormat(_) => "E0119",
+            Error::Displacement(_) => "E0120",
+            Error::WrongFormat { .. } => "E0201",
+            Error::Duplicate(_) => "E0202",
+            Error::UndefinedPublic(_) => "E0203",
+            Error::UndefinedEntry(_) => "E0204",
+            Error::ExternDefined(_) => "E0205",
+            Error::NotASymbol(_) => "E0206",
+            Error::Alignment => "E0207",
+            Error::UnalignedBase(_) => "E0208",
+            Error::ZeroBase => "E0209",
+            Error::PeBase(_) => "E0210",
+            Error::Org => "E0211",
+            Error::NoSuchLabel(_) => "E0301",
+            Error::NoSuchConstant(_) => "E0302",
+            Error::NoSuchMacro(_) => "E0303",
+            Error::ArgumentCount { .. } => "E0304",
+            Error::OperandSize(_) => "E0305",
+            Error::SizeNotSpecified => "E0306",
+            Error::ExpectedOperand(_) => "E0307",
+            Error::NotGeneralPurpose(_) => "E0308",
+            Error::NotSegment(_) => "E0309",
+            Error::InvalidOperands(_) => "E0310",
+            Error::NotRegister(_) => "E0311",
+            Error::LoadCs => "E0312",
+            Error::InvalidSize(_) => "E0313",
+            Error::SymbolSize(_) => "E0314",
+            Error::Use64Only(_) => "E0315",
+            Error::NotInUse64(_) => "E0316",
+            Error::ControlRegister { .. } => "E0317",
+            Error::MixedAddress => "E0318",
+            Error::StackIndex => "E0319",
+            Error::RipIndex => "E0320",
+            Error::ScaledAddress16 => "E0321",
+            Error::Address16 => "E0322",
+            Error::Displacement16(_) => "E0323",
+            Error::Label16(_) => "E0324",
+            Error::MaskK0 => "E0325",
+            Error::ZeroingWithoutMask => "E0326",
+            Error::ZeroingMemory => "E0327",
+            Error::ZeroingMask => "E0328",
+            Error::MaskNotDestination => "E0329",
+            Error::RoundingNotLast => "E0330",
+            Error::NoEvexForm => "E0331",
+            Error::Broadcast(_) => "E0332",
+            Error::NoBroadcast => "E0333",
+            Error::RoundingRegisters => "E0334",
+            Error::RoundingZmm => "E0335",
+            Error::RoundingNotSae(_) => "E0336",
+            Error::SaeOnly(_) => "E0337",
+            Error::BroadcastSource(_) => "E0338",
+            Error::RelocationOutsideLabel => "E0339",
+            Error::ExternAddress(_) => "E0340",
+            Error::MachOAddress(_) => "E0341",
+            Error::AbsoluteAddress(_) => "E0342",
+            Error::CoffRelocation => "E0343",
+            Error::SignExtended(_) => "E0344",
+            Error::ImmediateRange(_) => "E0345",
+            Error::Io(_) => "E0401",
+            Error::Artifact(_) => "E0402",
+            Error::TooManyRelocations(_) => "E0403",
+            // E0404 was a truncated object from faerie
+            Error::Linker(_) => "E0405",
+            Error::LinkFailed(_) => "E0406",
+            Error::Reported(_) => "E0901",
+            Error::Limit(_) => "E0902",
+        }
+    }
+
+    pub fn help(&self) -> Option<String> {
+        match self {
+            Error::UnterminatedString => Some(String::from("strings end with the same quote they start with, on the same line")),
+            Error::TooManyRegisters | Error::TooManyIndexes | Error::InvalidScale(_) | Error::EmptyTerm | Error::InvalidTerm(_) => Some(String::from("addresses look like `[base + index * scale + displacement]`")),
+            Error::NoSuchRounding(_) | Error::InvalidBroadcast(_) | Error::InvalidDecoration(_) => Some(String::from("avx-512 operands take a mask like `{k1}`, `{z}`, a broadcast like `{1to16}` or rounding like `{rn-sae}`")),
+            Error::Usage { usage, .. } => Some(format!("usage: {}", usage)),
+            Error::UnknownFormat(_) => Some(String::from("supported formats are binary, ELF64, ELF64 executable, ELF64 PIE, ELF64 shared, PE64, PE64 GUI, MS64 COFF and MachO64")),
+            Error::WrongFormat { help, .. } => help.map(String::from),
+            Error::UndefinedPublic(name) => Some(format!("define it with `{}:` or remove it from `public`", name)),
+            Error::UndefinedEntry(name) => Some(format!("define it with `{}:` or name another label with `entry`", name)),
+            Error::NoSuchConstant(name) => Some(format!("define it with `{} equ <VALUE>`", name)),
+            Error::NoSuchMacro(name) => Some(format!("define it with `macro {} [ARGS] {{ ... }}` before it is used", name)),
+            Error::Org => Some(String::from("executables are placed with `--base` and the linker places the sections of objects")),
+            Error::SizeNotSpecified => Some(String::from("put `byte`, `word`, `dword` or `qword` before the memory operand")),
+            Error::Use64Only(_) | Error::NotInUse64(_) | Error::ControlRegister { .. } => Some(String::from("the mode is chosen with `use16`, `use32` or `use64`")),
+            Error::ExternAddress(name) => Some(format!("use `dq {}` for its address", name)),
+            Error::AbsoluteAddress(name) => Some(format!("use `[rip + {}]`, `lea` or `dq`", name)),
+            Error::SignExtended(name) => Some(format!("use `[rip + {}]`, `lea` or `mov` into a 64-bit register", name)),
+            Error::ImmediateRange(_) => Some(String::from("`mov` it into a 64-bit register first, only `mov` takes a 64-bit immediate")),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnterminatedString => write!(f, "unterminated string"),
+            Error::Expected(expected) => write!(f, "expected {}", expected),
+            Error::UnexpectedToken(token) => write!(f, "unexpected token `{}`", token),
+            Error::NoSuchInstruction(ident) => write!(f, "no such instruction `{}`", ident),
+            Error::TooManyRegisters => write!(f, "too many registers in address"),
+            Error::TooManyIndexes => write!(f, "too many index registers in address"),
+            Error::InvalidScale(scale) => write!(f, "invalid scale `{}`, expected 1, 2, 4 or 8", scale),
+            Error::EmptyTerm => write!(f, "empty term in address"),
+            Error::InvalidTerm(term) => write!(f, "invalid address term `{}`", term.iter().map(Token::to_string).collect::<Vec<String>>().join(" ")),
+            Error::NoSuchRounding(mode) => write!(f, "no such rounding mode `{}`", mode),
+            Error::InvalidBroadcast(broadcast) => write!(f, "invalid broadcast `{{{}}}`", broadcast),
+            Error::BroadcastNeedsMemory => write!(f, "broadcast needs a memory operand"),
+            Error::InvalidDecoration(decoration) => write!(f, "invalid operand decoration `{{{}}}`", decoration),
+            Error::MemoryOnly(keyword) => write!(f, "`{}` can only be applied to memory operands", keyword),
+            Error::EmptyExpression => write!(f, "empty expression"),
+            Error::InvalidExpression => write!(f, "invalid expression"),
+            Error::Permission(token) => write!(f, "unexpected token `{}`, expected `readable`, `writeable` or `executable`", token),
+            Error::OperandCount { expected, got } => write!(f, "expected {} operands but got {}", expected, got),
+            Error::Usage { message, .. } => write!(f, "{}", message),
+            Error::UnknownFormat(format) => write!(f, "unknown format `{}`", format),
+            Error::Displacement(disp) => write!(f, "displacement `{:#x}` does not fit in 32 bits", disp),
+            Error::WrongFormat { directive, formats, .. } => write!(f, "{} can only be used with {}", directive, formats),
+            Error::Duplicate(directive) => write!(f, "{} can only be specified once", directive),
+            Error::UndefinedPublic(name) => write!(f, "public symbol `{}` is never defined", name),
+            Error::UndefinedEntry(name) => write!(f, "entry label `{}` is not defined", name),
+            Error::ExternDefined(name) => write!(f, "external symbol `{}` is also defined as a label", name),
+            Error::NotASymbol(name) => write!(f, "`size` needs a symbol but `{}` is not one", name),
+            Error::Alignment => write!(f, "expected power of two as alignment"),
+            Error::UnalignedBase(base) => write!(f, "base address `{:#x}` of an executable has to be page aligned", base),
+            Error::ZeroBase => write!(f, "position independent images need the first page for their headers, their base address cant be zero"),
+            Error::PeBase(base) => write!(f, "PE64 images are loaded at a multiple of 0x10000 with their headers in the first page, base address `{:#x}` has to be one page past that", base),
+            Error::Org => write!(f, "`org` can only be used with `format binary`"),
+            Error::NoSuchLabel(name) => write!(f, "no such label `{}`", name),
+            Error::NoSuchConstant(name) => write!(f, "no such constant `{}`", name),
+            Error::NoSuchMacro(name) => write!(f, "no such macro `{}`", name),
+            Error::ArgumentCount { expected, got } => write!(f, "expected {} arguments but got {}", expected, got),
+            Error::OperandSize(None) => write!(f, "operand sizes do not match"),
+            Error::OperandSize(Some(reg)) => write!(f, "operand sizes do not match, `{}` has the wrong size", reg),
+            Error::SizeNotSpecified => write!(f, "operand size not specified"),
+            Error::ExpectedOperand(expected) => write!(f, "expected {}", expected),
+            Error::NotGeneralPurpose(reg) => write!(f, "expected general purpose register but got `{}`", reg),
+            Error::NotSegment(reg) => write!(f, "`{}` is not a segment register", reg),
+            Error::InvalidOperands(inst) => write!(f, "invalid operands for {}", inst),
+            Error::NotRegister(inst) => write!(f, "the destination of `{}` has to be a register", inst),
+            Error::LoadCs => write!(f, "`cs` cant be loaded with `mov`"),
+            Error::InvalidSize(size) => write!(f, "invalid operand size `{}`", format!("{:?}", size).to_lowercase()),
+            Error::SymbolSize(name) => write!(f, "expected positive integer as size of `{}`", name),
+            Error::Use64Only(what) => write!(f, "{} can only be used in use64", what),
+            Error::NotInUse64(what) => write!(f, "{} cant be used in use64", what),
+            Error::ControlRegister { bits, mode } => write!(f, "control and debug registers can only be moved to or from {}-bit registers in use{}", bits, mode),
+            Error::MixedAddress => write!(f, "address registers must all be general purpose registers of the same size"),
+            Error::StackIndex => write!(f, "`esp` and `rsp` cant be used as index registers"),
+            Error::RipIndex => write!(f, "`rip` relative addresses cant have an index register"),
+            Error::ScaledAddress16 => write!(f, "16-bit addresses cant be scaled"),
+            Error::Address16 => write!(f, "16-bit addresses only allow `bx` or `bp` combined with `si` or `di`"),
+            Error::Displacement16(disp) => write!(f, "displacement `{:#x}` does not fit in 16 bits", disp),
+            Error::Label16(label) => write!(f, "address of `{}` does not fit in 16 bits", label),
+            Error::MaskK0 => write!(f, "`k0` cant be used as a write mask"),
+            Error::ZeroingWithoutMask => write!(f, "zero masking `{{z}}` needs a write mask"),
+            Error::ZeroingMemory => write!(f, "zero masking `{{z}}` cant be used on memory destinations"),
+            Error::ZeroingMask => write!(f, "zero masking `{{z}}` cant be used with a mask destination"),
+            Error::MaskNotDestination => write!(f, "write masks are only allowed on the destination of avx-512 instructions"),
+            Error::RoundingNotLast => write!(f, "rounding control is only allowed as the last operand of avx-512 instructions"),
+            Error::NoEvexForm => write!(f, "instruction has no evex form, zmm registers, registers above 15, masking, broadcast and rounding need avx-512"),
+            Error::Broadcast(count) => write!(f, "expected broadcast `{{1to{}}}`", count),
+            Error::NoBroadcast => write!(f, "instruction does not support broadcast"),
+            Error::RoundingRegisters => write!(f, "rounding control needs register operands"),
+            Error::RoundingZmm => write!(f, "rounding control needs zmm operands"),
+            Error::RoundingNotSae(inst) => write!(f, "`{}` takes a rounding mode, not `{{sae}}`", inst),
+            Error::SaeOnly(inst) => write!(f, "`{}` only supports `{{sae}}`", inst),
+            Error::BroadcastSource(reg) => write!(f, "expected xmm register as broadcast source but got `{}`", reg),
+            Error::RelocationOutsideLabel => write!(f, "relocations need a label to be defined in"),
+            Error::ExternAddress(name) => write!(f, "extrn symbol `{}` can only be called or jumped to", name),
+            Error::MachOAddress(name) => write!(f, "Mach-O objects can only refer to `{}` with `rip` relative addresses in code or `dq` in data", name),
+            Error::AbsoluteAddress(name) => write!(f, "absolute address of `{}` has to be 64-bit in position independent code", name),
+            Error::CoffRelocation => write!(f, "coff objects can only relocate 32-bit and 64-bit addresses"),
+            Error::SignExtended(name) => write!(f, "coff objects cant relocate the sign extended 32-bit address of `{}`", name),
+            Error::ImmediateRange(integer) => write!(f, "immediate `{}` changes its value when it is sign extended from 32 bits", integer),
+            Error::Io(err) => write!(f, "{}", err),
+            Error::Artifact(err) => write!(f, "{}", err),
+            Error::TooManyRelocations(section) => write!(f, "section `{}` has too many relocations for a coff object", section),
+            Error::Linker(err) => write!(f, "failed to run `ld`: {}", err),
+            Error::LinkFailed(status) => write!(f, "`ld` exited with {}", status),
+            Error::Reported(errors) => write!(f, "{} errors were reported", errors),
+            Error::Limit(limit) => write!(f, "stopping after {} errors", limit),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl From<faerie::ArtifactError> for Error {
+    fn from(err: faerie::ArtifactError) -> Error {
+        Error::Artifact(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    fn errors() -> Vec<Error> {
+        let name = || String::from("f");
+
+        vec![
+            Error::UnterminatedString,
+            Error::Expected("`]`"),
+            Error::UnexpectedToken(Token::Ident(name())),
+            Error::NoSuchInstruction(name()),
+            Error::TooManyRegisters,
+            Error::TooManyIndexes,
+            Error::InvalidScale(3),
+            Error::EmptyTerm,
+            Error::InvalidTerm(vec![Token::Register(Register::Rax)]),
+            Error::NoSuchRounding(name()),
+            Error::InvalidBroadcast(name()),
+            Error::BroadcastNeedsMemory,
+            Error::InvalidDecoration(Token::Ident(name())),
+            Error::MemoryOnly(Keyword::Byte),
+            Error::EmptyExpression,
+            Error::InvalidExpression,
+            Error::Permission(Token::Ident(name())),
+            Error::OperandCount { expected: 2, got: 1 },
+            Error::Usage { message: "expected a name", usage: "segment NAME" },
+            Error::UnknownFormat(name()),
+            Error::Displacement(0x1_0000_0000),
+            Error::WrongFormat { directive: "`entry`", formats: "executables", help: None },
+            Error::Duplicate("`format`"),
+            Error::UndefinedPublic(name()),
+            Error::UndefinedEntry(name()),
+            Error::ExternDefined(name()),
+            Error::NotASymbol(name()),
+            Error::Alignment,
+            Error::UnalignedBase(0x1234),
+            Error::ZeroBase,
+            Error::PeBase(0x1234),
+            Error::Org,
+            Error::NoSuchLabel(name()),
+            Error::NoSuchConstant(name()),
+            Error::NoSuchMacro(name()),
+            Error::ArgumentCount { expected: 2, got: 1 },
+            Error::OperandSize(None),
+            Error::SizeNotSpecified,
+            Error::ExpectedOperand("a register"),
+            Error::NotGeneralPurpose(Register::Cs),
+            Error::NotSegment(Register::Rax),
+            Error::InvalidOperands("`mov`"),
+            Error::NotRegister("lea"),
+            Error::LoadCs,
+            Error::InvalidSize(Size::Byte),
+            Error::SymbolSize(name()),
+            Error::Use64Only(name()),
+            Error::NotInUse64("`rip`"),
+            Error::ControlRegister { bits: 64, mode: 64 },
+            Error::MixedAddress,
+            Error::StackIndex,
+            Error::RipIndex,
+            Error::ScaledAddress16,
+            Error::Address16,
+            Error::Displacement16(0x10000),
+            Error::Label16(name()),
+            Error::MaskK0,
+            Error::ZeroingWithoutMask,
+            Error::ZeroingMemory,
+            Error::ZeroingMask,
+            Error::MaskNotDestination,
+            Error::RoundingNotLast,
+            Error::NoEvexForm,
+            Error::Broadcast(16),
+            Error::NoBroadcast,
+            Error::RoundingRegisters,
+            Error::RoundingZmm,
+            Error::RoundingNotSae("vaddps"),
+            Error::SaeOnly("vmaxps"),
+            Error::BroadcastSource(Register::Rax),
+            Error::RelocationOutsideLabel,
+            Error::ExternAddress(name()),
+            Error::MachOAddress(name()),
+            Error::AbsoluteAddress(name()),
+            Error::CoffRelocation,
+            Error::SignExtended(name()),
+            Error::ImmediateRange(0x8000_0000),
+            Error::Io(io::Error::from(io::ErrorKind::NotFound)),
+            Error::Artifact(faerie::ArtifactError::Undeclared(name())),
+            Error::TooManyRelocations(String::from(".text")),
+            Error::Linker(io::Error::from(io::ErrorKind::NotFound)),
+            Error::LinkFailed(ExitStatus::from_raw(1 << 8)),
+            Error::Reported(2),
+            Error::Limit(20),
+        ]
+    }
+
+    // the digit after `E0` says which stage found the error
+    fn stage(error: &Error) -> &'static str {
+        match error {
+            Error::UnterminatedString => "0",
+            Error::Expected(_) | Error::UnexpectedToken(_) | Error::NoSuchInstruction(_) | Error::TooManyRegisters | Error::TooManyIndexes | Error::InvalidScale(_) | Error::EmptyTerm |
+            Error::InvalidTerm(_) | Error::NoSuchRounding(_) | Error::InvalidBroadcast(_) | Error::BroadcastNeedsMemory | Error::InvalidDecoration(_) | Error::MemoryOnly(_) |
+            Error::EmptyExpression | Error::InvalidExpression | Error::Permission(_) | Error::OperandCount { .. } | Error::Usage { .. } | Error::UnknownFormat(_) | Error::Displacement(_) => "1",
+            Error::WrongFormat { .. } | Error::Duplicate(_) | Error::UndefinedPublic(_) | Error::UndefinedEntry(_) | Error::ExternDefined(_) | Error::NotASymbol(_) | Error::Alignment |
+            Error::UnalignedBase(_) | Error::ZeroBase | Error::PeBase(_) | Error::Org => "2",
+            Error::Io(_) | Error::Artifact(_) | Error::TooManyRelocations(_) | Error::Linker(_) | Error::LinkFailed(_) => "4",
+            Error::Reported(_) | Error::Limit(_) => "9",
+            _ => "3",
+        }
+    }
+
+    #[test]
+    fn codes() {
+        let errors = errors();
+        let mut codes: Vec<&str> = errors.iter().map(Error::code).collect();
+
+        for (error, code) in errors.iter().zip(&codes) {
+            assert!(code.len() == 5 && code.starts_with("E0") && code[1..].bytes().all(|byte| byte.is_ascii_digit()), "{:?} has the code {}", error, code);
+            assert_eq!(&code[2..3], stage(error), "{:?} has the code {}", error, code);
+        }
+
+        codes.sort();
+        codes.dedup();
+
+        assert_eq!(codes.len(), errors.len());
+        assert!(!codes.contains(&"E0404"));
+    }
+
+    #[test]
+    fn stable_codes() {
+        // codes are stable, retired ones are not reused
+        assert_eq!(Error::UnterminatedString.code(), "E0001");
+        assert_eq!(Error::UndefinedEntry(String::from("_start")).code(), "E0204");
+        assert_eq!(Error::NoSuchLabel(String::from("f")).code(), "E0301");
+        assert_eq!(Error::SignExtended(String::from("f")).code(), "E0344");
+        assert_eq!(Error::Linker(io::Error::from(io::ErrorKind::NotFound)).code(), "E0405");
+        assert_eq!(Error::LinkFailed(ExitStatus::from_raw(1 << 8)).code(), "E0406");
+        assert_eq!(Error::Limit(20).code(), "E0902");
+    }
+
+    #[test]
+    fn messages() {
+        assert_eq!(Error::UndefinedEntry(String::from("_start")).to_string(), "entry label `_start` is not defined");
+        assert_eq!(Error::UndefinedEntry(String::from("_start")).help().unwrap(), "define it with `_start:` or name another label with `entry`");
+        assert_eq!(Error::Broadcast(16).to_string(), "expected broadcast `{1to16}`");
+        assert_eq!(Error::InvalidSize(Size::Qword).to_string(), "invalid operand size `qword`");
+        assert_eq!(Error::LinkFailed(ExitStatus::from_raw(1 << 8)).to_string(), "`ld` exited with exit status: 1");
+        assert!(Error::NoSuchLabel(String::from("f")).help().is_none());
+    }
+}
+
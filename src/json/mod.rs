@@ -0,0 +1,20 @@
+// the map and diagnostics are written as json without pulling in a serializer
+pub fn string(string: &str) -> String {
+    let mut escaped = String::from("\"");
+
+    for character in string.chars() {
+        match character {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            character if (character as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", character as u32)),
+            character => escaped.push(character),
+        }
+    }
+
+    escaped + "\""
+}
+
+pub fn number(number: Option<usize>) -> String {
+    number.map(|number| number.to_string()).unwrap_or_else(|| String::from("null"))
+}
+
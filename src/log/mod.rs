@@ -1,11 +1,318 @@
+use crate::parser::lexer::Span;
+use crate::error::Error;
+use crate::json::string;
+
 use colored::*;
+use clap::ValueEnum;
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::io::IsTerminal;
+use std::fs;
+
+static ERRORS: AtomicUsize = AtomicUsize::new(0);
+static WARNINGS: AtomicUsize = AtomicUsize::new(0);
+static JSON: AtomicBool = AtomicBool::new(false);
+
+// every warning is in one of these categories, `all` only picks all of them on the command line
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy)]
+pub enum Lint {
+    All,
+    UnusedLabel,
+    UnusedConstant,
+    UnusedMacro,
+    RedefinedConstant,
+    ImmediateTruncated,
+    UnreachableCode,
+    ShadowedConstant,
+}
+
+impl Lint {
+    pub fn name(&self) -> String {
+        self.to_possible_value().map(|value| value.get_name().to_string()).unwrap_or_default()
+    }
+}
+
+// `-W` wins over `-A` and a single lint wins over `all`, every lint warns unless it is allowed
+pub struct Lints {
+    pub warn: Vec<Lint>,
+    pub allow: Vec<Lint>,
+    pub deny: bool,
+}
+
+impl Lints {
+    fn is_allowed(&self, lint: Lint) -> bool {
+        if self.warn.contains(&lint) {
+            false
+        } else if self.allow.contains(&lint) {
+            true
+        } else {
+            !self.warn.contains(&Lint::All) && self.allow.contains(&Lint::All)
+        }
+    }
+}
+
+// notes are other places that explain the error, like the macro an instruction was expanded from, errors have a code and maybe a help
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub message: String,
+    pub notes: Vec<(Span, String)>,
+    pub lint: Option<Lint>,
+    pub code: Option<&'static str>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(span: Span, err: &Error, notes: Vec<(Span, String)>) -> Diagnostic {
+        Diagnostic {
+            span,
+            message: err.to_string(),
+            notes,
+            lint: None,
+            code: Some(err.code()),
+            help: err.help(),
+        }
+    }
+}
+
+// errors and warnings reported so far, a run stops once `limit` errors are reported unless it is zero
+pub struct Diagnostics {
+    pub errors: Vec<Diagnostic>,
+    pub warnings: Vec<Diagnostic>,
+    pub limit: usize,
+    pub lints: Lints,
+}
+
+impl Diagnostics {
+    pub fn new(limit: usize, lints: Lints) -> Diagnostics {
+        Diagnostics {
+            errors: Vec::new(),
+            warnings: Vec::new(),
+            limit,
+            lints,
+        }
+    }
+
+    // warnings never stop a run by themselves, denied ones are errors and stop it before anything is written
+    pub fn warn(&mut self, lint: Lint, span: Span, message: String, notes: Vec<(Span, String)>) {
+        let diagnostic = Diagnostic {
+            span,
+            message,
+            notes,
+            lint: Some(lint),
+            code: None,
+            help: None,
+        };
+
+        if self.lints.is_allowed(lint) {
+            return;
+        }
+
+        let reported = if self.lints.deny { &mut self.errors } else { &mut self.warnings };
+
+        if !reported.contains(&diagnostic) {
+            reported.push(diagnostic);
+        }
+    }
+
+    // both passes and the scan before them see the same mistakes, each is only reported once
+    pub fn report(&mut self, span: Span, err: Error, notes: Vec<(Span, String)>) -> Result<(), Error> {
+        let diagnostic = Diagnostic::error(span, &err, notes);
+
+        if !self.errors.contains(&diagnostic) {
+            self.errors.push(diagnostic);
+        }
+
+        if self.is_exhausted() {
+            return Err(Error::Limit(self.limit));
+        }
+
+        Ok(())
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.limit != 0 && self.errors.len() >= self.limit
+    }
+}
+
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy)]
+pub enum Color {
+    Auto,
+    Always,
+    Never,
+}
+
+pub fn color(color: Color) {
+    match color {
+        Color::Auto => colored::control::set_override(std::io::stdout().is_terminal()),
+        Color::Always => colored::control::set_override(true),
+        Color::Never => colored::control::set_override(false),
+    }
+}
+
+// `json` prints every message as a line of json for editors and ci instead of text
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy)]
+pub enum MessageFormat {
+    Human,
+    Json,
+}
+
+pub fn message_format(format: MessageFormat) {
+    JSON.store(format == MessageFormat::Json, Ordering::Relaxed);
+}
+
+fn is_json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
 
 pub fn info(message: &str) {
+    if is_json() {
+        println!("{{\"severity\": \"info\", \"code\": null, \"message\": {}, \"file\": null, \"line\": null, \"column\": null, \"span\": null, \"notes\": [], \"help\": null}}", string(message));
+        return;
+    }
+
     println!("> {}: {}", "info".green(), message);
 }
 
-pub fn error(prefix: &str, message: &str) {
-    println!("> {}:{}: {}", prefix, "error".red(), message);
+// tabs are shown as four spaces so carets line up under them
+fn width(text: &str) -> usize {
+    text.chars().map(|character| if character == '\t' { 4 } else { 1 }).sum()
+}
+
+// the columns of a span on its line, kept inside the line and at least one wide
+fn columns(span: &Span, text: &str) -> (usize, usize) {
+    let length = text.chars().count();
+    let start = (span.start.max(1) - 1).min(length);
+
+    (start, (span.end.max(span.start + 1) - 1).min(length).max(start + 1))
+}
+
+// file level spans have no line to show
+fn source(span: &Span) -> Option<String> {
+    match span.line {
+        0 => None,
+        line => fs::read_to_string(&*span.file).ok().and_then(|source| source.lines().nth(line - 1).map(String::from)),
+    }
+}
+
+// [--> file:line:column] and the line with the span underlined
+fn snippet(span: &Span, underline: &str, color: colored::Color) {
+    match source(span) {
+        Some(text) => {
+            let (start, end) = columns(span, &text);
+            let gutter = " ".repeat(span.line.to_string().len());
+            let prefix: String = text.chars().take(start).collect();
+            let marked: String = text.chars().skip(start).take(end - start).collect();
+            let underline = underline.repeat(width(&marked).max(1));
+
+            println!("{}{} {}:{}:{}", gutter, "-->".blue().bold(), span.file, span.line, start + 1);
+            println!("{} {}", gutter, "|".blue().bold());
+            println!("{} {} {}", span.line.to_string().blue().bold(), "|".blue().bold(), text.replace('\t', "    "));
+            println!("{} {} {}{}", gutter, "|".blue().bold(), " ".repeat(width(&prefix)), underline.color(color).bold());
+        },
+        _ => println!("{} {}", "-->".blue().bold(), span.file),
+    }
+}
+
+// the same columns the snippet underlines, `end` is exclusive
+fn location(span: &Span) -> String {
+    match source(span) {
+        Some(text) => {
+            let (start, end) = columns(span, &text);
+
+            format!("\"file\": {}, \"line\": {}, \"column\": {}, \"span\": {{\"start\": {}, \"end\": {}}}", string(&span.file), span.line, start + 1, start + 1, end + 1)
+        },
+        None => format!("\"file\": {}, \"line\": null, \"column\": null, \"span\": null", string(&span.file)),
+    }
+}
+
+// warnings use their lint as the code, denied ones too
+fn code(diagnostic: &Diagnostic) -> Option<String> {
+    diagnostic.code.map(String::from).or(diagnostic.lint.map(|lint| lint.name()))
+}
+
+fn json(severity: &str, diagnostic: &Diagnostic) {
+    let code = code(diagnostic);
+    let notes: Vec<String> = diagnostic.notes.iter()
+        .map(|(span, note)| format!("{{\"message\": {}, {}}}", string(note), location(span)))
+        .collect();
+
+    println!(
+        "{{\"severity\": {}, \"code\": {}, \"message\": {}, {}, \"notes\": [{}], \"help\": {}}}",
+        string(severity),
+        code.as_deref().map(string).unwrap_or_else(|| String::from("null")),
+        string(&diagnostic.message),
+        location(&diagnostic.span),
+        notes.join(", "),
+        diagnostic.help.as_deref().map(string).unwrap_or_else(|| String::from("null")),
+    );
+}
+
+fn render(severity: &str, color: colored::Color, diagnostic: &Diagnostic) {
+    if is_json() {
+        return json(severity, diagnostic);
+    }
+
+    match code(diagnostic) {
+        Some(code) => println!("{}: {}", format!("{}[{}]", severity, code).color(color).bold(), diagnostic.message.bold()),
+        None => println!("{}: {}", severity.color(color).bold(), diagnostic.message.bold()),
+    }
+
+    snippet(&diagnostic.span, "^", color);
+
+    for (span, note) in &diagnostic.notes {
+        println!("{}: {}", "note".green().bold(), note);
+        snippet(span, "-", colored::Color::Blue);
+    }
+
+    // warnings turned into errors say which lint they came from
+    match diagnostic.lint {
+        Some(lint) if severity == "error" => println!("{} {}: `{}` is denied by `--deny-warnings`", "=".blue().bold(), "note".bold(), lint.name()),
+        Some(lint) => println!("{} {}: `-A {}` allows this", "=".blue().bold(), "note".bold(), lint.name()),
+        None => {},
+    }
+
+    if let Some(help) = &diagnostic.help {
+        println!("{} {}: {}", "=".blue().bold(), "help".bold(), help);
+    }
+
+    println!();
 }
 
+pub fn error(diagnostic: &Diagnostic) {
+    ERRORS.fetch_add(1, Ordering::Relaxed);
+
+    render("error", colored::Color::Red, diagnostic);
+}
+
+pub fn warning(diagnostic: &Diagnostic) {
+    WARNINGS.fetch_add(1, Ordering::Relaxed);
+
+    render("warning", colored::Color::Yellow, diagnostic);
+}
+
+// successful runs still say how many warnings they had
+pub fn warned(file: &str) {
+    let warnings = WARNINGS.load(Ordering::Relaxed);
+
+    if warnings > 0 && !is_json() {
+        println!("{}: `{}` generated {} warning{}", "warning".yellow().bold(), file, warnings, if warnings == 1 { "" } else { "s" });
+    }
+}
+
+// the last line of a failed run counts every error reported in it, `exhausted` runs stopped at the error limit
+pub fn failed(file: &str, exhausted: bool) {
+    let errors = ERRORS.load(Ordering::Relaxed);
+
+    // json consumers count the error lines themselves, the exit status says the run failed
+    if is_json() {
+        return;
+    }
+
+    if exhausted {
+        println!("{}: stopping after {} errors, `--error-limit` changes how many are reported", "note".green().bold(), errors);
+    }
+
+    println!("{}: could not assemble `{}` due to {} previous error{}", "error".red().bold(), file, errors, if errors == 1 { "" } else { "s" });
+}
 
@@ -2,8 +2,12 @@ mod preprocessor;
 mod codegen;
 mod parser;
 mod log;
+mod error;
+mod json;
 
-use codegen::Codegen;
+use codegen::{Codegen, Flat, MapFormat};
+use parser::lexer::Span;
+use log::{Diagnostic, Diagnostics, Lint, Lints};
 use clap::{Parser, Subcommand};
 
 use std::process;
@@ -14,41 +18,120 @@ struct Args {
     #[command(subcommand)]
     command: Commands,
 
-    #[arg(long, short, action)]
-    debug: bool,
+    /// whether diagnostics are colored, `auto` only colors them in a terminal
+    #[arg(long, value_enum, default_value_t = log::Color::Auto, global = true)]
+    color: log::Color,
+
+    /// print diagnostics as colored text or as one json object per line
+    #[arg(long, value_enum, default_value_t = log::MessageFormat::Human, global = true)]
+    message_format: log::MessageFormat,
 }
 
 #[derive(Subcommand, Debug)]
 enum Commands {
-    Assemble { file: String },
+    Assemble {
+        file: String,
+
+        /// link the object file with the system `ld` instead of writing the executable directly
+        #[arg(long, action)]
+        ld: bool,
+
+        /// address executables are loaded at and flat binaries start at, `0x401000` and `0` by default
+        #[arg(long, value_parser = parse_address)]
+        base: Option<usize>,
+
+        /// how flat binaries are written, as raw bytes, intel hex, s-records or a c or rust array
+        #[arg(long, value_enum, default_value_t = Flat::Raw)]
+        emit: Flat,
+
+        /// write a listing with the address, encoded bytes and source of every instruction
+        #[arg(long)]
+        listing: Option<String>,
+
+        /// write a map of every symbol, constant and macro with its section, address, size and source line
+        #[arg(long)]
+        map: Option<String>,
+
+        /// how the map is written
+        #[arg(long, value_enum, default_value_t = MapFormat::Text)]
+        map_format: MapFormat,
+
+        /// stop after this many errors, zero reports all of them
+        #[arg(long, default_value_t = 20)]
+        error_limit: usize,
+
+        /// warn about a lint, all of them warn unless they are allowed
+        #[arg(short = 'W', value_enum, value_name = "LINT")]
+        warn: Vec<Lint>,
+
+        /// allow a lint so it doesnt warn
+        #[arg(short = 'A', value_enum, value_name = "LINT")]
+        allow: Vec<Lint>,
+
+        /// treat warnings as errors
+        #[arg(long, action)]
+        deny_warnings: bool,
+    },
+}
+
+fn parse_address(address: &str) -> Result<usize, String> {
+    match address.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => address.parse(),
+    }.map_err(|err| format!("invalid address `{}`: {}", address, err))
+}
+
+// scanning reports its errors before either pass, everything is shown in the order of the file
+fn report(diagnostics: &Diagnostics) {
+    let mut reported: Vec<(bool, &Diagnostic)> = diagnostics.errors.iter().map(|error| (true, error))
+        .chain(diagnostics.warnings.iter().map(|warning| (false, warning)))
+        .collect();
+
+    reported.sort_by_key(|(_, diagnostic)| diagnostic.span.line);
+
+    for (error, diagnostic) in reported {
+        if error {
+            log::error(diagnostic);
+        } else {
+            log::warning(diagnostic);
+        }
+    }
 }
 
 fn main() {
     let args = Args::parse();
 
+    log::color(args.color);
+    log::message_format(args.message_format);
+
     match args.command {
-        Commands::Assemble { file } => {
+        Commands::Assemble { file, ld, base, emit, listing, map, map_format, error_limit, warn, allow, deny_warnings } => {
             log::info(&format!("assembling `{}`", file));
 
-            let mut codegen = match Codegen::new(&file) {
+            let diagnostics = Diagnostics::new(error_limit, Lints { warn, allow, deny: deny_warnings });
+
+            let mut codegen = match Codegen::new(&file, base, diagnostics) {
                 Ok(codegen) => codegen,
                 Err(err) => {
-                    println!("{}", err);
+                    log::error(&Diagnostic::error(Span { file: file.as_str().into(), ..Span::default() }, &err, Vec::new()));
+                    log::failed(&file, false);
                     process::exit(1);
                 },
             };
 
-            if let Err(err) = codegen.emit(&file) {
-                log::error(&format!("{}:{}", file, codegen.line), &err.to_string());
-                process::exit(1);
-            }
-
-            if args.debug {
-                for (section, address) in codegen.preprocessor.offsets {
-                    log::info(&format!("{} -> {:#x?}", section, address));
+            if let Err(err) = codegen.emit(&file, ld, emit, listing.as_deref(), map.as_deref().map(|map| (map, map_format))) {
+                // errors while writing the output are the only ones not reported as they happen
+                if codegen.diagnostics.errors.is_empty() {
+                    codegen.diagnostics.errors.push(Diagnostic::error(codegen.span.clone(), &err, codegen.notes()));
                 }
+
+                report(&codegen.diagnostics);
+                log::failed(&file, codegen.diagnostics.is_exhausted());
+                process::exit(1);
             }
 
+            report(&codegen.diagnostics);
+            log::warned(&file);
             log::info("done");
         },
     }
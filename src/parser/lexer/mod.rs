@@ -1,7 +1,12 @@
+use crate::error::Error;
+
 use std::io::BufReader;
 use std::io::BufRead;
 use std::io::Seek;
+use std::io::SeekFrom;
 use std::fs::File;
+use std::rc::Rc;
+use std::fmt;
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub enum Keyword {
@@ -13,20 +18,121 @@ pub enum Keyword {
     Jb,
     Jmp,
     Cmp,
+    Call,
+    Ret,
 
     Mov,
     Add,
     Sub,
     Mul,
+    Lea,
 
     Pop,
     Push,
 
     Syscall,
+
+    Vaddps,
+    Vpxor,
+    Vmovdqu,
+    Vbroadcastss,
+    Vpshufb,
+    Vfmadd231ps,
+    Vzeroupper,
+
+    Vmaxps,
+    Vpxord,
+    Vpxorq,
+    Vmovdqu32,
+    Vmovdqu64,
+    Vpcmpeqd,
+    Kmovw,
+
+    Fld,
+    Fst,
+    Fstp,
+    Fild,
+    Fistp,
+    Fadd,
+    Faddp,
+    Fsub,
+    Fsubp,
+    Fmul,
+    Fmulp,
+    Fdiv,
+    Fdivp,
+    Fxch,
+    Fcomi,
+    Fsqrt,
+    Fsin,
+    Fcos,
+    Finit,
+    Fldcw,
+    Fnstcw,
+
+    Lgdt,
+    Lidt,
+    Ltr,
+    Invlpg,
+    Wrmsr,
+    Rdmsr,
+    Iretq,
+    Swapgs,
+
+    Use16,
+    Use32,
+    Use64,
+
+    Format,
+    Org,
+    Entry,
+
+    Public,
+    Extrn,
+    Needed,
+    Import,
+    Align,
+    Size,
+
+    Section,
+    Segment,
+    Readable,
+    Writeable,
+    Executable,
+
+    Db,
+    Dw,
+    Dd,
+    Dq,
+    Rb,
+    Rw,
+    Rd,
+    Rq,
+
+    Byte,
+    Word,
+    Dword,
+    Qword,
+    Tword,
+}
+
+impl fmt::Display for Keyword {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_lowercase())
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 pub enum Register {
+    Ax,
+    Bx,
+    Cx,
+    Dx,
+    Si,
+    Di,
+    Sp,
+    Bp,
+
     Eax,
     Ebx,
     Ecx,
@@ -35,6 +141,75 @@ pub enum Register {
     Edi,
     Esp,
     Ebp,
+
+    Rax,
+    Rbx,
+    Rcx,
+    Rdx,
+    Rsi,
+    Rdi,
+    Rsp,
+    Rbp,
+    Rip,
+    R8,
+    R9,
+    R10,
+    R11,
+    R12,
+    R13,
+    R14,
+    R15,
+
+    Xmm(u8),
+    Ymm(u8),
+    Zmm(u8),
+    K(u8),
+    St(u8),
+
+    Es,
+    Cs,
+    Ss,
+    Ds,
+    Fs,
+    Gs,
+    Cr(u8),
+    Dr(u8),
+}
+
+impl Register {
+    pub fn is_gpr16(&self) -> bool {
+        matches!(self, Register::Ax | Register::Bx | Register::Cx | Register::Dx | Register::Si | Register::Di | Register::Sp | Register::Bp)
+    }
+
+    pub fn is_gpr32(&self) -> bool {
+        matches!(self, Register::Eax | Register::Ebx | Register::Ecx | Register::Edx | Register::Esi | Register::Edi | Register::Esp | Register::Ebp)
+    }
+
+    pub fn is_segment(&self) -> bool {
+        matches!(self, Register::Es | Register::Cs | Register::Ss | Register::Ds | Register::Fs | Register::Gs)
+    }
+
+    pub fn is_gpr64(&self) -> bool {
+        matches!(self,
+            Register::Rax | Register::Rbx | Register::Rcx | Register::Rdx | Register::Rsi | Register::Rdi | Register::Rsp | Register::Rbp |
+            Register::R8 | Register::R9 | Register::R10 | Register::R11 | Register::R12 | Register::R13 | Register::R14 | Register::R15
+        )
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Register::Xmm(index) => write!(f, "xmm{}", index),
+            Register::Ymm(index) => write!(f, "ymm{}", index),
+            Register::Zmm(index) => write!(f, "zmm{}", index),
+            Register::K(index) => write!(f, "k{}", index),
+            Register::St(index) => write!(f, "st{}", index),
+            Register::Cr(index) => write!(f, "cr{}", index),
+            Register::Dr(index) => write!(f, "dr{}", index),
+            _ => write!(f, "{}", format!("{:?}", self).to_lowercase()),
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
@@ -43,6 +218,11 @@ pub enum Symbol {
     Comma,
     OpenBrace,
     CloseBrace,
+    OpenBracket,
+    CloseBracket,
+    Plus,
+    Minus,
+    Star,
 }
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
@@ -51,24 +231,104 @@ pub enum Token {
     Keyword(Keyword),
     Symbol(Symbol),
     Ident(String),
-    Int(i32),
+    Str(String),
+    Int(i64),
     Eof,
 }
 
+// tokens are shown the way they were written, strings always with single quotes
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Register(reg) => write!(f, "{}", reg),
+            Token::Keyword(keyword) => write!(f, "{}", keyword),
+            Token::Symbol(symbol) => write!(f, "{}", match symbol {
+                Symbol::Colon => ':',
+                Symbol::Comma => ',',
+                Symbol::OpenBrace => '{',
+                Symbol::CloseBrace => '}',
+                Symbol::OpenBracket => '[',
+                Symbol::CloseBracket => ']',
+                Symbol::Plus => '+',
+                Symbol::Minus => '-',
+                Symbol::Star => '*',
+            }),
+            Token::Ident(ident) => write!(f, "{}", ident),
+            Token::Str(string) => write!(f, "'{}'", string),
+            Token::Int(integer) => write!(f, "{}", integer),
+            Token::Eof => write!(f, "end of file"),
+        }
+    }
+}
+
+// columns count characters from one and `end` is the column after the last one
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Span {
+    pub file: Rc<str>,
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    // from the start of `self` to the end of `other`
+    pub fn to(&self, other: &Span) -> Span {
+        Span {
+            end: if other.line == self.line { other.end } else { self.end },
+            ..self.clone()
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            0 => write!(f, "{}", self.file),
+            line => write!(f, "{}:{}:{}", self.file, line, self.start),
+        }
+    }
+}
+
+// `line` is the number of the last line read, starting at one, and `span` covers its tokens or what failed to lex
 pub struct Lexer {
     reader: BufReader<File>,
+    file: Rc<str>,
+    pub line: usize,
+    pub span: Span,
 }
 
 impl Lexer {
-    pub fn new(file: &str) -> Result<Lexer, Box<dyn std::error::Error>> {
+    pub fn new(file: &str) -> Result<Lexer, Error> {
         let fd = File::open(file)?;
 
         Ok(Lexer {
             reader: BufReader::new(fd),
+            file: Rc::from(file),
+            line: 0,
+            span: Span { file: Rc::from(file), ..Span::default() },
         })
     }
 
-    fn lex_token(&mut self, token: &str) -> Result<Token, Box<dyn std::error::Error>> {
+    fn lex_indexed(token: &str, prefix: &str, count: u8) -> Option<u8> {
+        token.strip_prefix(prefix)
+            .and_then(|index| index.parse::<u8>().ok())
+            .filter(|index| *index < count)
+    }
+
+    // accepts decimal, `0x` prefixed and `h` suffixed hex, anything up to 64 bits wraps into an i64
+    fn lex_integer(token: &str) -> Option<i64> {
+        let (digits, radix) = if let Some(hex) = token.strip_prefix("0x") {
+            (hex, 16)
+        } else if token.starts_with(|character: char| character.is_ascii_digit()) && token.ends_with('h') {
+            (&token[..token.len() - 1], 16)
+        } else {
+            (token, 10)
+        };
+
+        u64::from_str_radix(digits, radix).ok().map(|integer| integer as i64)
+    }
+
+    fn lex_token(&mut self, token: &str) -> Result<Token, Error> {
         match token.to_lowercase().as_str() {
             "syscall" => Ok(Token::Keyword(Keyword::Syscall)),
 
@@ -79,13 +339,108 @@ impl Lexer {
             "add" => Ok(Token::Keyword(Keyword::Add)),
             "sub" => Ok(Token::Keyword(Keyword::Sub)),
             "mul" => Ok(Token::Keyword(Keyword::Mul)),
+            "lea" => Ok(Token::Keyword(Keyword::Lea)),
 
             "cmp" => Ok(Token::Keyword(Keyword::Cmp)),
             "jmp" => Ok(Token::Keyword(Keyword::Jmp)),
+            "call" => Ok(Token::Keyword(Keyword::Call)),
+            "ret" => Ok(Token::Keyword(Keyword::Ret)),
             "je" => Ok(Token::Keyword(Keyword::Je)),
             "jg" => Ok(Token::Keyword(Keyword::Jg)),
             "jb" => Ok(Token::Keyword(Keyword::Jb)),
 
+            "vaddps" => Ok(Token::Keyword(Keyword::Vaddps)),
+            "vpxor" => Ok(Token::Keyword(Keyword::Vpxor)),
+            "vmovdqu" => Ok(Token::Keyword(Keyword::Vmovdqu)),
+            "vbroadcastss" => Ok(Token::Keyword(Keyword::Vbroadcastss)),
+            "vpshufb" => Ok(Token::Keyword(Keyword::Vpshufb)),
+            "vfmadd231ps" => Ok(Token::Keyword(Keyword::Vfmadd231ps)),
+            "vzeroupper" => Ok(Token::Keyword(Keyword::Vzeroupper)),
+
+            "vmaxps" => Ok(Token::Keyword(Keyword::Vmaxps)),
+            "vpxord" => Ok(Token::Keyword(Keyword::Vpxord)),
+            "vpxorq" => Ok(Token::Keyword(Keyword::Vpxorq)),
+            "vmovdqu32" => Ok(Token::Keyword(Keyword::Vmovdqu32)),
+            "vmovdqu64" => Ok(Token::Keyword(Keyword::Vmovdqu64)),
+            "vpcmpeqd" => Ok(Token::Keyword(Keyword::Vpcmpeqd)),
+            "kmovw" => Ok(Token::Keyword(Keyword::Kmovw)),
+
+            "fld" => Ok(Token::Keyword(Keyword::Fld)),
+            "fst" => Ok(Token::Keyword(Keyword::Fst)),
+            "fstp" => Ok(Token::Keyword(Keyword::Fstp)),
+            "fild" => Ok(Token::Keyword(Keyword::Fild)),
+            "fistp" => Ok(Token::Keyword(Keyword::Fistp)),
+            "fadd" => Ok(Token::Keyword(Keyword::Fadd)),
+            "faddp" => Ok(Token::Keyword(Keyword::Faddp)),
+            "fsub" => Ok(Token::Keyword(Keyword::Fsub)),
+            "fsubp" => Ok(Token::Keyword(Keyword::Fsubp)),
+            "fmul" => Ok(Token::Keyword(Keyword::Fmul)),
+            "fmulp" => Ok(Token::Keyword(Keyword::Fmulp)),
+            "fdiv" => Ok(Token::Keyword(Keyword::Fdiv)),
+            "fdivp" => Ok(Token::Keyword(Keyword::Fdivp)),
+            "fxch" => Ok(Token::Keyword(Keyword::Fxch)),
+            "fcomi" => Ok(Token::Keyword(Keyword::Fcomi)),
+            "fsqrt" => Ok(Token::Keyword(Keyword::Fsqrt)),
+            "fsin" => Ok(Token::Keyword(Keyword::Fsin)),
+            "fcos" => Ok(Token::Keyword(Keyword::Fcos)),
+            "finit" => Ok(Token::Keyword(Keyword::Finit)),
+            "fldcw" => Ok(Token::Keyword(Keyword::Fldcw)),
+            "fnstcw" => Ok(Token::Keyword(Keyword::Fnstcw)),
+
+            "lgdt" => Ok(Token::Keyword(Keyword::Lgdt)),
+            "lidt" => Ok(Token::Keyword(Keyword::Lidt)),
+            "ltr" => Ok(Token::Keyword(Keyword::Ltr)),
+            "invlpg" => Ok(Token::Keyword(Keyword::Invlpg)),
+            "wrmsr" => Ok(Token::Keyword(Keyword::Wrmsr)),
+            "rdmsr" => Ok(Token::Keyword(Keyword::Rdmsr)),
+            "iretq" => Ok(Token::Keyword(Keyword::Iretq)),
+            "swapgs" => Ok(Token::Keyword(Keyword::Swapgs)),
+
+            "use16" => Ok(Token::Keyword(Keyword::Use16)),
+            "use32" => Ok(Token::Keyword(Keyword::Use32)),
+            "use64" => Ok(Token::Keyword(Keyword::Use64)),
+
+            "format" => Ok(Token::Keyword(Keyword::Format)),
+            "org" => Ok(Token::Keyword(Keyword::Org)),
+            "entry" => Ok(Token::Keyword(Keyword::Entry)),
+
+            "public" => Ok(Token::Keyword(Keyword::Public)),
+            "extrn" => Ok(Token::Keyword(Keyword::Extrn)),
+            "needed" => Ok(Token::Keyword(Keyword::Needed)),
+            "import" => Ok(Token::Keyword(Keyword::Import)),
+            "align" => Ok(Token::Keyword(Keyword::Align)),
+            "size" => Ok(Token::Keyword(Keyword::Size)),
+
+            "section" => Ok(Token::Keyword(Keyword::Section)),
+            "segment" => Ok(Token::Keyword(Keyword::Segment)),
+            "readable" => Ok(Token::Keyword(Keyword::Readable)),
+            "writeable" => Ok(Token::Keyword(Keyword::Writeable)),
+            "executable" => Ok(Token::Keyword(Keyword::Executable)),
+
+            "db" => Ok(Token::Keyword(Keyword::Db)),
+            "dw" => Ok(Token::Keyword(Keyword::Dw)),
+            "dd" => Ok(Token::Keyword(Keyword::Dd)),
+            "dq" => Ok(Token::Keyword(Keyword::Dq)),
+            "rb" => Ok(Token::Keyword(Keyword::Rb)),
+            "rw" => Ok(Token::Keyword(Keyword::Rw)),
+            "rd" => Ok(Token::Keyword(Keyword::Rd)),
+            "rq" => Ok(Token::Keyword(Keyword::Rq)),
+
+            "byte" => Ok(Token::Keyword(Keyword::Byte)),
+            "word" => Ok(Token::Keyword(Keyword::Word)),
+            "dword" => Ok(Token::Keyword(Keyword::Dword)),
+            "qword" => Ok(Token::Keyword(Keyword::Qword)),
+            "tword" => Ok(Token::Keyword(Keyword::Tword)),
+
+            "ax" => Ok(Token::Register(Register::Ax)),
+            "bx" => Ok(Token::Register(Register::Bx)),
+            "cx" => Ok(Token::Register(Register::Cx)),
+            "dx" => Ok(Token::Register(Register::Dx)),
+            "si" => Ok(Token::Register(Register::Si)),
+            "di" => Ok(Token::Register(Register::Di)),
+            "sp" => Ok(Token::Register(Register::Sp)),
+            "bp" => Ok(Token::Register(Register::Bp)),
+
             "eax" => Ok(Token::Register(Register::Eax)),
             "ebx" => Ok(Token::Register(Register::Ebx)),
             "ecx" => Ok(Token::Register(Register::Ecx)),
@@ -95,6 +450,33 @@ impl Lexer {
             "esp" => Ok(Token::Register(Register::Esp)),
             "ebp" => Ok(Token::Register(Register::Ebp)),
 
+            "rax" => Ok(Token::Register(Register::Rax)),
+            "rbx" => Ok(Token::Register(Register::Rbx)),
+            "rcx" => Ok(Token::Register(Register::Rcx)),
+            "rdx" => Ok(Token::Register(Register::Rdx)),
+            "rsi" => Ok(Token::Register(Register::Rsi)),
+            "rdi" => Ok(Token::Register(Register::Rdi)),
+            "rsp" => Ok(Token::Register(Register::Rsp)),
+            "rbp" => Ok(Token::Register(Register::Rbp)),
+            "rip" => Ok(Token::Register(Register::Rip)),
+            "r8" => Ok(Token::Register(Register::R8)),
+            "r9" => Ok(Token::Register(Register::R9)),
+            "r10" => Ok(Token::Register(Register::R10)),
+            "r11" => Ok(Token::Register(Register::R11)),
+            "r12" => Ok(Token::Register(Register::R12)),
+            "r13" => Ok(Token::Register(Register::R13)),
+            "r14" => Ok(Token::Register(Register::R14)),
+            "r15" => Ok(Token::Register(Register::R15)),
+
+            "st" => Ok(Token::Register(Register::St(0))),
+
+            "es" => Ok(Token::Register(Register::Es)),
+            "cs" => Ok(Token::Register(Register::Cs)),
+            "ss" => Ok(Token::Register(Register::Ss)),
+            "ds" => Ok(Token::Register(Register::Ds)),
+            "fs" => Ok(Token::Register(Register::Fs)),
+            "gs" => Ok(Token::Register(Register::Gs)),
+
             "equ" | "=" => Ok(Token::Keyword(Keyword::Equ)),
             "macro" => Ok(Token::Keyword(Keyword::Macro)),
 
@@ -102,8 +484,27 @@ impl Lexer {
             "}" => Ok(Token::Symbol(Symbol::CloseBrace)),
             ":" => Ok(Token::Symbol(Symbol::Colon)),
             "," => Ok(Token::Symbol(Symbol::Comma)),
-            _ => {
-                if let Ok(integer) = token.parse::<i32>() {
+            "[" => Ok(Token::Symbol(Symbol::OpenBracket)),
+            "]" => Ok(Token::Symbol(Symbol::CloseBracket)),
+            "+" => Ok(Token::Symbol(Symbol::Plus)),
+            "-" => Ok(Token::Symbol(Symbol::Minus)),
+            "*" => Ok(Token::Symbol(Symbol::Star)),
+            lowercase => {
+                if let Some(index) = Self::lex_indexed(lowercase, "xmm", 32) {
+                    Ok(Token::Register(Register::Xmm(index)))
+                } else if let Some(index) = Self::lex_indexed(lowercase, "ymm", 32) {
+                    Ok(Token::Register(Register::Ymm(index)))
+                } else if let Some(index) = Self::lex_indexed(lowercase, "zmm", 32) {
+                    Ok(Token::Register(Register::Zmm(index)))
+                } else if let Some(index) = Self::lex_indexed(lowercase, "k", 8) {
+                    Ok(Token::Register(Register::K(index)))
+                } else if let Some(index) = Self::lex_indexed(lowercase, "st", 8) {
+                    Ok(Token::Register(Register::St(index)))
+                } else if let Some(index) = Self::lex_indexed(lowercase, "cr", 9) {
+                    Ok(Token::Register(Register::Cr(index)))
+                } else if let Some(index) = Self::lex_indexed(lowercase, "dr", 8) {
+                    Ok(Token::Register(Register::Dr(index)))
+                } else if let Some(integer) = Self::lex_integer(lowercase) {
                     Ok(Token::Int(integer))
                 } else {
                     Ok(Token::Ident(token.to_string()))
@@ -112,48 +513,117 @@ impl Lexer {
         }
     }
 
-    fn lex_line(&mut self, line: &str) -> Result<Vec<Token>, Box<dyn std::error::Error>> {
-        let mut tokens: Vec<Token> = Vec::new();
+    fn span(&self, start: usize, end: usize) -> Span {
+        Span {
+            file: self.file.clone(),
+            line: self.line,
+            start,
+            end,
+        }
+    }
+
+    // every token comes with the columns it was lexed from, strings include their quotes
+    fn lex_line(&mut self, line: &str) -> Result<Vec<(Token, Span)>, Error> {
+        let mut tokens: Vec<(Token, Span)> = Vec::new();
         let mut token = String::new();
+        let mut quote: Option<char> = None;
+        let mut start = 1;
 
-        for character in line.chars() {
-            if [' ', ',', ':', '\n'].contains(&character) {
+        for (column, character) in line.chars().enumerate().map(|(index, character)| (index + 1, character)) {
+            if let Some(delimiter) = quote {
+                if character == delimiter {
+                    tokens.push((Token::Str(std::mem::take(&mut token)), self.span(start, column + 1)));
+                    quote = None;
+                } else {
+                    token.push(character);
+                }
+            } else if (character == '\'' || character == '"') && token.is_empty() {
+                quote = Some(character);
+                start = column;
+            } else if character.is_whitespace() || [',', ':', '[', ']', '{', '}', '+', '-', '*'].contains(&character) {
                 if !token.is_empty() {
-                    tokens.push(self.lex_token(&token)?);
+                    tokens.push((self.lex_token(&token)?, self.span(start, column)));
                 }
 
                 if !character.is_whitespace() {
-                    tokens.push(self.lex_token(&character.to_string())?);
+                    tokens.push((self.lex_token(&character.to_string())?, self.span(column, column + 1)));
                 }
 
                 token.drain(..);
             } else {
+                if token.is_empty() {
+                    start = column;
+                }
+
                 token.push(character);
             }
         }
 
+        if quote.is_some() {
+            self.span = self.span(start, line.trim_end().chars().count() + 1);
+
+            return Err(Error::UnterminatedString);
+        } else if !token.is_empty() {
+            tokens.push((self.lex_token(&token)?, self.span(start, start + token.chars().count())));
+        }
+
         Ok(tokens)
     }
 
-    pub fn rewind(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    // skips the `{ .. }` body after a line, `open` is whether that line already opened it, nothing is read when there is none
+    pub fn skip_block(&mut self, open: bool) -> Result<(), Error> {
+        let (position, line, span) = (self.reader.stream_position()?, self.line, self.span.clone());
+        let starts = |tokens: &Option<Vec<(Token, Span)>>, token: &Token| tokens.as_ref().and_then(|tokens| tokens.first()).map(|(first, _)| first) == Some(token);
+
+        if open || starts(&self.next_line().unwrap_or(None), &Token::Symbol(Symbol::OpenBrace)) {
+            loop {
+                let tokens = self.next_line().unwrap_or(None);
+
+                if starts(&tokens, &Token::Symbol(Symbol::CloseBrace)) || starts(&tokens, &Token::Eof) {
+                    break;
+                }
+            }
+        } else {
+            self.reader.seek(SeekFrom::Start(position))?;
+            self.line = line;
+        }
+
+        // whatever went wrong is still reported on the line before the body
+        self.span = span;
+
+        Ok(())
+    }
+
+    pub fn rewind(&mut self) -> Result<(), Error> {
         self.reader.rewind()?;
+        self.line = 0;
 
         Ok(())
     }
 
-    pub fn next_line(&mut self) -> Result<Option<Vec<Token>>, Box<dyn std::error::Error>> {
+    pub fn next_line(&mut self) -> Result<Option<Vec<(Token, Span)>>, Error> {
         let mut line = String::new();
 
         if self.reader.read_line(&mut line)? != 0 {
+            self.line += 1;
+            self.span = self.span(1, 1);
+
             let tokens = self.lex_line(&line)?;
 
+            if let (Some((_, first)), Some((_, last))) = (tokens.first(), tokens.last()) {
+                self.span = first.to(last);
+            }
+
             if !tokens.is_empty() {
                 Ok(Some(tokens))
             } else {
                 Ok(None)
             }
         } else {
-            Ok(Some(vec![Token::Eof]))
+            // the end of the file is not on any line
+            self.span = Span { file: self.file.clone(), ..Span::default() };
+
+            Ok(Some(vec![(Token::Eof, self.span.clone())]))
         }
     }
 }
@@ -5,6 +5,9 @@ use lexer::Keyword;
 use lexer::Symbol;
 use lexer::Token;
 use lexer::Lexer;
+use lexer::Span;
+
+use crate::error::Error;
 
 pub struct SplitTokens {
     lhs: Vec<Token>,
@@ -12,23 +15,107 @@ pub struct SplitTokens {
 }
 
 impl SplitTokens {
-    pub fn new(tokens: &[Token]) -> Result<SplitTokens, Box<dyn std::error::Error>> {
-        if let Ok(comma) = tokens.binary_search(&Token::Symbol(Symbol::Comma)) {
+    pub fn new(tokens: &[Token]) -> Result<SplitTokens, Error> {
+        if let Some(comma) = tokens.iter().position(|token| *token == Token::Symbol(Symbol::Comma)) {
             Ok(SplitTokens {
                 lhs: tokens[..comma].to_vec(),
                 rhs: tokens[comma + 1..].to_vec(),
             })
         } else {
-            Err("expected `,` between expressions".into())
+            Err(Error::Expected("`,` between expressions"))
         }
     }
 }
 
+#[derive(Debug, PartialEq, Clone)]
+pub struct Address {
+    pub base: Option<Register>,
+    pub index: Option<Register>,
+    pub scale: u8,
+    pub disp: i32,
+    pub broadcast: Option<u8>,
+    pub size: Option<Size>,
+    pub segment: Option<Register>,
+    pub symbol: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Size {
+    Byte,
+    Word,
+    Dword,
+    Qword,
+    Tword,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Format {
+    Binary,
+    Elf64,
+    Elf64Executable,
+    Elf64Pie,
+    Elf64Shared,
+    Pe64,
+    Pe64Gui,
+    Ms64Coff,
+    MachO64,
+}
+
+impl Format {
+    // executables and shared libraries are laid out in segments by the assembler itself
+    pub fn is_image(&self) -> bool {
+        matches!(self, Format::Elf64Executable | Format::Elf64Pie | Format::Elf64Shared | Format::Pe64 | Format::Pe64Gui)
+    }
+
+    // relocatable objects are laid out in sections by the linker
+    pub fn is_object(&self) -> bool {
+        matches!(self, Format::Elf64 | Format::Ms64Coff | Format::MachO64)
+    }
+
+    // objects written by faerie, every symbol is a definition of its own
+    pub fn is_faerie(&self) -> bool {
+        matches!(self, Format::MachO64)
+    }
+
+    pub fn is_pe(&self) -> bool {
+        matches!(self, Format::Pe64 | Format::Pe64Gui)
+    }
+
+    // position independent images are relocated by the dynamic linker
+    pub fn is_dynamic(&self) -> bool {
+        matches!(self, Format::Elf64Pie | Format::Elf64Shared)
+    }
+}
+
+// sections and segments are always readable
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Permissions {
+    pub writeable: bool,
+    pub executable: bool,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Rounding {
+    Nearest,
+    Down,
+    Up,
+    Zero,
+    Sae,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Value {
     Register(Register),
-    Integer(i32),
+    Integer(i64),
     Const(String),
+    String(String),
+    Memory(Address),
+    Masked {
+        value: Box<Value>,
+        mask: Option<Register>,
+        zeroing: bool,
+    },
+    Rounding(Rounding),
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -40,7 +127,7 @@ pub enum ConstExpr {
     Macro {
         ident: String,
         args: Vec<String>,
-        body: Vec<Inst>,
+        body: Vec<Spanned>,
     },
     Call {
         ident: String,
@@ -48,6 +135,14 @@ pub enum ConstExpr {
     },
 }
 
+// an instruction with the tokens it covers and each of its operands, in the order they are written
+#[derive(Debug, PartialEq, Clone)]
+pub struct Spanned {
+    pub span: Span,
+    pub operands: Vec<Span>,
+    pub inst: Inst,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Inst {
     ConstExpr(ConstExpr),
@@ -55,6 +150,13 @@ pub enum Inst {
     Label { ident: String },
 
     Jmp { label: String },
+    Call { label: String },
+    Ret,
+    JmpFar {
+        selector: Value,
+        label: String,
+        size: Option<Size>,
+    },
     Je { label: String },
     Jg { label: String },
     Jb { label: String },
@@ -80,148 +182,728 @@ pub enum Inst {
     Mul {
         dest: Register,
     },
+    Lea {
+        dest: Value,
+        src: Value,
+    },
     Cmp {
         lhs: Value,
         rhs: Value,
     },
     Syscall,
 
+    Vaddps {
+        dest: Value,
+        src1: Value,
+        src2: Value,
+        rounding: Option<Rounding>,
+    },
+    Vpxor {
+        dest: Value,
+        src1: Value,
+        src2: Value,
+    },
+    Vpshufb {
+        dest: Value,
+        src1: Value,
+        src2: Value,
+    },
+    Vfmadd231ps {
+        dest: Value,
+        src1: Value,
+        src2: Value,
+        rounding: Option<Rounding>,
+    },
+    Vmovdqu {
+        lhs: Value,
+        rhs: Value,
+    },
+    Vbroadcastss {
+        dest: Value,
+        src: Value,
+    },
+    Vzeroupper,
+
+    Vmaxps {
+        dest: Value,
+        src1: Value,
+        src2: Value,
+        rounding: Option<Rounding>,
+    },
+    Vpxord {
+        dest: Value,
+        src1: Value,
+        src2: Value,
+    },
+    Vpxorq {
+        dest: Value,
+        src1: Value,
+        src2: Value,
+    },
+    Vpcmpeqd {
+        dest: Value,
+        src1: Value,
+        src2: Value,
+    },
+    Vmovdqu32 {
+        lhs: Value,
+        rhs: Value,
+    },
+    Vmovdqu64 {
+        lhs: Value,
+        rhs: Value,
+    },
+    Kmovw {
+        lhs: Value,
+        rhs: Value,
+    },
+
+    Fld {
+        src: Value,
+    },
+    Fst {
+        dest: Value,
+    },
+    Fstp {
+        dest: Value,
+    },
+    Fild {
+        src: Value,
+    },
+    Fistp {
+        dest: Value,
+    },
+    Fadd {
+        operands: Vec<Value>,
+    },
+    Faddp {
+        operands: Vec<Value>,
+    },
+    Fsub {
+        operands: Vec<Value>,
+    },
+    Fsubp {
+        operands: Vec<Value>,
+    },
+    Fmul {
+        operands: Vec<Value>,
+    },
+    Fmulp {
+        operands: Vec<Value>,
+    },
+    Fdiv {
+        operands: Vec<Value>,
+    },
+    Fdivp {
+        operands: Vec<Value>,
+    },
+    Fxch {
+        operands: Vec<Value>,
+    },
+    Fcomi {
+        operands: Vec<Value>,
+    },
+    Fsqrt,
+    Fsin,
+    Fcos,
+    Finit,
+    Fldcw {
+        src: Value,
+    },
+    Fnstcw {
+        dest: Value,
+    },
+
+    Lgdt {
+        src: Value,
+    },
+    Lidt {
+        src: Value,
+    },
+    Ltr {
+        src: Value,
+    },
+    Invlpg {
+        src: Value,
+    },
+    Wrmsr,
+    Rdmsr,
+    Iretq,
+    Swapgs,
+
+    Use { bits: u8 },
+
+    Format { format: Format },
+    Org { origin: Value },
+    Entry { label: String },
+
+    Public { ident: String },
+    Extrn { ident: String },
+    Needed { library: String },
+    Import {
+        library: String,
+        idents: Vec<String>,
+    },
+    Align { value: Value },
+    Size {
+        ident: String,
+        value: Value,
+    },
+
+    Section {
+        name: String,
+        permissions: Permissions,
+    },
+    Segment { permissions: Permissions },
+
+    Data {
+        label: Option<String>,
+        size: Size,
+        values: Vec<Value>,
+    },
+    Reserve {
+        label: Option<String>,
+        size: Size,
+        count: Value,
+    },
+
     Eof,
 }
 
+// errors in macro bodies dont stop the macro from being parsed, they are kept until the next instruction
+// `failed` is the last operand that did not parse, so its error can point at it
 pub struct Parser {
     pub lexer: Lexer,
+    pub errors: Vec<(Span, Error)>,
+    failed: Option<Vec<Token>>,
 }
 
 impl Parser {
-    pub fn new(file: &str) -> Result<Parser, Box<dyn std::error::Error>> {
+    pub fn new(file: &str) -> Result<Parser, Error> {
         Ok(Parser {
             lexer: Lexer::new(file)?,
+            errors: Vec::new(),
+            failed: None,
         })
     }
 
-    fn parse_label(&mut self, ident: &String, tokens: &[Token]) -> Result<Inst, Box<dyn std::error::Error>> {
+    // operands follow the mnemonic, or the name and keyword of data and constants, and are separated by commas
+    fn operands(tokens: &[(Token, Span)]) -> Vec<&[(Token, Span)]> {
+        let start = match tokens {
+            [(Token::Keyword(_), _), ..] => 1,
+            [(Token::Ident(_), _), (Token::Symbol(Symbol::Colon), _), ..] => return Vec::new(),
+            [(Token::Ident(_), _), (Token::Keyword(_), _), ..] => 2,
+            [(Token::Ident(_), _), ..] => 1,
+            _ => return Vec::new(),
+        };
+
+        tokens[start..].split(|(token, _)| *token == Token::Symbol(Symbol::Comma))
+            .filter(|operand| !operand.is_empty())
+            .collect()
+    }
+
+    // from the first to the last token of a run
+    fn run_span(run: &[(Token, Span)]) -> Span {
+        run[0].1.to(&run[run.len() - 1].1)
+    }
+
+    // the first run of `tokens` that is `needle`
+    fn find<'a>(tokens: &'a [(Token, Span)], needle: &[Token]) -> Option<&'a [(Token, Span)]> {
+        (0..=tokens.len().saturating_sub(needle.len()))
+            .map(|start| &tokens[start..start + needle.len()])
+            .find(|run| !run.is_empty() && run.iter().map(|(token, _)| token).eq(needle.iter()))
+    }
+
+    // unknown instructions point at their name, other errors at the tokens they name inside the operand that did not parse
+    // tokens are parsed left to right, so the first place that matches is the one that failed
+    fn locate(&mut self, tokens: &[(Token, Span)], span: Span, err: &Error) -> Span {
+        let operand = self.failed.take().and_then(|failed| Self::find(tokens, &failed));
+
+        let named = match err {
+            Error::NoSuchInstruction(_) => return tokens.first().map(|(_, name)| name.clone()).unwrap_or(span),
+            Error::UnexpectedToken(token) | Error::Permission(token) | Error::InvalidDecoration(token) => vec![token.clone()],
+            Error::InvalidTerm(term) => term.clone(),
+            Error::InvalidScale(scale) => vec![Token::Int(*scale)],
+            _ => Vec::new(),
+        };
+
+        Self::find(operand.unwrap_or(tokens), &named)
+            .or(operand)
+            .map(Self::run_span)
+            .unwrap_or(span)
+    }
+
+    fn spanned(tokens: &[(Token, Span)], span: Span, inst: Inst) -> Spanned {
+        Spanned {
+            span,
+            operands: Self::operands(tokens).into_iter().map(Self::run_span).collect(),
+            inst,
+        }
+    }
+
+    fn parse_label(&mut self, ident: &String, tokens: &[Token]) -> Result<Inst, Error> {
         if let Some(token) = tokens.get(1) {
             match token {
                 Token::Symbol(Symbol::Colon) => Ok(Inst::Label { ident: ident.to_string() }),
-                _ => Err(format!("no such instruction `{}`", ident).into()),
+                _ => Err(Error::NoSuchInstruction(ident.clone())),
             }
         } else {
-            Err(format!("no such instruction `{}`", ident).into())
+            Err(Error::NoSuchInstruction(ident.clone()))
         }
     }
 
-    fn parse_expr(&mut self, expr: &[Token]) -> Result<Value, Box<dyn std::error::Error>> {
-        if let Some(prefix) = expr.first() {
-            match prefix {
-                Token::Register(reg) => return Ok(Value::Register(*reg)),
-                Token::Int(integer) => return Ok(Value::Integer(*integer)),
-                Token::Ident(ident) => return Ok(Value::Const(ident.clone())),
-                _ => return Err(format!("unexpected token `{:?}`", prefix).into()),
+    fn parse_address(&mut self, expr: &[Token]) -> Result<Address, Error> {
+        let mut address = Address {
+            base: None,
+            index: None,
+            scale: 1,
+            disp: 0,
+            broadcast: None,
+            size: None,
+            segment: None,
+            symbol: None,
+        };
+
+        let expr = match expr {
+            [Token::Register(segment), Token::Symbol(Symbol::Colon), rest @ ..] if segment.is_segment() => {
+                address.segment = Some(*segment);
+
+                rest
+            },
+            _ => expr,
+        };
+
+        let mut sign = 1;
+        let mut disp: i64 = 0;
+
+        for (position, term) in expr.split_inclusive(|token| [Token::Symbol(Symbol::Plus), Token::Symbol(Symbol::Minus)].contains(token)).enumerate() {
+            let (operator, term) = match term.split_last() {
+                Some((operator @ Token::Symbol(Symbol::Plus | Symbol::Minus), term)) => (Some(operator), term),
+                _ => (None, term),
+            };
+
+            match term {
+                [Token::Int(integer)] => disp = disp.wrapping_add(sign * integer),
+                [Token::Ident(symbol)] if sign > 0 && address.symbol.is_none() => address.symbol = Some(symbol.clone()),
+                [Token::Register(reg)] if sign > 0 => {
+                    if address.base.is_none() {
+                        address.base = Some(*reg);
+                    } else if address.index.is_none() {
+                        address.index = Some(*reg);
+                    } else {
+                        return Err(Error::TooManyRegisters);
+                    }
+                },
+                [Token::Register(reg), Token::Symbol(Symbol::Star), Token::Int(scale)]
+                | [Token::Int(scale), Token::Symbol(Symbol::Star), Token::Register(reg)] if sign > 0 => {
+                    if address.index.is_some() {
+                        return Err(Error::TooManyIndexes);
+                    } else if ![1, 2, 4, 8].contains(scale) {
+                        return Err(Error::InvalidScale(*scale));
+                    }
+
+                    address.index = Some(*reg);
+                    address.scale = *scale as u8;
+                },
+                [] if position == 0 && operator.is_some() => {},
+                [] => return Err(Error::EmptyTerm),
+                _ => return Err(Error::InvalidTerm(term.to_vec())),
             }
+
+            sign = if operator == Some(&Token::Symbol(Symbol::Minus)) { -1 } else { 1 };
+        }
+
+        // displacements are 32 bits, unsigned ones above 2GiB are only meaningful outside use64 and wrap
+        if !(-0x8000_0000..=0xffff_ffff).contains(&disp) {
+            return Err(Error::Displacement(disp));
         }
 
-        Err("empty expression".into())
+        address.disp = disp as i32;
+
+        Ok(address)
     }
 
-    fn parse_reg(&mut self, expr: &[Token]) -> Result<Register, Box<dyn std::error::Error>> {
+    fn parse_rounding(&mut self, expr: &[Token]) -> Result<Rounding, Error> {
+        match expr {
+            // `rd` is lexed as the keyword that reserves dwords
+            [Token::Symbol(Symbol::OpenBrace), Token::Keyword(Keyword::Rd), Token::Symbol(Symbol::Minus), Token::Ident(sae), Token::Symbol(Symbol::CloseBrace)] if sae.to_lowercase() == "sae" => Ok(Rounding::Down),
+            [Token::Symbol(Symbol::OpenBrace), Token::Ident(mode), Token::Symbol(Symbol::Minus), Token::Ident(sae), Token::Symbol(Symbol::CloseBrace)] if sae.to_lowercase() == "sae" => {
+                match mode.to_lowercase().as_str() {
+                    "rn" => Ok(Rounding::Nearest),
+                    "ru" => Ok(Rounding::Up),
+                    "rz" => Ok(Rounding::Zero),
+                    _ => Err(Error::NoSuchRounding(mode.clone())),
+                }
+            },
+            [Token::Symbol(Symbol::OpenBrace), Token::Ident(sae), Token::Symbol(Symbol::CloseBrace)] if sae.to_lowercase() == "sae" => Ok(Rounding::Sae),
+            _ => Err(Error::Usage { message: "invalid rounding operand", usage: "{rn-sae}, {rd-sae}, {ru-sae}, {rz-sae} or {sae}" }),
+        }
+    }
+
+    fn parse_decorations(&mut self, mut value: Value, mut tokens: &[Token]) -> Result<Value, Error> {
+        while let [Token::Symbol(Symbol::OpenBrace), decoration, Token::Symbol(Symbol::CloseBrace), rest @ ..] = tokens {
+            let (mut inner, mut mask, mut zeroing) = match value {
+                Value::Masked { value, mask, zeroing } => (*value, mask, zeroing),
+                value => (value, None, false),
+            };
+
+            match (&mut inner, decoration) {
+                (_, Token::Register(reg @ Register::K(_))) => mask = Some(*reg),
+                (_, Token::Ident(ident)) if ident.to_lowercase() == "z" => zeroing = true,
+                (Value::Memory(address), Token::Ident(ident)) if ident.to_lowercase().starts_with("1to") => {
+                    address.broadcast = Some(ident[3..].parse::<u8>().map_err(|_| Error::InvalidBroadcast(ident.clone()))?);
+                },
+                (_, Token::Ident(ident)) if ident.to_lowercase().starts_with("1to") => return Err(Error::BroadcastNeedsMemory),
+                _ => return Err(Error::InvalidDecoration(decoration.clone())),
+            }
+
+            value = if mask.is_some() || zeroing {
+                Value::Masked {
+                    value: Box::new(inner),
+                    mask,
+                    zeroing,
+                }
+            } else {
+                inner
+            };
+
+            tokens = rest;
+        }
+
+        if let Some(token) = tokens.first() {
+            return Err(Error::UnexpectedToken(token.clone()));
+        }
+
+        Ok(value)
+    }
+
+    fn parse_expr(&mut self, expr: &[Token]) -> Result<Value, Error> {
+        self.parse_value(expr).inspect_err(|_| self.failed = Some(expr.to_vec()))
+    }
+
+    fn parse_value(&mut self, expr: &[Token]) -> Result<Value, Error> {
+        let (value, rest) = match expr {
+            [Token::Register(reg), rest @ ..] => (Value::Register(*reg), rest),
+            [Token::Int(integer), rest @ ..] => (Value::Integer(*integer), rest),
+            [Token::Ident(ident), rest @ ..] => (Value::Const(ident.clone()), rest),
+            [Token::Str(string), rest @ ..] => (Value::String(string.clone()), rest),
+            [Token::Symbol(Symbol::Minus), Token::Int(integer), rest @ ..] => (Value::Integer(integer.wrapping_neg()), rest),
+            [Token::Symbol(Symbol::Minus), ..] => return Err(Error::Expected("integer after `-`")),
+            [Token::Symbol(Symbol::OpenBracket), ..] => {
+                let close = expr.iter()
+                    .position(|token| *token == Token::Symbol(Symbol::CloseBracket))
+                    .ok_or(Error::Expected("`]` after address"))?;
+
+                (Value::Memory(self.parse_address(&expr[1..close])?), &expr[close + 1..])
+            },
+            [Token::Symbol(Symbol::OpenBrace), ..] => return Ok(Value::Rounding(self.parse_rounding(expr)?)),
+            [Token::Keyword(keyword @ (Keyword::Byte | Keyword::Word | Keyword::Dword | Keyword::Qword | Keyword::Tword)), rest @ ..] => {
+                let size = match keyword {
+                    Keyword::Byte => Size::Byte,
+                    Keyword::Word => Size::Word,
+                    Keyword::Dword => Size::Dword,
+                    Keyword::Qword => Size::Qword,
+                    _ => Size::Tword,
+                };
+
+                return match self.parse_expr(rest)? {
+                    Value::Memory(address) => Ok(Value::Memory(Address { size: Some(size), ..address })),
+                    Value::Masked { value, mask, zeroing } => match *value {
+                        Value::Memory(address) => Ok(Value::Masked {
+                            value: Box::new(Value::Memory(Address { size: Some(size), ..address })),
+                            mask,
+                            zeroing,
+                        }),
+                        _ => Err(Error::MemoryOnly(keyword.clone())),
+                    },
+                    _ => Err(Error::MemoryOnly(keyword.clone())),
+                };
+            },
+            [prefix, ..] => return Err(Error::UnexpectedToken(prefix.clone())),
+            [] => return Err(Error::EmptyExpression),
+        };
+
+        self.parse_decorations(value, rest)
+    }
+
+    fn parse_operands(&mut self, tokens: &[Token]) -> Result<Vec<Value>, Error> {
+        tokens.split(|token| *token == Token::Symbol(Symbol::Comma))
+            .map(|operand| self.parse_expr(operand))
+            .collect()
+    }
+
+    fn parse_fpu_operands(&mut self, tokens: &[Token]) -> Result<Vec<Value>, Error> {
+        if tokens.is_empty() {
+            Ok(Vec::new())
+        } else {
+            self.parse_operands(tokens)
+        }
+    }
+
+    fn parse_ternary(&mut self, tokens: &[Token]) -> Result<(Value, Value, Value), Error> {
+        match self.parse_operands(tokens)?.as_slice() {
+            [dest, src1, src2] => Ok((dest.clone(), src1.clone(), src2.clone())),
+            operands => Err(Error::OperandCount { expected: 3, got: operands.len() }),
+        }
+    }
+
+    // returns where the operands before a trailing `{rn-sae}` style operand end
+    fn parse_rounding_operand(&mut self, tokens: &[Token]) -> Result<(usize, Option<Rounding>), Error> {
+        match tokens.iter().rposition(|token| *token == Token::Symbol(Symbol::Comma)) {
+            Some(comma) if tokens.get(comma + 1) == Some(&Token::Symbol(Symbol::OpenBrace)) => {
+                Ok((comma, Some(self.parse_rounding(&tokens[comma + 1..])?)))
+            },
+            _ => Ok((tokens.len(), None)),
+        }
+    }
+
+    fn parse_reg(&mut self, expr: &[Token]) -> Result<Register, Error> {
         if let Some(prefix) = expr.first() {
             match prefix {
                 Token::Register(reg) => return Ok(*reg),
-                _ => return Err("expected register".into()),
+                _ => return Err(Error::Expected("register")),
             }
         }
 
-        Err("empty expression".into())
+        Err(Error::EmptyExpression)
     }
 
-    fn parse_jcc(&mut self, tokens: &[Token]) -> Result<String, Box<dyn std::error::Error>> {
-        if let Some(suffix) = tokens.first() {
-            if let Token::Ident(label) = suffix {
-                return Ok(label.clone());
+    fn parse_jcc(&mut self, tokens: &[Token]) -> Result<String, Error> {
+        if let Some(Token::Ident(label)) = tokens.first() {
+            return Ok(label.clone());
+        }
+
+        Err(Error::Expected("label in jcc instruction"))
+    }
+
+    fn parse_far_jmp(&mut self, tokens: &[Token]) -> Result<Inst, Error> {
+        let (size, tokens) = match tokens {
+            [Token::Keyword(Keyword::Word), rest @ ..] => (Some(Size::Word), rest),
+            [Token::Keyword(Keyword::Dword), rest @ ..] => (Some(Size::Dword), rest),
+            _ => (None, tokens),
+        };
+
+        match tokens {
+            [selector @ .., Token::Symbol(Symbol::Colon), Token::Ident(label)] => Ok(Inst::JmpFar {
+                selector: self.parse_expr(selector)?,
+                label: label.clone(),
+                size,
+            }),
+            _ => Err(Error::Usage { message: "invalid far jump", usage: "jmp [word|dword] <SELECTOR>:<LABEL>" }),
+        }
+    }
+
+    fn parse_format(&mut self, tokens: &[Token]) -> Result<Format, Error> {
+        let names = tokens.iter()
+            .map(|token| match token {
+                Token::Ident(ident) => Ok(ident.to_lowercase()),
+                Token::Keyword(Keyword::Executable) => Ok(String::from("executable")),
+                _ => Err(Error::UnexpectedToken(token.clone())),
+            })
+            .collect::<Result<Vec<String>, _>>()?;
+
+        match names.iter().map(|name| name.as_str()).collect::<Vec<&str>>().as_slice() {
+            ["binary"] => Ok(Format::Binary),
+            ["elf64"] => Ok(Format::Elf64),
+            ["elf64", "executable"] => Ok(Format::Elf64Executable),
+            ["elf64", "pie"] => Ok(Format::Elf64Pie),
+            ["elf64", "shared"] => Ok(Format::Elf64Shared),
+            ["pe64"] | ["pe64", "console"] => Ok(Format::Pe64),
+            ["pe64", "gui"] => Ok(Format::Pe64Gui),
+            ["ms64", "coff"] => Ok(Format::Ms64Coff),
+            ["macho64"] => Ok(Format::MachO64),
+            _ => Err(Error::UnknownFormat(names.join(" "))),
+        }
+    }
+
+    fn parse_symbol(&mut self, tokens: &[Token]) -> Result<String, Error> {
+        match tokens {
+            [Token::Ident(ident)] => Ok(ident.clone()),
+            _ => Err(Error::Expected("a single symbol name")),
+        }
+    }
+
+    fn parse_library(&mut self, tokens: &[Token]) -> Result<String, Error> {
+        match tokens {
+            [Token::Str(library)] => Ok(library.clone()),
+            _ => Err(Error::Usage { message: "expected library name as string", usage: "needed 'libc.so.6'" }),
+        }
+    }
+
+    // import 'kernel32.dll', ExitProcess, WriteFile
+    fn parse_import(&mut self, tokens: &[Token]) -> Result<Inst, Error> {
+        let split = SplitTokens::new(tokens)?;
+
+        let idents = split.rhs.split(|token| *token == Token::Symbol(Symbol::Comma))
+            .map(|tokens| self.parse_symbol(tokens))
+            .collect::<Result<Vec<String>, _>>()?;
+
+        Ok(Inst::Import {
+            library: self.parse_library(&split.lhs)?,
+            idents,
+        })
+    }
+
+    fn parse_permissions(&mut self, tokens: &[Token]) -> Result<Permissions, Error> {
+        let mut permissions = Permissions::default();
+
+        for token in tokens {
+            match token {
+                Token::Keyword(Keyword::Readable) => {},
+                Token::Keyword(Keyword::Writeable) => permissions.writeable = true,
+                Token::Keyword(Keyword::Executable) => permissions.executable = true,
+                _ => return Err(Error::Permission(token.clone())),
             }
         }
 
-        Err("expected label in jcc instruction".into())
+        Ok(permissions)
+    }
+
+    fn parse_section(&mut self, tokens: &[Token]) -> Result<Inst, Error> {
+        match tokens {
+            [Token::Str(name), rest @ ..] => Ok(Inst::Section {
+                name: name.clone(),
+                permissions: self.parse_permissions(rest)?,
+            }),
+            _ => Err(Error::Usage { message: "invalid section", usage: "section '<NAME>' [readable] [writeable] [executable]" }),
+        }
+    }
+
+    // `db` and friends define data, `rb` and friends reserve uninitialized space
+    fn parse_data(&mut self, label: Option<String>, keyword: &Keyword, tokens: &[Token]) -> Result<Option<Inst>, Error> {
+        let (size, reserve) = match keyword {
+            Keyword::Db => (Size::Byte, false),
+            Keyword::Dw => (Size::Word, false),
+            Keyword::Dd => (Size::Dword, false),
+            Keyword::Dq => (Size::Qword, false),
+            Keyword::Rb => (Size::Byte, true),
+            Keyword::Rw => (Size::Word, true),
+            Keyword::Rd => (Size::Dword, true),
+            Keyword::Rq => (Size::Qword, true),
+            _ => return Ok(None),
+        };
+
+        if reserve {
+            Ok(Some(Inst::Reserve {
+                label,
+                size,
+                count: self.parse_expr(tokens)?,
+            }))
+        } else {
+            Ok(Some(Inst::Data {
+                label,
+                size,
+                values: self.parse_operands(tokens)?,
+            }))
+        }
     }
 
-    fn parse_const_expr(&mut self, ident: String, tokens: &[Token]) -> Result<ConstExpr, Box<dyn std::error::Error>> {
+    fn parse_const_expr(&mut self, ident: String, tokens: &[Token]) -> Result<ConstExpr, Error> {
         if tokens.len() < 3 {
-            Err("empty expression".into())
+            Err(Error::EmptyExpression)
         } else if tokens[1] != Token::Keyword(Keyword::Equ) {
-            Err("expected `equ` in constexpr".into())
-        } else if let Ok(value) = self.parse_expr(&[tokens[2].clone()]) {
+            Err(Error::Expected("`equ` in constexpr"))
+        } else if let Ok(value) = self.parse_expr(&tokens[2..]) {
             Ok(ConstExpr::Constant {
                 ident: ident.clone(),
                 value,
             })
         } else {
-            Err("invalid expression".into())
+            Err(Error::InvalidExpression)
         }
     }
 
-    fn parse_ident(&mut self, token: &Token) -> Result<String, Box<dyn std::error::Error>> {
+    fn parse_ident(&mut self, token: &Token) -> Result<String, Error> {
         if let Token::Ident(ident) = token {
             Ok(ident.clone())
         } else {
-            Err("expected identifier".into())
+            Err(Error::Expected("identifier"))
         }
     }
 
-    fn parse_args(&mut self, tokens: &[Token]) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    fn parse_args(&mut self, tokens: &[Token]) -> Result<Vec<String>, Error> {
         let mut args: Vec<String> = Vec::new();
 
         for token in tokens {
             if let Token::Ident(ident) = token {
                 args.push(ident.clone());
             } else if *token != Token::Symbol(Symbol::Comma) {
-                return Err(format!("unexpected token `{:?}`", token).into());
+                return Err(Error::UnexpectedToken(token.clone()));
             }
         }
 
         Ok(args)
     }
 
-    fn parse_macro(&mut self, tokens: &[Token]) -> Result<ConstExpr, Box<dyn std::error::Error>> {
+    fn parse_macro_header(&mut self, tokens: &[Token]) -> Result<(String, Vec<String>), Error> {
         if tokens.len() < 2 {
-            Err("invalid macro expression\nusage:\nmacro <IDENT> [ARGS]\n{\n    <body>\n}".into())
+            Err(Error::Usage { message: "invalid macro expression", usage: "macro <IDENT> [ARGS] { <body> }" })
         } else {
-            let ident = self.parse_ident(&tokens[0])?;
-            let args = self.parse_args(&tokens[1..])?;
-
-            let mut line = self.lexer.next_line()?;
-            let mut body: Vec<Inst> = Vec::new();
-
-            loop {
-                if let Some(line) = &line {
-                    if let Some(prefix) = line.get(0) {
-                        if *prefix == Token::Symbol(Symbol::CloseBrace) || *prefix == Token::Eof {
-                            return Ok(ConstExpr::Macro {
-                                ident,
-                                args,
-                                body,
-                            });
-                        }
+            Ok((self.parse_ident(&tokens[0])?, self.parse_args(&tokens[1..])?))
+        }
+    }
+
+    // a malformed header skips its body, so the body lines are not reported on their own
+    fn parse_macro(&mut self, tokens: &[Token]) -> Result<ConstExpr, Error> {
+        let (ident, args) = match self.parse_macro_header(tokens) {
+            Ok(header) => header,
+            Err(err) => {
+                self.lexer.skip_block(tokens.contains(&Token::Symbol(Symbol::OpenBrace)))?;
 
-                        if *prefix != Token::Symbol(Symbol::OpenBrace) {
-                            if let Some(inst) = self.parse_line(line.clone())? {
-                                body.push(inst);
-                            }
+                return Err(err);
+            },
+        };
+
+        let mut line = self.lexer.next_line()?;
+        // every instruction keeps where it was for diagnostics and debug info
+        let mut body: Vec<Spanned> = Vec::new();
+
+        loop {
+            if let Some(line) = &line {
+                if let Some((prefix, _)) = line.first() {
+                    if *prefix == Token::Symbol(Symbol::CloseBrace) || *prefix == Token::Eof {
+                        return Ok(ConstExpr::Macro {
+                            ident,
+                            args,
+                            body,
+                        });
+                    }
+
+                    if *prefix != Token::Symbol(Symbol::OpenBrace) {
+                        let span = self.lexer.span.clone();
+
+                        self.failed = None;
+
+                        match self.parse_line(Self::tokens(line)) {
+                            Ok(Some(inst)) => body.push(Self::spanned(line, span, inst)),
+                            Ok(None) => {},
+                            Err(err) => {
+                                let span = self.locate(line, span, &err);
+
+                                self.errors.push((span, err));
+                            },
                         }
                     }
                 }
-
-                line = self.lexer.next_line()?;
             }
+
+            line = match self.lexer.next_line() {
+                Ok(line) => line,
+                Err(err) => {
+                    self.errors.push((self.lexer.span.clone(), err));
+
+                    None
+                },
+            };
         }
     }
 
-    fn parse_call(&mut self, ident: String, tokens: &[Token]) -> Result<ConstExpr, Box<dyn std::error::Error>> {
+    fn parse_call(&mut self, ident: String, tokens: &[Token]) -> Result<ConstExpr, Error> {
         let mut args: Vec<Value> = Vec::new();
 
-        for token in &tokens[1..] {
-            if *token != Token::Symbol(Symbol::Comma) {
-                args.push(self.parse_expr(&[token.clone()])?);
+        for arg in tokens[1..].split(|token| *token == Token::Symbol(Symbol::Comma)) {
+            if !arg.is_empty() {
+                args.push(self.parse_expr(arg)?);
             }
         }
 
@@ -231,10 +913,16 @@ impl Parser {
         })
     }
 
-    fn parse_line(&mut self, mut tokens: Vec<Token>) -> Result<Option<Inst>, Box<dyn std::error::Error>> {
+    fn parse_line(&mut self, mut tokens: Vec<Token>) -> Result<Option<Inst>, Error> {
         if let Some(prefix) = tokens.clone().first() {
             return match prefix {
                 Token::Ident(ident) => {
+                    if let [_, Token::Keyword(keyword), rest @ ..] = tokens.as_slice() {
+                        if let Some(data) = self.parse_data(Some(ident.clone()), keyword, rest)? {
+                            return Ok(Some(data));
+                        }
+                    }
+
                     if let Ok(constexpr) = self.parse_const_expr(ident.clone(), &tokens) {
                         Ok(Some(Inst::ConstExpr(constexpr)))
                     } else if let Ok(constexpr) = self.parse_call(ident.clone(), &tokens) {
@@ -268,34 +956,176 @@ impl Parser {
                         Keyword::Mul => Ok(Some(Inst::Mul {
                             dest: self.parse_reg(&tokens)?,
                         })),
+                        Keyword::Lea => Ok(Some(Inst::Lea {
+                            dest: self.parse_expr(&SplitTokens::new(&tokens)?.lhs)?,
+                            src: self.parse_expr(&SplitTokens::new(&tokens)?.rhs)?,
+                        })),
                         Keyword::Cmp => Ok(Some(Inst::Cmp {
                             lhs: self.parse_expr(&SplitTokens::new(&tokens)?.lhs)?,
                             rhs: self.parse_expr(&SplitTokens::new(&tokens)?.rhs)?,
                         })),
+                        Keyword::Jmp if tokens.contains(&Token::Symbol(Symbol::Colon)) => Ok(Some(self.parse_far_jmp(&tokens)?)),
                         Keyword::Jmp => Ok(Some(Inst::Jmp { label: self.parse_jcc(&tokens)? })),
                         Keyword::Je => Ok(Some(Inst::Je { label: self.parse_jcc(&tokens)? })),
                         Keyword::Jg => Ok(Some(Inst::Jg { label: self.parse_jcc(&tokens)? })),
                         Keyword::Jb => Ok(Some(Inst::Jb { label: self.parse_jcc(&tokens)? })),
+                        Keyword::Call => Ok(Some(Inst::Call { label: self.parse_jcc(&tokens)? })),
+                        Keyword::Ret => Ok(Some(Inst::Ret)),
                         Keyword::Syscall => Ok(Some(Inst::Syscall)),
 
+                        Keyword::Vaddps => {
+                            let (end, rounding) = self.parse_rounding_operand(&tokens)?;
+                            let (dest, src1, src2) = self.parse_ternary(&tokens[..end])?;
+
+                            Ok(Some(Inst::Vaddps { dest, src1, src2, rounding }))
+                        },
+                        Keyword::Vpxor => {
+                            let (dest, src1, src2) = self.parse_ternary(&tokens)?;
+
+                            Ok(Some(Inst::Vpxor { dest, src1, src2 }))
+                        },
+                        Keyword::Vpshufb => {
+                            let (dest, src1, src2) = self.parse_ternary(&tokens)?;
+
+                            Ok(Some(Inst::Vpshufb { dest, src1, src2 }))
+                        },
+                        Keyword::Vfmadd231ps => {
+                            let (end, rounding) = self.parse_rounding_operand(&tokens)?;
+                            let (dest, src1, src2) = self.parse_ternary(&tokens[..end])?;
+
+                            Ok(Some(Inst::Vfmadd231ps { dest, src1, src2, rounding }))
+                        },
+                        Keyword::Vmovdqu => Ok(Some(Inst::Vmovdqu {
+                            lhs: self.parse_expr(&SplitTokens::new(&tokens)?.lhs)?,
+                            rhs: self.parse_expr(&SplitTokens::new(&tokens)?.rhs)?,
+                        })),
+                        Keyword::Vbroadcastss => Ok(Some(Inst::Vbroadcastss {
+                            dest: self.parse_expr(&SplitTokens::new(&tokens)?.lhs)?,
+                            src: self.parse_expr(&SplitTokens::new(&tokens)?.rhs)?,
+                        })),
+                        Keyword::Vzeroupper => Ok(Some(Inst::Vzeroupper)),
+
+                        Keyword::Vmaxps => {
+                            let (end, rounding) = self.parse_rounding_operand(&tokens)?;
+                            let (dest, src1, src2) = self.parse_ternary(&tokens[..end])?;
+
+                            Ok(Some(Inst::Vmaxps { dest, src1, src2, rounding }))
+                        },
+                        Keyword::Vpxord => {
+                            let (dest, src1, src2) = self.parse_ternary(&tokens)?;
+
+                            Ok(Some(Inst::Vpxord { dest, src1, src2 }))
+                        },
+                        Keyword::Vpxorq => {
+                            let (dest, src1, src2) = self.parse_ternary(&tokens)?;
+
+                            Ok(Some(Inst::Vpxorq { dest, src1, src2 }))
+                        },
+                        Keyword::Vpcmpeqd => {
+                            let (dest, src1, src2) = self.parse_ternary(&tokens)?;
+
+                            Ok(Some(Inst::Vpcmpeqd { dest, src1, src2 }))
+                        },
+                        Keyword::Vmovdqu32 => Ok(Some(Inst::Vmovdqu32 {
+                            lhs: self.parse_expr(&SplitTokens::new(&tokens)?.lhs)?,
+                            rhs: self.parse_expr(&SplitTokens::new(&tokens)?.rhs)?,
+                        })),
+                        Keyword::Vmovdqu64 => Ok(Some(Inst::Vmovdqu64 {
+                            lhs: self.parse_expr(&SplitTokens::new(&tokens)?.lhs)?,
+                            rhs: self.parse_expr(&SplitTokens::new(&tokens)?.rhs)?,
+                        })),
+                        Keyword::Kmovw => Ok(Some(Inst::Kmovw {
+                            lhs: self.parse_expr(&SplitTokens::new(&tokens)?.lhs)?,
+                            rhs: self.parse_expr(&SplitTokens::new(&tokens)?.rhs)?,
+                        })),
+
+                        Keyword::Fld => Ok(Some(Inst::Fld { src: self.parse_expr(&tokens)? })),
+                        Keyword::Fst => Ok(Some(Inst::Fst { dest: self.parse_expr(&tokens)? })),
+                        Keyword::Fstp => Ok(Some(Inst::Fstp { dest: self.parse_expr(&tokens)? })),
+                        Keyword::Fild => Ok(Some(Inst::Fild { src: self.parse_expr(&tokens)? })),
+                        Keyword::Fistp => Ok(Some(Inst::Fistp { dest: self.parse_expr(&tokens)? })),
+                        Keyword::Fadd => Ok(Some(Inst::Fadd { operands: self.parse_fpu_operands(&tokens)? })),
+                        Keyword::Faddp => Ok(Some(Inst::Faddp { operands: self.parse_fpu_operands(&tokens)? })),
+                        Keyword::Fsub => Ok(Some(Inst::Fsub { operands: self.parse_fpu_operands(&tokens)? })),
+                        Keyword::Fsubp => Ok(Some(Inst::Fsubp { operands: self.parse_fpu_operands(&tokens)? })),
+                        Keyword::Fmul => Ok(Some(Inst::Fmul { operands: self.parse_fpu_operands(&tokens)? })),
+                        Keyword::Fmulp => Ok(Some(Inst::Fmulp { operands: self.parse_fpu_operands(&tokens)? })),
+                        Keyword::Fdiv => Ok(Some(Inst::Fdiv { operands: self.parse_fpu_operands(&tokens)? })),
+                        Keyword::Fdivp => Ok(Some(Inst::Fdivp { operands: self.parse_fpu_operands(&tokens)? })),
+                        Keyword::Fxch => Ok(Some(Inst::Fxch { operands: self.parse_fpu_operands(&tokens)? })),
+                        Keyword::Fcomi => Ok(Some(Inst::Fcomi { operands: self.parse_fpu_operands(&tokens)? })),
+                        Keyword::Fsqrt => Ok(Some(Inst::Fsqrt)),
+                        Keyword::Fsin => Ok(Some(Inst::Fsin)),
+                        Keyword::Fcos => Ok(Some(Inst::Fcos)),
+                        Keyword::Finit => Ok(Some(Inst::Finit)),
+                        Keyword::Fldcw => Ok(Some(Inst::Fldcw { src: self.parse_expr(&tokens)? })),
+                        Keyword::Fnstcw => Ok(Some(Inst::Fnstcw { dest: self.parse_expr(&tokens)? })),
+
+                        Keyword::Lgdt => Ok(Some(Inst::Lgdt { src: self.parse_expr(&tokens)? })),
+                        Keyword::Lidt => Ok(Some(Inst::Lidt { src: self.parse_expr(&tokens)? })),
+                        Keyword::Ltr => Ok(Some(Inst::Ltr { src: self.parse_expr(&tokens)? })),
+                        Keyword::Invlpg => Ok(Some(Inst::Invlpg { src: self.parse_expr(&tokens)? })),
+                        Keyword::Wrmsr => Ok(Some(Inst::Wrmsr)),
+                        Keyword::Rdmsr => Ok(Some(Inst::Rdmsr)),
+                        Keyword::Iretq => Ok(Some(Inst::Iretq)),
+                        Keyword::Swapgs => Ok(Some(Inst::Swapgs)),
+
+                        Keyword::Use16 => Ok(Some(Inst::Use { bits: 16 })),
+                        Keyword::Use32 => Ok(Some(Inst::Use { bits: 32 })),
+                        Keyword::Use64 => Ok(Some(Inst::Use { bits: 64 })),
+
+                        Keyword::Format => Ok(Some(Inst::Format { format: self.parse_format(&tokens)? })),
+                        Keyword::Org => Ok(Some(Inst::Org { origin: self.parse_expr(&tokens)? })),
+                        Keyword::Entry => Ok(Some(Inst::Entry { label: self.parse_symbol(&tokens)? })),
+
+                        Keyword::Public => Ok(Some(Inst::Public { ident: self.parse_symbol(&tokens)? })),
+                        Keyword::Extrn => Ok(Some(Inst::Extrn { ident: self.parse_symbol(&tokens)? })),
+                        Keyword::Needed => Ok(Some(Inst::Needed { library: self.parse_library(&tokens)? })),
+                        Keyword::Import => Ok(Some(self.parse_import(&tokens)?)),
+                        Keyword::Align => Ok(Some(Inst::Align { value: self.parse_expr(&tokens)? })),
+                        Keyword::Size => Ok(Some(Inst::Size {
+                            ident: self.parse_symbol(&SplitTokens::new(&tokens)?.lhs)?,
+                            value: self.parse_expr(&SplitTokens::new(&tokens)?.rhs)?,
+                        })),
+
+                        Keyword::Section => Ok(Some(self.parse_section(&tokens)?)),
+                        Keyword::Segment => Ok(Some(Inst::Segment { permissions: self.parse_permissions(&tokens)? })),
+
+                        Keyword::Db | Keyword::Dw | Keyword::Dd | Keyword::Dq | Keyword::Rb | Keyword::Rw | Keyword::Rd | Keyword::Rq => Ok(self.parse_data(None, keyword, &tokens)?),
+
                         Keyword::Macro => Ok(Some(Inst::ConstExpr(self.parse_macro(&tokens)?))),
-                        _ => Err(format!("unexpected token `{:?}`", keyword).into()),
+                        _ => Err(Error::UnexpectedToken(Token::Keyword(keyword.clone()))),
                     }
                 },
                 Token::Eof => Ok(Some(Inst::Eof)),
-                _ => Err(format!("unexpected token `{:?}`", prefix).into()),
+                _ => Err(Error::UnexpectedToken(prefix.clone())),
             };
         }
 
         Ok(None)
     }
 
-    pub fn next_inst(&mut self) -> Result<Option<Inst>, Box<dyn std::error::Error>> {
-        if let Some(tokens) = self.lexer.next_line()? {
-            return self.parse_line(tokens);
-        }
+    fn tokens(line: &[(Token, Span)]) -> Vec<Token> {
+        line.iter().map(|(token, _)| token.clone()).collect()
+    }
 
-        Ok(None)
+    // instructions span all tokens on their line, macros only the line they start on, errors come with where they are
+    pub fn next_inst(&mut self) -> Result<Option<Spanned>, (Span, Error)> {
+        self.errors.clear();
+        self.failed = None;
+
+        match self.lexer.next_line() {
+            Ok(Some(line)) => {
+                let span = self.lexer.span.clone();
+
+                match self.parse_line(Self::tokens(&line)) {
+                    Ok(inst) => Ok(inst.map(|inst| Self::spanned(&line, span, inst))),
+                    Err(err) => Err((self.locate(&line, span, &err), err)),
+                }
+            },
+            Ok(None) => Ok(None),
+            Err(err) => Err((self.lexer.span.clone(), err)),
+        }
     }
 }
 
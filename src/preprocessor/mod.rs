@@ -1,17 +1,42 @@
-use crate::parser::lexer::Register;
+use crate::parser::Permissions;
 use crate::parser::ConstExpr;
+use crate::parser::Format;
 use crate::parser::Parser;
 use crate::parser::Value;
 use crate::parser::Inst;
+use crate::parser::Spanned;
+use crate::parser::lexer::Span;
+use crate::log::{Diagnostics, Lint};
+use crate::error::Error;
 
-use faerie::Decl;
+use faerie::{Decl, Scope};
 
 use std::collections::HashMap;
 
 #[derive(Clone)]
 pub struct Macro {
     pub args: Vec<String>,
-    pub body: Vec<Inst>,
+    pub body: Vec<Spanned>,
+}
+
+// every section keeps its own location counter, `Preprocessor::offset` is the counter of the current one
+#[derive(Clone)]
+pub struct Section {
+    pub name: String,
+    pub permissions: Permissions,
+    pub start: usize,
+    pub offset: usize,
+    pub definition: String,
+    pub align: usize,
+}
+
+// a label found while scanning, `start` marks the first label of a section
+struct Scanned {
+    ident: String,
+    section: String,
+    permissions: Permissions,
+    start: bool,
+    align: usize,
 }
 
 pub struct Preprocessor {
@@ -20,6 +45,21 @@ pub struct Preprocessor {
     pub labels: Vec<(String, Decl)>,
     pub offsets: HashMap<String, usize>,
     pub offset: usize,
+    pub format: Format,
+    pub sections: Vec<Section>,
+    pub section: usize,
+    pub externs: Vec<String>,
+    pub parents: HashMap<String, String>,
+    pub anonymous: Vec<String>,
+    pub sizes: HashMap<String, Value>,
+    pub entry: Option<String>,
+    pub origin: Option<usize>,
+    pub publics: Vec<String>,
+    pub needed: Vec<String>,
+    pub imports: Vec<(String, String)>,
+    pub spans: HashMap<String, Span>,
+    span: Span,
+    directives: Vec<(&'static str, String, Span)>,
 }
 
 impl Preprocessor {
@@ -29,88 +69,377 @@ impl Preprocessor {
             consts: HashMap::new(),
             labels: Vec::new(),
             offsets: HashMap::new(),
-            offset: 0x401000,
+            offset: 0,
+            format: Format::Elf64Executable,
+            sections: Vec::new(),
+            section: 0,
+            externs: Vec::new(),
+            parents: HashMap::new(),
+            anonymous: Vec::new(),
+            sizes: HashMap::new(),
+            entry: None,
+            origin: None,
+            publics: Vec::new(),
+            needed: Vec::new(),
+            imports: Vec::new(),
+            spans: HashMap::new(),
+            span: Span::default(),
+            directives: Vec::new(),
+        }
+    }
+
+    // sections in object files start at zero since the linker decides where they go
+    pub fn base(&self) -> usize {
+        match self.format {
+            Format::Binary => self.origin.unwrap_or(0),
+            Format::Elf64 | Format::Ms64Coff | Format::MachO64 => 0,
+            Format::Elf64Executable | Format::Pe64 | Format::Pe64Gui => self.origin.unwrap_or(0x401000),
+            // the first page of position independent images holds their headers
+            Format::Elf64Pie | Format::Elf64Shared => self.origin.unwrap_or(0x1000),
+        }
+    }
+
+    pub fn is_label(&self, ident: &str) -> bool {
+        self.labels.iter().any(|(label, _)| label == ident) || self.parents.contains_key(ident)
+    }
+
+    // errors about a directive point at the first one of its kind, or the one naming `ident`, and at the whole file without one
+    fn directive(&mut self, kind: &str, ident: Option<&str>) {
+        self.span = match self.directives.iter().find(|(directive, name, _)| *directive == kind && ident.is_none_or(|ident| name == ident)) {
+            Some((_, _, span)) => span.clone(),
+            None => Span { file: self.span.file.clone(), ..Span::default() },
+        };
+    }
+
+    // code before the first label of a section is defined under the name of the section in object files
+    fn section(&mut self, name: String, permissions: Permissions, start: usize) -> Section {
+        let definition = if self.anonymous.contains(&name) {
+            self.offsets.insert(name.clone(), start);
+
+            name.clone()
+        } else {
+            String::new()
+        };
+
+        // code in objects starts on 16 bytes like other assemblers place it, `align` can only raise that
+        Section {
+            name,
+            permissions,
+            start,
+            offset: start,
+            definition,
+            align: if permissions.executable { 16 } else { 1 },
         }
     }
 
-    pub fn preprocess(&mut self, parser: &mut Parser) -> Result<(), Box<dyn std::error::Error>> {
+    // code before the first `section` or `segment` directive goes into an implicit executable section
+    pub fn reset(&mut self) {
+        let permissions = Permissions {
+            writeable: false,
+            executable: true,
+        };
+
+        self.offset = self.base();
+        self.section = 0;
+        self.sections = vec![self.section(String::from(".text"), permissions, self.offset)];
+    }
+
+    // `section` resumes a section with the same name, `segment` always starts a new one on the next page
+    pub fn enter(&mut self, name: Option<String>, permissions: Permissions) {
+        self.sections[self.section].offset = self.offset;
+
+        if let Some(index) = name.as_ref().and_then(|name| self.sections.iter().position(|section| section.name == *name)) {
+            self.section = index;
+        } else {
+            let start = match self.format {
+                format if format.is_image() => self.offset.div_ceil(0x1000) * 0x1000,
+                _ => self.base(),
+            };
+
+            let section = self.section(name.unwrap_or_default(), permissions, start);
+
+            self.section = self.sections.len();
+            self.sections.push(section);
+        }
+
+        self.offset = self.sections[self.section].offset;
+    }
+
+    // `align` needs a power of two that is known while scanning
+    pub fn alignment(&self, value: &Value) -> Result<usize, Error> {
+        match value {
+            Value::Integer(align) if *align > 0 && (*align as u64).is_power_of_two() => Ok(*align as usize),
+            Value::Const(ident) => self.alignment(self.consts.get(ident).ok_or(Error::NoSuchConstant(ident.clone()))?),
+            _ => Err(Error::Alignment),
+        }
+    }
+
+    fn decl(permissions: Permissions, scope: Scope, align: usize) -> Decl {
+        if permissions.executable {
+            Decl::function().with_scope(scope).with_align(Some(align as u64)).into()
+        } else {
+            Decl::data().with_scope(scope).with_writable(permissions.writeable).with_align(Some(align as u64)).into()
+        }
+    }
+
+    // in object files only public labels and the start of every section become symbols in code, other labels are offsets into them
+    fn define(&mut self, scanned: Vec<Scanned>, diagnostics: &mut Diagnostics) -> Result<(), Error> {
+        let mut definitions: HashMap<String, String> = HashMap::new();
+        let mut aligns: HashMap<String, usize> = HashMap::new();
+
+        for public in self.publics.clone().iter().filter(|public| !scanned.iter().any(|label| label.ident == **public)) {
+            self.directive("public", Some(public));
+
+            diagnostics.report(self.span.clone(), Error::UndefinedPublic(public.clone()), Vec::new())?;
+        }
+
+        // external symbols that are also labels are only kept as labels so the rest of the file can still be checked
+        for extrn in self.externs.clone().iter().filter(|extrn| scanned.iter().any(|label| label.ident == **extrn)) {
+            diagnostics.report(self.spans[extrn].clone(), Error::ExternDefined(extrn.clone()), Vec::new())?;
+
+            self.externs.retain(|other| other != extrn);
+        }
+
+        let publics = self.publics.clone();
+
+        for label in &scanned {
+            // nothing falls through data, so every data label is a symbol of its own
+            if !self.format.is_faerie() || label.start || publics.contains(&label.ident) || !label.permissions.executable {
+                definitions.insert(label.section.clone(), label.ident.clone());
+            } else if let Some(definition) = definitions.get(&label.section) {
+                self.parents.insert(label.ident.clone(), definition.clone());
+            }
+
+            let align = aligns.entry(definitions[&label.section].clone()).or_insert(1);
+
+            *align = label.align.max(*align);
+        }
+
+        for label in scanned.into_iter().filter(|label| !self.parents.contains_key(&label.ident)) {
+            let scope = if !self.format.is_faerie() || publics.contains(&label.ident) { Scope::Global } else { Scope::Local };
+
+            self.labels.push((label.ident.clone(), Self::decl(label.permissions, scope, aligns[&label.ident])));
+        }
+
+        for extrn in &self.externs {
+            self.labels.push((extrn.clone(), Decl::function_import().into()));
+        }
+
+        for ident in self.sizes.keys().filter(|ident| !self.labels.iter().any(|(label, _)| label == *ident) || self.externs.contains(ident)).cloned().collect::<Vec<String>>() {
+            self.directive("size", Some(&ident));
+
+            diagnostics.report(self.span.clone(), Error::NotASymbol(ident.clone()), Vec::new())?;
+        }
+
+        Ok(())
+    }
+
+    // mistakes are reported to `diagnostics` and scanning goes on, `Err` only stops it at the error limit
+    pub fn preprocess(&mut self, parser: &mut Parser, diagnostics: &mut Diagnostics) -> Result<(), Error> {
         let mut inst = parser.next_inst();
+        let mut format = None;
+        let mut permissions = Permissions {
+            writeable: false,
+            executable: true,
+        };
+
+        let mut scanned: Vec<Scanned> = Vec::new();
+        let mut started: Vec<String> = Vec::new();
+        let mut section = String::from(".text");
+        let mut align = 1;
 
         loop {
-            if let Ok(inst) = inst {
-                if let Some(inst) = inst {
-                    match inst {
-                        Inst::ConstExpr(constexpr) => {
-                            match constexpr.clone() {
-                                ConstExpr::Constant { ident, value } => {
-                                    self.consts.insert(ident, value);
-                                },
-                                ConstExpr::Macro { ident, args, body } => {
-                                    self.macros.insert(ident, Macro {
-                                        args,
-                                        body,
-                                    });
-                                },
-                                ConstExpr::Call { .. } => {},
-                            }
-                        },
-                        Inst::Label { ident } => {
-                            self.offsets.insert(ident.clone(), self.offset);
-                            self.labels.push((ident, Decl::function().global().with_align(Some(1)).into()));
-                        },
-                        Inst::Push { value } => {
-                            if let Value::Integer(_) = value {
-                                self.offset += 5;
-                            } else if let Value::Register(_) = value {
-                                self.offset += 2;
-                            }
-                        },
-                        Inst::Pop { .. } => self.offset += 1,
-                        Inst::Mov { rhs, .. } => {
-                            if let Value::Integer(_) = rhs {
-                                self.offset += 5;
-                            } else if let Value::Register(_) = rhs {
-                                self.offset += 2;
-                            }
-                        },
-                        Inst::Add { rhs, lhs } | Inst::Sub { rhs, lhs } => {
-                            if let Value::Register(rd) = lhs {
-                                if let Value::Integer(_) = rhs {
-                                    if rd == Register::Eax {
-                                        self.offset += 5;
-                                    } else {
-                                        self.offset += 6;
-                                    }
-                                } else if let Value::Register(_) = rhs {
-                                    self.offset += 2;
-                                }
-                            }
-                        },
-                        Inst::Mul { .. } => self.offset += 2,
-                        Inst::Cmp { lhs, rhs } => {
-                            if let Value::Register(_) = lhs {
-                                if let Value::Integer(_) = rhs {
-                                    self.offset += 6;
-                                } else if let Value::Register(_) = rhs {
-                                    self.offset += 2;
+            for (span, err) in parser.errors.drain(..) {
+                diagnostics.report(span, err, Vec::new())?;
+            }
+
+            if let Err((span, err)) = inst {
+                diagnostics.report(span, err, Vec::new())?;
+            } else if let Ok(Some(Spanned { span, operands, inst })) = inst {
+                self.span = span.clone();
+
+                match inst {
+                    Inst::ConstExpr(constexpr) if !matches!(constexpr, ConstExpr::Call { .. }) => {
+                        match constexpr.clone() {
+                            ConstExpr::Constant { ident, value } => {
+                                // the last definition of a constant is the one used everywhere
+                                if let Some(previous) = self.spans.get(&ident).filter(|_| self.consts.contains_key(&ident)) {
+                                    diagnostics.warn(Lint::RedefinedConstant, span.clone(), format!("constant `{}` is redefined, only the last value is used", ident), vec![(previous.clone(), String::from("previously defined here"))]);
                                 }
-                            }
-                        },
-                        Inst::Jmp { .. } => self.offset += 5,
-                        Inst::Je { .. } | Inst::Jg { .. } | Inst::Jb { .. } => self.offset += 6,
-                        Inst::Syscall => self.offset += 2,
-
-                        Inst::Eof => {
-                            break
-                        },
-                    }
+
+                                self.spans.insert(ident.clone(), span.clone());
+                                self.consts.insert(ident, value);
+                            },
+                            ConstExpr::Macro { ident, args, body } => {
+                                self.spans.insert(ident.clone(), span.clone());
+                                self.macros.insert(ident, Macro {
+                                    args,
+                                    body,
+                                });
+                            },
+                            ConstExpr::Call { .. } => {},
+                        }
+                    },
+                    Inst::Label { ident } | Inst::Data { label: Some(ident), .. } | Inst::Reserve { label: Some(ident), .. } => {
+                        self.spans.insert(ident.clone(), span.clone());
+
+                        scanned.push(Scanned {
+                            ident,
+                            section: section.clone(),
+                            permissions,
+                            start: !started.contains(&section),
+                            align: std::mem::replace(&mut align, 1),
+                        });
+
+                        started.push(section.clone());
+                    },
+                    Inst::Public { ident } => {
+                        self.directives.push(("public", ident.clone(), span));
+                        self.publics.push(ident);
+                    },
+                    Inst::Needed { library } => {
+                        self.directives.push(("needed", library.clone(), span));
+                        self.needed.push(library);
+                    },
+                    // imported symbols are external symbols that know their library
+                    Inst::Import { library, idents } => {
+                        for ident in idents {
+                            self.directives.push(("import", ident.clone(), span.clone()));
+                            self.spans.insert(ident.clone(), span.clone());
+                            self.imports.push((ident.clone(), library.clone()));
+                            self.externs.push(ident);
+                        }
+                    },
+                    Inst::Extrn { ident } => {
+                        self.directives.push(("extrn", ident.clone(), span.clone()));
+                        self.spans.insert(ident.clone(), span.clone());
+                        self.externs.push(ident);
+                    },
+                    Inst::Align { value } => match self.alignment(&value) {
+                        Ok(alignment) => align = alignment.max(align),
+                        Err(err) => diagnostics.report(operands.first().cloned().unwrap_or(span), err, Vec::new())?,
+                    },
+                    Inst::Size { ident, value } => {
+                        self.directives.push(("size", ident.clone(), span));
+                        self.sizes.insert(ident, value);
+                    },
+                    Inst::Entry { label } => {
+                        self.directives.push(("entry", label.clone(), span.clone()));
+
+                        if self.entry.replace(label).is_some() {
+                            diagnostics.report(span, Error::Duplicate("entry"), Vec::new())?;
+                        }
+                    },
+                    Inst::Section { name, permissions: directive } => {
+                        if !self.format.is_object() {
+                            diagnostics.report(span, Error::WrongFormat { directive: "`section`", formats: "`format ELF64`, `MS64 COFF` and `MachO64`", help: Some("use `segment` for executables") }, Vec::new())?;
+                        } else {
+                            permissions = directive;
+                            section = name;
+                        }
+                    },
+                    Inst::Segment { permissions: segment } => {
+                        if !self.format.is_image() {
+                            diagnostics.report(span, Error::WrongFormat { directive: "`segment`", formats: "`format ELF64 executable`", help: Some("use `section` for objects") }, Vec::new())?;
+                        } else {
+                            permissions = segment;
+                        }
+                    },
+                    Inst::Format { format: directive } => {
+                        self.directives.push(("format", String::new(), span.clone()));
+
+                        if format.replace(directive).is_some() {
+                            diagnostics.report(span, Error::Duplicate("format"), Vec::new())?;
+                        } else {
+                            self.format = directive;
+                        }
+                    },
+                    Inst::Org { .. } => self.directives.push(("org", String::new(), span.clone())),
+                    Inst::Eof => {
+                        break
+                    },
+                    Inst::Use { .. } => {},
+                    _ => {
+                        if !started.contains(&section) && self.format.is_faerie() {
+                            scanned.push(Scanned {
+                                ident: section.clone(),
+                                section: section.clone(),
+                                permissions,
+                                start: true,
+                                align: 1,
+                            });
+
+                            started.push(section.clone());
+                            self.anonymous.push(section.clone());
+                        }
+
+                        // alignment in the middle of a symbol applies to the symbol it is in
+                        if let Some(label) = scanned.iter_mut().rev().find(|label| label.section == section) {
+                            label.align = std::mem::replace(&mut align, 1).max(label.align);
+                        }
+                    },
                 }
             }
 
             inst = parser.next_inst();
         }
 
-        self.offset = 0x401000;
+        if !self.imports.is_empty() && !self.format.is_pe() {
+            self.directive("import", None);
+
+            diagnostics.report(self.span.clone(), Error::WrongFormat { directive: "`import`", formats: "`format PE64`", help: Some("use `extrn` for other formats") }, Vec::new())?;
+        }
+
+        if (!self.publics.is_empty() || self.externs.len() > self.imports.len()) && !self.format.is_object() && !self.format.is_dynamic() {
+            self.directive(if self.publics.is_empty() { "extrn" } else { "public" }, None);
+
+            diagnostics.report(self.span.clone(), Error::WrongFormat { directive: "`public` and `extrn`", formats: "`format ELF64`, `ELF64 PIE`, `ELF64 shared`, `MS64 COFF` and `MachO64`", help: Some("PE64 images use `import`") }, Vec::new())?;
+        }
+
+        if !self.sizes.is_empty() && self.format != Format::Elf64 && !self.format.is_dynamic() {
+            self.directive("size", None);
+
+            diagnostics.report(self.span.clone(), Error::WrongFormat { directive: "`size`", formats: "`format ELF64`, `ELF64 PIE` and `ELF64 shared`", help: None }, Vec::new())?;
+        }
+
+        if !self.needed.is_empty() && !self.format.is_dynamic() {
+            self.directive("needed", None);
+
+            diagnostics.report(self.span.clone(), Error::WrongFormat { directive: "`needed`", formats: "`format ELF64 PIE` and `ELF64 shared`", help: None }, Vec::new())?;
+        }
+
+        // images and objects are not laid out from where their code starts, so they cant move it
+        if self.format != Format::Binary {
+            for (_, _, span) in self.directives.clone().into_iter().filter(|(directive, _, _)| *directive == "org") {
+                diagnostics.report(span, Error::Org, Vec::new())?;
+            }
+        }
+
+        self.directive("format", None);
+
+        if self.format.is_image() && !self.base().is_multiple_of(0x1000) {
+            diagnostics.report(self.span.clone(), Error::UnalignedBase(self.base()), Vec::new())?;
+        } else if self.format.is_dynamic() && self.base() == 0 {
+            diagnostics.report(self.span.clone(), Error::ZeroBase, Vec::new())?;
+        } else if self.format.is_pe() && (self.base() < 0x11000 || !(self.base() - 0x1000).is_multiple_of(0x10000)) {
+            diagnostics.report(self.span.clone(), Error::PeBase(self.base()), Vec::new())?;
+        }
+
+        // executables start at `_start` without an `entry` directive, `ld` gets the entry of elf objects it links
+        let entry = self.entry.clone().unwrap_or(String::from("_start"));
+
+        if self.entry.is_some() {
+            self.directive("entry", None);
+        }
+
+        if self.entry.is_some() && !self.format.is_image() && self.format != Format::Elf64 {
+            diagnostics.report(self.span.clone(), Error::WrongFormat { directive: "`entry`", formats: "executables, shared libraries and `format ELF64`", help: None }, Vec::new())?;
+        } else if (self.entry.is_some() || (self.format.is_image() && self.format != Format::Elf64Shared)) && !scanned.iter().any(|label| label.ident == entry) {
+            diagnostics.report(self.span.clone(), Error::UndefinedEntry(entry), Vec::new())?;
+        }
+
+        self.define(scanned, diagnostics)?;
+        self.reset();
 
         Ok(())
     }